@@ -0,0 +1,68 @@
+use serde::Serialize;
+
+use crate::snapshot::Snapshot;
+
+/// A content branch. Branches only exist as the `sprt_branch` tag on a project's snapshots, so this is built from them.
+#[derive(Debug, Serialize, Clone)]
+pub struct Branch {
+    pub name: String,
+    /// The latest snapshot on the branch
+    pub head: Snapshot,
+    pub count: usize,
+    /// Size of the database and uploads in the head snapshot
+    pub size: u64,
+    /// Data added to the repo by snapshots on this branch
+    pub data_added: u64,
+    /// The user who took the head snapshot
+    pub author: String,
+    /// The machine the head snapshot was taken on. Older snapshots don't record it.
+    pub host: Option<String>,
+}
+
+/// Group snapshots into branches, with the most recently updated branch first
+pub fn group_branches(mut snapshots: Vec<Snapshot>) -> Vec<Branch> {
+    snapshots.sort_by(|a, b| b.snapshot.time.cmp(&a.snapshot.time));
+
+    let mut branches: Vec<Branch> = vec![];
+
+    for snapshot in snapshots {
+        let name = snapshot.get_branch().unwrap_or("???".to_string());
+        let data_added = snapshot.get_stats().map_or(0, |stats| stats.data_added);
+
+        match branches.iter_mut().find(|branch| branch.name == name) {
+            Some(branch) => {
+                branch.count += 1;
+                branch.data_added += data_added;
+            }
+            None => branches.push(Branch {
+                name,
+                count: 1,
+                size: snapshot.get_total_bytes(),
+                data_added,
+                author: snapshot.snapshot.username.clone(),
+                host: snapshot.get_host(),
+                head: snapshot,
+            }),
+        }
+    }
+
+    branches
+}
+
+/// Check a branch name is usable. Branches are stored in a comma separated tag list, so they're kept to letters,
+/// digits, dots, dashes, underscores and slashes.
+pub fn validate_branch(name: &str) -> anyhow::Result<()> {
+    let valid = name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '/'));
+
+    if !valid {
+        return Err(anyhow::anyhow!(
+            "Invalid branch name `{}`. Branch names may contain letters, digits, dots, dashes, underscores and slashes, and must start with a letter or digit.",
+            name
+        ));
+    }
+
+    Ok(())
+}
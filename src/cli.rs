@@ -3,8 +3,9 @@ use colored::*;
 
 use std::time::SystemTime;
 
-use crate::{cli::clap::Options, engine::Engine};
+use crate::{cli::clap::Options, engine::Engine, facts::FactError};
 
+mod branch;
 pub mod clap;
 mod commands;
 mod repo;
@@ -49,6 +50,17 @@ pub fn entrypoint(engine: &Engine) {
                 "ERROR".bold().white().on_red(),
                 e.to_string().bold().dimmed()
             );
+
+            if let Some(stderr) = e
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<FactError>())
+                .and_then(|fact_error| fact_error.stderr())
+            {
+                for line in stderr.lines() {
+                    eprintln!("{: ^9} {}", "", line.red().dimmed());
+                }
+            }
+
             eprintln!(
                 "{: ^9} {}",
                 "",
@@ -0,0 +1,51 @@
+use colored::*;
+use indicatif::HumanBytes;
+use std::io::Write;
+use tabwriter::TabWriter;
+
+use crate::{branch::Branch, project::Project};
+
+/// Generates a table showing each content branch, and its head snapshot
+pub fn branch_table(branches: &[Branch], project: &Project) -> anyhow::Result<String> {
+    let mut tw = TabWriter::new(vec![]).ansi(true);
+
+    writeln!(
+        &mut tw,
+        "\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        "Branch".dimmed().bold(),
+        "Head".dimmed().bold(),
+        "Snapshots".dimmed().bold(),
+        "Size".dimmed().bold(),
+        "+Data".dimmed().italic().cyan(),
+        "Author / Host".dimmed().bold(),
+        "Date / Time".dimmed().bold()
+    )?;
+
+    for branch in branches {
+        writeln!(
+            &mut tw,
+            "{:^8}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            if branch.name == project.config.branch {
+                "current ▶".green().dimmed().bold()
+            } else {
+                "".normal()
+            },
+            branch.name.bold(),
+            branch.head.id.to_hex().to_string().dimmed(),
+            branch.count,
+            HumanBytes(branch.size),
+            format!("(+{})", HumanBytes(branch.data_added))
+                .dimmed()
+                .italic(),
+            match &branch.host {
+                Some(host) => format!("{}@{}", branch.author, host),
+                None => branch.author.to_owned(),
+            },
+            branch.head.snapshot.time
+        )?;
+    }
+
+    tw.flush().unwrap();
+
+    Ok(String::from_utf8(tw.into_inner().unwrap()).unwrap())
+}
@@ -35,6 +35,18 @@ pub enum SubCommand {
     Stash(StashArgs),
     /// List available remote snapshots
     Ls,
+    /// Tag a snapshot with a name, which can be used in place of its ID
+    Tag(TagArgs),
+    /// Pin a snapshot, so it can't be forgotten or dropped without an explicit override
+    Pin(PinArgs),
+    /// Unpin a pinned snapshot
+    Unpin(PinArgs),
+    /// Content branch commands
+    Branch(BranchArgs),
+    /// Show what changed between two snapshots. Defaults to the active snapshot and the head of its branch
+    Diff(DiffArgs),
+    /// Forget old remote snapshots using a retention policy, then prune unused data
+    Forget(ForgetArgs),
     /// Update Sprout to latest release
     Update,
 }
@@ -43,6 +55,44 @@ pub enum SubCommand {
 pub struct SnapArgs {
     /// Create a snapshot on a specific content branch
     pub branch: Option<String>,
+
+    /// Describe the snapshot, eg. "after the content freeze"
+    #[arg(short, long)]
+    pub message: Option<String>,
+
+    /// Tag the snapshot with a name, which can be used in place of its ID. Unique per project and branch
+    #[arg(short, long)]
+    pub tag: Option<String>,
+
+    /// Move the tag if another snapshot on this branch already has it
+    #[arg(long, requires = "tag")]
+    pub force: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct TagArgs {
+    /// The snapshot ID (or existing tag) to tag
+    #[arg(index = 1)]
+    pub snapshot_id: String,
+
+    /// The tag name. Letters, digits, dots, dashes and underscores
+    #[arg(index = 2)]
+    pub name: String,
+
+    /// Move the tag if another snapshot on this branch already has it
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct PinArgs {
+    /// The snapshot ID or tag
+    #[arg(index = 1)]
+    pub snapshot_id: String,
+
+    /// Pin a local stash rather than a remote snapshot
+    #[arg(long)]
+    pub stash: bool,
 }
 
 #[derive(Args, Debug)]
@@ -51,9 +101,83 @@ pub struct SeedArgs {
     #[arg(short, long)]
     pub no_stash: bool,
 
-    /// Restore a particular snapshot ID
+    /// Restore a particular snapshot ID or tag
     #[arg(index = 1)]
     pub snapshot_id: Option<String>,
+
+    /// Only restore the database, leaving uploads untouched
+    #[arg(long, conflicts_with = "uploads_only")]
+    pub db_only: bool,
+
+    /// Only restore uploads, leaving the database untouched
+    #[arg(long)]
+    pub uploads_only: bool,
+
+    /// Only restore uploads matching this glob, relative to the uploads directory. Other local files are kept. May be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip uploads matching this glob, relative to the uploads directory. Matching local files are kept. May be repeated
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Only restore these database tables (comma separated, globs allowed). Overrides `tables.include` in sprout.yaml
+    #[arg(long, value_delimiter = ',', value_name = "TABLES")]
+    pub tables: Vec<String>,
+
+    /// Leave these database tables untouched (comma separated, globs allowed). Overrides `tables.exclude` in sprout.yaml
+    #[arg(long, value_delimiter = ',', value_name = "TABLES")]
+    pub exclude_tables: Vec<String>,
+
+    /// Show what would change, without stashing or touching the database or uploads
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// If the seed fails, leave the project as it is rather than rolling back to the stash. Useful for debugging
+    #[arg(long, conflicts_with = "no_stash")]
+    pub no_rollback: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// The snapshot ID or tag to compare from. Default: the active snapshot
+    #[arg(index = 1)]
+    pub from: Option<String>,
+
+    /// The snapshot ID or tag to compare to. Default: the latest snapshot on the current branch
+    #[arg(index = 2)]
+    pub to: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ForgetArgs {
+    /// Keep the N most recent snapshots
+    #[arg(long, value_name = "N")]
+    pub keep_last: Option<usize>,
+
+    /// Keep the most recent snapshot for each of the last N days
+    #[arg(long, value_name = "N")]
+    pub keep_daily: Option<usize>,
+
+    /// Keep the most recent snapshot for each of the last N weeks
+    #[arg(long, value_name = "N")]
+    pub keep_weekly: Option<usize>,
+
+    /// Keep the N most recent snapshots on every content branch
+    #[arg(long, value_name = "N")]
+    pub keep_per_branch: Option<usize>,
+
+    /// Only consider snapshots on this content branch
+    #[arg(short, long)]
+    pub branch: Option<String>,
+
+    /// Show which snapshots would be forgotten, without removing anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Forget pinned snapshots too, if the policy doesn't keep them
+    #[arg(long)]
+    pub include_pinned: bool,
 }
 
 #[derive(Args, Debug)]
@@ -68,6 +192,78 @@ pub struct StashArgs {
     pub subcommand: Option<StashCommand>,
 }
 
+#[derive(Args, Debug)]
+pub struct BranchArgs {
+    #[clap(subcommand)]
+    pub subcommand: BranchCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum BranchCommand {
+    /// List this project's content branches
+    List,
+
+    /// Switch to another content branch, and seed its latest snapshot
+    Switch(BranchSwitchArgs),
+
+    /// Rename a content branch
+    Rename(BranchRenameArgs),
+
+    /// Forget every snapshot on a content branch, then prune unused data
+    Delete(BranchDeleteArgs),
+
+    /// Start a new content branch from an existing snapshot, without uploading anything
+    Fork(BranchForkArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct BranchSwitchArgs {
+    /// The branch to switch to
+    #[arg(index = 1)]
+    pub name: String,
+
+    /// Do not stash current database and uploads before seeding
+    #[arg(short, long)]
+    pub no_stash: bool,
+
+    /// If the seed fails, leave the project as it is rather than rolling back to the stash
+    #[arg(long, conflicts_with = "no_stash")]
+    pub no_rollback: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct BranchRenameArgs {
+    /// The branch to rename
+    #[arg(index = 1)]
+    pub from: String,
+
+    /// Its new name
+    #[arg(index = 2)]
+    pub to: String,
+}
+
+#[derive(Args, Debug)]
+pub struct BranchDeleteArgs {
+    /// The branch to delete
+    #[arg(index = 1)]
+    pub name: String,
+
+    /// Forget pinned snapshots on the branch too
+    #[arg(long)]
+    pub include_pinned: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct BranchForkArgs {
+    /// The branch (its latest snapshot), snapshot ID or tag to start from
+    #[arg(index = 1)]
+    pub from: String,
+
+    /// The new branch
+    #[arg(index = 2)]
+    pub to: String,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum RepoCommand {
     /// Create a new repository definition
@@ -90,6 +286,9 @@ pub enum StashCommand {
 
     /// Drop a particular stash by snapshot ID
     Drop(StashDropArgs),
+
+    /// Apply the stash retention policy from sprout-config.yaml and reclaim unused space
+    Prune,
 }
 
 #[derive(Args, Debug)]
@@ -122,6 +321,10 @@ pub struct UnStashArgs {
     /// Restore a particular stash snapshot by ID. This will not check project or branch constraints - use with caution.
     #[arg(index = 1)]
     pub snapshot_id: Option<String>,
+
+    /// Show what would change, without touching the database or uploads
+    #[arg(long)]
+    pub dry_run: bool,
 }
 
 #[derive(Args, Debug)]
@@ -129,6 +332,10 @@ pub struct StashDropArgs {
     /// Drop a particular stash snapshot by ID. This will not check project or branch constraints - use with caution.
     #[arg(index = 1)]
     pub snapshot_id: String,
+
+    /// Drop the stash even if it's pinned
+    #[arg(long)]
+    pub force: bool,
 }
 
 pub struct CliResponse {
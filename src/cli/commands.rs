@@ -1,6 +1,7 @@
 use ::clap::Parser;
 use colored::*;
 use dialoguer::{Confirm, Input};
+use indicatif::HumanBytes;
 
 use env_logger::Builder;
 use log::{info, warn};
@@ -8,16 +9,20 @@ use passwords::PasswordGenerator;
 use rustic_backend::BackendOptions;
 use rustic_core::{ConfigOptions, Id, KeyOptions, Progress, ProgressBars, RepositoryOptions};
 use self_update::cargo_crate_version;
-use std::{io::Write, time::SystemTime};
+use std::{collections::HashSet, io::Write, time::SystemTime};
 
 use crate::{
-    cli::clap::{CliResponse, Options, RepoCommand, StashCommand, SubCommand},
+    cli::clap::{BranchCommand, CliResponse, Options, RepoCommand, StashCommand, SubCommand},
+    diff::SnapshotDiff,
     engine::Engine,
-    facts::wordpress::WordPress,
+    facts::FactProviderKind,
+    filter::{PathFilter, TableSelection},
+    hooks::Hook,
     progress::SproutProgressBar,
-    project::Project,
-    repo::{definition::RepositoryDefinition, ProjectRepository},
-    snapshot::Snapshot,
+    project::{Project, SeedOptions},
+    repo::{definition::RepositoryDefinition, refuse_pinned, refuse_referenced, ProjectRepository},
+    retention::KeepPolicy,
+    snapshot::{Annotation, Scope, Snapshot},
     stash::Stash,
     theme::CliTheme,
     CFG_OS, CFG_TARGET_ARCH,
@@ -122,9 +127,18 @@ pub fn run(engine: &Engine) -> anyhow::Result<CliResponse> {
         )
     })?;
 
-    let facts = Box::new(WordPress {
-        path: options.path.to_owned(),
-    });
+    let config_path = options.path.join("sprout.yaml");
+
+    // There's no sprout.yaml before `init`, but one which is there has to parse
+    let facts_kind = if config_path.exists() {
+        Project::load_project_config(&config_path)
+            .map_err(|e| anyhow::anyhow!("Could not read {}: {}", config_path.display(), e))?
+            .facts
+    } else {
+        FactProviderKind::default()
+    };
+
+    let facts = facts_kind.provider(&options.path);
 
     match options.subcommand {
         SubCommand::Init => {
@@ -329,11 +343,48 @@ pub fn run(engine: &Engine) -> anyhow::Result<CliResponse> {
                 info!("This project or branch appears to be new.");
             }
 
+            let annotation = Annotation {
+                message: args.message,
+                tag: args.tag,
+            };
+
+            let tag_holder = match &annotation.tag {
+                Some(tag) => repo.check_tag(tag, &project.config.branch, args.force)?,
+                None => None,
+            };
+
+            project.run_hook(Hook::PreSnap, project.config.snapshot)?;
+
             info!("Starting snapshot...");
 
-            let snapshot = repo.snapshot(false)?;
+            let snap = || -> anyhow::Result<_> {
+                let snapshot = repo.snapshot_annotated(false, &Scope::default(), &annotation)?;
+
+                if let (Some(tag), Some(holder)) = (&annotation.tag, tag_holder) {
+                    repo.untag(&holder, tag)?;
+                    info!("Moved tag {} from {}", tag, holder.id);
+                }
+
+                project.update_snapshot_id(snapshot.id, project.config.branch.to_owned())?;
+
+                Ok(snapshot)
+            };
+
+            let snapshot = match snap() {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    // Give post_snap a chance to undo pre_snap, eg. leaving maintenance mode
+                    if let Err(hook_error) =
+                        project.run_hook_after_failure(Hook::PostSnap, project.config.snapshot)
+                    {
+                        warn!("{}", hook_error);
+                    }
+
+                    return Err(e);
+                }
+            };
 
-            project.update_snapshot_id(snapshot.id, project.config.branch.to_owned())?;
+            project.run_hook(Hook::PostSnap, Some(snapshot.id))?;
 
             Ok(CliResponse {
                 msg: "Snapshot created".to_string(),
@@ -355,12 +406,7 @@ pub fn run(engine: &Engine) -> anyhow::Result<CliResponse> {
             let snapshot = match args.snapshot_id {
                 None => project.get_active_snapshot(&repo)?,
                 Some(snapshot_id) => {
-                    let snap = Snapshot::from_snapshot_id(
-                        &repo.repo,
-                        Id::from_hex(&snapshot_id)
-                            .map_err(|_| anyhow::anyhow!("Could not find snapshot"))?,
-                    )
-                    .map_err(|_| anyhow::anyhow!("Could not find snapshot"))?;
+                    let snap = repo.resolve_snapshot(&snapshot_id)?;
 
                     match &project.unique_hash {
                         Some(hash) => {
@@ -381,32 +427,68 @@ pub fn run(engine: &Engine) -> anyhow::Result<CliResponse> {
                 }
             };
 
-            if !args.no_stash {
-                warn!("This command is destructive. Stashing your database and uploads locally.");
-                let stash = Stash::new(engine, engine.get_stash_path())?;
-                stash.stash(&project)?;
-            } else {
-                let confirmation = Confirm::with_theme(&CliTheme::default())
-                    .with_prompt("This command is destructive, and stashing has been disabled. Do you want to continue?")
-                    .interact()
-                    .unwrap();
-
-                if confirmation {
-                    warn!("Continuing without stashing. This will overwrite your database and uploads directory.");
-                } else {
-                    return Ok(CliResponse {
-                        msg: "Aborted by user, but no error".to_string(),
-                        data: None,
-                    });
+            let options = SeedOptions {
+                scope: match (args.db_only, args.uploads_only) {
+                    (true, _) => Scope::database_only(),
+                    (_, true) => Scope::uploads_only(),
+                    _ => Scope::default(),
+                },
+                filter: PathFilter::new(&args.include, &args.exclude)?,
+                tables: TableSelection {
+                    include: if args.tables.is_empty() {
+                        project.config.tables.include.to_owned()
+                    } else {
+                        args.tables.to_owned()
+                    },
+                    exclude: if args.exclude_tables.is_empty() {
+                        project.config.tables.exclude.to_owned()
+                    } else {
+                        args.exclude_tables.to_owned()
+                    },
                 }
+                .to_filter()?,
+            };
+
+            // Table defaults from sprout.yaml don't make a seed partial, but anything narrowed on the command line does
+            let partial = options.scope.tag().is_some()
+                || !options.filter.is_empty()
+                || !args.tables.is_empty()
+                || !args.exclude_tables.is_empty();
+
+            if args.dry_run {
+                let report = project.plan_restore_from_snapshot(&repo, &snapshot, &options)?;
+
+                eprint!("\n{}", crate::cli::snapshot::change_table(&report)?);
+
+                return Ok(CliResponse {
+                    msg: format!("Dry run of seeding {} - nothing was changed", snapshot.id),
+                    data: Some(serde_json::to_string(&report)?),
+                });
             }
 
-            project.restore_from_snapshot(&repo, &snapshot)?;
+            if !seed_snapshot(
+                engine,
+                &project,
+                &repo,
+                &snapshot,
+                &options,
+                args.no_stash,
+                args.no_rollback,
+            )? {
+                return Ok(CliResponse {
+                    msg: "Aborted by user, but no error".to_string(),
+                    data: None,
+                });
+            }
 
-            project.update_snapshot_id(snapshot.id, snapshot.get_branch()?)?;
+            if !partial {
+                project.update_snapshot_id(snapshot.id, snapshot.get_branch()?)?;
+            } else {
+                info!("Partial seed - the active snapshot for this project has not been changed");
+            }
 
             Ok(CliResponse {
-                msg: "Content and database seeded".to_string(),
+                msg: format!("Seeded {}", options.scope),
                 data: Some(serde_json::to_string(&project)?),
             })
         }
@@ -433,9 +515,29 @@ pub fn run(engine: &Engine) -> anyhow::Result<CliResponse> {
 
             eprint!(
                 "\n{}",
-                crate::cli::snapshot::project_table(&snapshots, Some(&project))?
+                crate::cli::snapshot::project_table(
+                    &snapshots,
+                    Some(&project),
+                    &repo.get_pinned()?
+                )?
             );
 
+            let annotations: [(&str, fn(&Snapshot) -> Option<String>); 3] = [
+                ("Messages", Snapshot::get_message),
+                ("Uploads exclusion rules", |snapshot| {
+                    snapshot.get_rules().map(|rules| rules.to_string())
+                }),
+                ("Sanitised with", Snapshot::get_sanitise_profile),
+            ];
+
+            for (heading, annotation) in annotations {
+                if let Some(table) =
+                    crate::cli::snapshot::annotation_table(&snapshots, heading, annotation)?
+                {
+                    eprint!("\n{}", table);
+                }
+            }
+
             Ok(CliResponse {
                 msg: format!(
                     "Listed all snapshots for {} on {} - {}",
@@ -447,17 +549,333 @@ pub fn run(engine: &Engine) -> anyhow::Result<CliResponse> {
             })
         }
 
-        SubCommand::UnStash(args) => {
+        SubCommand::Tag(args) => {
             let project = Project::new(engine, options.path.to_owned(), facts)?;
 
             project.print_header();
 
-            info!("Restoring stashed database and uploads...");
+            let (_, definition) = RepositoryDefinition::get(engine, &project.config.repo)?;
+
+            let repo = project.open_repo(&definition.repo_key)?;
+
+            let snapshot = repo.resolve_snapshot(&args.snapshot_id)?;
+            let tagged = repo.tag(&snapshot, &args.name, args.force)?;
+
+            Ok(CliResponse {
+                msg: format!("Tagged {} as {}", snapshot.id, args.name),
+                data: Some(serde_json::to_string(&tagged)?),
+            })
+        }
+
+        SubCommand::Pin(args) => {
+            let project = Project::new(engine, options.path.to_owned(), facts)?;
+
+            project.print_header();
+
+            let repo = if args.stash {
+                Stash::new(engine, engine.get_stash_path())?.open_stash(&project)?
+            } else {
+                let (_, definition) = RepositoryDefinition::get(engine, &project.config.repo)?;
+                project.open_repo(&definition.repo_key)?
+            };
+
+            let snapshot = repo.resolve_snapshot(&args.snapshot_id)?;
+
+            Ok(CliResponse {
+                msg: if repo.pin(&snapshot)? {
+                    format!("Pinned {}", snapshot.id)
+                } else {
+                    format!("{} is already pinned", snapshot.id)
+                },
+                data: Some(serde_json::to_string(&snapshot)?),
+            })
+        }
+
+        SubCommand::Unpin(args) => {
+            let project = Project::new(engine, options.path.to_owned(), facts)?;
+
+            project.print_header();
+
+            let repo = if args.stash {
+                Stash::new(engine, engine.get_stash_path())?.open_stash(&project)?
+            } else {
+                let (_, definition) = RepositoryDefinition::get(engine, &project.config.repo)?;
+                project.open_repo(&definition.repo_key)?
+            };
+
+            let snapshot = repo.resolve_snapshot(&args.snapshot_id)?;
+
+            Ok(CliResponse {
+                msg: if repo.unpin(&snapshot)? {
+                    format!("Unpinned {}", snapshot.id)
+                } else {
+                    format!("{} isn't pinned", snapshot.id)
+                },
+                data: Some(serde_json::to_string(&snapshot)?),
+            })
+        }
+
+        SubCommand::Branch(args) => {
+            let mut project = Project::new(engine, options.path.to_owned(), facts)?;
+
+            project.print_header();
+
+            let (_, definition) = RepositoryDefinition::get(engine, &project.config.repo)?;
+
+            let repo = project.open_repo(&definition.repo_key)?;
+
+            match args.subcommand {
+                BranchCommand::List => {
+                    let branches = repo.get_branches()?;
+
+                    eprint!(
+                        "\n{}",
+                        crate::cli::branch::branch_table(&branches, &project)?
+                    );
+
+                    Ok(CliResponse {
+                        msg: format!(
+                            "Listed {} branch(es) for {}",
+                            branches.len(),
+                            project.config.name
+                        ),
+                        data: Some(serde_json::to_string(&branches)?),
+                    })
+                }
+
+                BranchCommand::Switch(args) => {
+                    project.determine_home_url()?;
+
+                    let head = repo
+                        .get_latest_snapshot_for_branch(&project, &args.name)
+                        .map_err(|_| {
+                            anyhow::anyhow!("The branch {} has no snapshots", args.name)
+                        })?;
+
+                    info!("Switching to {}, and seeding {}", args.name, head.id);
+
+                    if !seed_snapshot(
+                        engine,
+                        &project,
+                        &repo,
+                        &head,
+                        &SeedOptions::default(),
+                        args.no_stash,
+                        args.no_rollback,
+                    )? {
+                        return Ok(CliResponse {
+                            msg: "Aborted by user, but no error".to_string(),
+                            data: None,
+                        });
+                    }
+
+                    project.update_snapshot_id(head.id, args.name.to_owned())?;
+
+                    Ok(CliResponse {
+                        msg: format!("Switched to {}", args.name),
+                        data: Some(serde_json::to_string(&project)?),
+                    })
+                }
+
+                BranchCommand::Rename(args) => {
+                    let renamed = repo.rename_branch(&args.from, &args.to)?;
+
+                    if project.config.branch == args.from {
+                        let active = project
+                            .config
+                            .snapshot
+                            .and_then(|id| renamed.iter().find(|snap| snap.has_id(id)))
+                            .map(|snap| snap.id);
+
+                        match active {
+                            Some(id) => project.update_snapshot_id(id, args.to.to_owned())?,
+                            None => warn!(
+                                "This project is on {}, but its active snapshot isn't. Its sprout.yaml has not been changed.",
+                                args.from
+                            ),
+                        }
+                    }
+
+                    Ok(CliResponse {
+                        msg: format!(
+                            "Renamed {} to {} ({} snapshot(s))",
+                            args.from,
+                            args.to,
+                            renamed.len()
+                        ),
+                        data: Some(serde_json::to_string(&renamed)?),
+                    })
+                }
+
+                BranchCommand::Delete(args) => {
+                    if project.config.branch == args.name {
+                        return Err(anyhow::anyhow!(
+                            "This project is on {}. Switch to another branch before deleting it.",
+                            args.name
+                        ));
+                    }
+
+                    let snapshots = repo.get_branch_snapshots(&args.name)?;
+
+                    if snapshots.is_empty() {
+                        return Err(anyhow::anyhow!("The branch {} has no snapshots", args.name));
+                    }
+
+                    refuse_referenced(&snapshots, &project.get_referenced_snapshots()?)?;
+                    refuse_pinned(&snapshots, &repo.get_pinned()?, args.include_pinned)?;
+
+                    let confirmation = Confirm::with_theme(&CliTheme::default())
+                        .with_prompt(format!(
+                            "Are you sure you want to delete {}, and forget its {} snapshot(s)? This cannot be undone.",
+                            args.name,
+                            snapshots.len()
+                        ))
+                        .interact()
+                        .unwrap();
+
+                    if !confirmation {
+                        return Ok(CliResponse {
+                            msg: "Aborted by user, but no error".to_string(),
+                            data: None,
+                        });
+                    }
+
+                    let progress = SproutProgressBar {};
+                    let spinner = progress.progress_spinner("Forgetting snapshots and pruning...");
+
+                    let ret = repo.delete_branch(&args.name, args.include_pinned);
+
+                    spinner.finish();
+
+                    let forgotten = ret?;
+
+                    Ok(CliResponse {
+                        msg: format!(
+                            "Deleted {} and forgot {} snapshot(s)",
+                            args.name,
+                            forgotten.len()
+                        ),
+                        data: Some(serde_json::to_string(&forgotten)?),
+                    })
+                }
+
+                BranchCommand::Fork(args) => {
+                    let from = match repo.get_branch_snapshots(&args.from)?.into_iter().next() {
+                        Some(head) => head,
+                        None => repo.resolve_snapshot(&args.from)?,
+                    };
+
+                    let fork = repo.fork_branch(&from, &args.to)?;
+
+                    Ok(CliResponse {
+                        msg: format!("Forked {} from {}", args.to, from.id),
+                        data: Some(serde_json::to_string(&fork)?),
+                    })
+                }
+            }
+        }
+
+        SubCommand::Diff(args) => {
+            let project = Project::new(engine, options.path.to_owned(), facts)?;
+
+            project.print_header();
+
+            let (_, definition) = RepositoryDefinition::get(engine, &project.config.repo)?;
+
+            let repo = project.open_repo(&definition.repo_key)?;
+
+            let from = match &args.from {
+                Some(id) => repo.resolve_snapshot(id)?,
+                None => project.get_active_snapshot(&repo)?,
+            };
+
+            let to = match &args.to {
+                Some(id) => repo.resolve_snapshot(id)?,
+                None => repo.get_latest_snapshot_for_branch(&project, &project.config.branch)?,
+            };
+
+            info!("Comparing {} to {}", from.id, to.id);
+
+            let diff = SnapshotDiff::new(&repo, &from, &to)?;
+
+            eprint!("\n{}", crate::cli::snapshot::diff_table(&diff)?);
+
+            Ok(CliResponse {
+                msg: format!("Compared {} to {}", from.id, to.id),
+                data: Some(serde_json::to_string(&diff)?),
+            })
+        }
+
+        SubCommand::Forget(args) => {
+            let project = Project::new(engine, options.path.to_owned(), facts)?;
+
+            project.print_header();
+
+            let policy = KeepPolicy {
+                last: args.keep_last,
+                daily: args.keep_daily,
+                weekly: args.keep_weekly,
+                per_branch: args.keep_per_branch,
+            };
+
+            if policy.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Please set at least one of --keep-last, --keep-daily, --keep-weekly or --keep-per-branch"
+                ));
+            }
+
+            let (_, definition) = RepositoryDefinition::get(engine, &project.config.repo)?;
+
+            let repo = project.open_repo(&definition.repo_key)?;
+
+            let mut protected = project.get_referenced_snapshots()?;
+
+            info!(
+                "Protecting {} snapshot(s) referenced by known sprout.yaml files",
+                protected.len()
+            );
+
+            let pinned = repo.get_pinned()?;
+
+            if args.include_pinned {
+                warn!("Pinned snapshots may be forgotten (--include-pinned)");
+            } else {
+                info!("Protecting {} pinned snapshot(s)", pinned.len());
+                protected.extend(&pinned);
+            }
+
+            let (keep, forget) = repo.plan_forget(&policy, args.branch.as_deref(), &protected)?;
+
+            info!("Keeping {} snapshot(s)", keep.len());
+
+            if forget.is_empty() {
+                return Ok(CliResponse {
+                    msg: "No snapshots to forget".to_string(),
+                    data: Some(serde_json::to_string(&forget)?),
+                });
+            }
+
+            info!("The following snapshots will be forgotten:");
+
+            eprint!(
+                "\n{}",
+                crate::cli::snapshot::project_table(&forget, Some(&project), &pinned)?
+            );
+
+            if args.dry_run {
+                return Ok(CliResponse {
+                    msg: format!("Dry run - {} snapshot(s) would be forgotten", forget.len()),
+                    data: Some(serde_json::to_string(&forget)?),
+                });
+            }
 
             let confirmation = Confirm::with_theme(&CliTheme::default())
-                    .with_prompt("This command is destructive. This will overwrite your database and uploads directory. Do you want to continue?")
-                    .interact()
-                    .unwrap();
+                .with_prompt(format!(
+                    "Are you sure you want to forget {} snapshot(s)? This cannot be undone.",
+                    forget.len()
+                ))
+                .interact()
+                .unwrap();
 
             if !confirmation {
                 return Ok(CliResponse {
@@ -466,6 +884,30 @@ pub fn run(engine: &Engine) -> anyhow::Result<CliResponse> {
                 });
             }
 
+            let progress = SproutProgressBar {};
+            let spinner = progress.progress_spinner("Forgetting snapshots and pruning...");
+
+            let ret = repo.forget(&forget);
+
+            spinner.finish();
+
+            ret?;
+
+            Ok(CliResponse {
+                msg: format!(
+                    "Forgot {} snapshot(s) for {}",
+                    forget.len(),
+                    project.config.name
+                ),
+                data: Some(serde_json::to_string(&forget)?),
+            })
+        }
+
+        SubCommand::UnStash(args) => {
+            let project = Project::new(engine, options.path.to_owned(), facts)?;
+
+            project.print_header();
+
             let stash = Stash::new(engine, engine.get_stash_path())?;
 
             let snap_id = match args.snapshot_id {
@@ -473,8 +915,38 @@ pub fn run(engine: &Engine) -> anyhow::Result<CliResponse> {
                 None => stash.get_latest_stash(&project)?.id,
             };
 
+            if args.dry_run {
+                let report = stash.plan_restore(&project, snap_id)?;
+
+                eprint!("\n{}", crate::cli::snapshot::change_table(&report)?);
+
+                return Ok(CliResponse {
+                    msg: format!(
+                        "Dry run of restoring stash {} - nothing was changed",
+                        snap_id
+                    ),
+                    data: Some(serde_json::to_string(&report)?),
+                });
+            }
+
+            info!("Restoring stashed database and uploads...");
+
+            let confirmation = Confirm::with_theme(&CliTheme::default())
+                    .with_prompt("This command is destructive. This will overwrite your database and uploads directory. Do you want to continue?")
+                    .interact()
+                    .unwrap();
+
+            if !confirmation {
+                return Ok(CliResponse {
+                    msg: "Aborted by user, but no error".to_string(),
+                    data: None,
+                });
+            }
+
             stash.restore(&project, snap_id)?;
 
+            project.run_hook(Hook::PostUnstash, Some(snap_id))?;
+
             Ok(CliResponse {
                 msg: format!(
                     "Restored the stash of {} ({})",
@@ -493,6 +965,8 @@ pub fn run(engine: &Engine) -> anyhow::Result<CliResponse> {
 
                 project.determine_home_url()?;
 
+                project.run_hook(Hook::PreStash, project.config.snapshot)?;
+
                 let stash = Stash::new(engine, engine.get_stash_path())?;
                 stash.stash(&project)?;
 
@@ -520,7 +994,10 @@ pub fn run(engine: &Engine) -> anyhow::Result<CliResponse> {
                         project.config.name
                     );
 
-                    eprint!("\n{}", crate::cli::snapshot::project_table(&stashes, None)?);
+                    eprint!(
+                        "\n{}",
+                        crate::cli::snapshot::project_table(&stashes, None, &stash.get_pinned()?)?
+                    );
 
                     Ok(CliResponse {
                         msg: format!("Listed all local stashes for {}", project.config.name),
@@ -552,13 +1029,43 @@ pub fn run(engine: &Engine) -> anyhow::Result<CliResponse> {
                         });
                     }
 
-                    stash.drop(Id::from_hex(&args.snapshot_id)?)?;
+                    stash.drop(Id::from_hex(&args.snapshot_id)?, args.force)?;
 
                     Ok(CliResponse {
                         msg: "Dropped the stashed snapshot".to_string(),
                         data: None,
                     })
                 }
+                StashCommand::Prune => {
+                    let stash = Stash::new(engine, engine.get_stash_path())?;
+
+                    info!("Applying stash retention policy...");
+
+                    let progress = SproutProgressBar {};
+                    let spinner = progress.progress_spinner("Pruning stashes...");
+
+                    let ret = stash.apply_retention(None);
+
+                    spinner.finish();
+
+                    let (dropped, reclaimed) = ret?;
+
+                    if !dropped.is_empty() {
+                        eprint!(
+                            "\n{}",
+                            crate::cli::snapshot::project_table(&dropped, None, &HashSet::new())?
+                        );
+                    }
+
+                    Ok(CliResponse {
+                        msg: format!(
+                            "Dropped {} stash(es) and reclaimed {}",
+                            dropped.len(),
+                            HumanBytes(reclaimed)
+                        ),
+                        data: Some(serde_json::to_string(&dropped)?),
+                    })
+                }
             },
         },
         SubCommand::Update => {
@@ -638,3 +1145,60 @@ pub fn run(engine: &Engine) -> anyhow::Result<CliResponse> {
         }
     }
 }
+
+/// Seed `snapshot` into the project, stashing first (unless `no_stash`) and rolling back to the stash if the seed
+/// fails (unless `no_rollback`). Returns false if the user backed out.
+fn seed_snapshot(
+    engine: &Engine,
+    project: &Project,
+    repo: &ProjectRepository,
+    snapshot: &Snapshot,
+    options: &SeedOptions,
+    no_stash: bool,
+    no_rollback: bool,
+) -> anyhow::Result<bool> {
+    project.run_hook(Hook::PreSeed, Some(snapshot.id))?;
+
+    let mut stashed = None;
+
+    if !no_stash {
+        warn!(
+            "This command is destructive. Stashing your {} locally.",
+            options.scope
+        );
+        project.run_hook(Hook::PreStash, project.config.snapshot)?;
+        let stash = Stash::new(engine, engine.get_stash_path())?;
+        let stash_id = stash.stash_with(project, &options.scope)?.id;
+        stashed = Some((stash, stash_id));
+    } else {
+        let confirmation = Confirm::with_theme(&CliTheme::default())
+            .with_prompt("This command is destructive, and stashing has been disabled. Do you want to continue?")
+            .interact()
+            .unwrap();
+
+        if confirmation {
+            warn!("Continuing without stashing. This will overwrite your database and uploads directory.");
+        } else {
+            return Ok(false);
+        }
+    }
+
+    let seed = || -> anyhow::Result<()> {
+        project.restore_from_snapshot_with(repo, snapshot, options)?;
+        project.run_hook(Hook::PostSeed, Some(snapshot.id))
+    };
+
+    match &stashed {
+        Some((stash, stash_id)) if !no_rollback => stash.with_rollback(project, *stash_id, seed)?,
+        Some((_, stash_id)) => seed().map_err(|e| {
+            let message = format!(
+                "{}. Not rolling back - restore your stash with `sprout un-stash {}`",
+                e, stash_id
+            );
+            e.context(message)
+        })?,
+        None => seed()?,
+    }
+
+    Ok(true)
+}
@@ -1,21 +1,30 @@
 use colored::*;
 use indicatif::HumanBytes;
-use std::io::Write;
+use rustic_core::Id;
+use std::{collections::HashSet, io::Write};
 use tabwriter::TabWriter;
 
-use crate::{project::Project, snapshot::Snapshot};
-/// Generates a table showing all snapshots passed in
+use crate::{
+    diff::{SnapshotDiff, TableStatus},
+    project::Project,
+    report::{ChangeReport, FileChange},
+    snapshot::Snapshot,
+};
+/// Generates a table showing all snapshots passed in. Snapshots in `pinned` are flagged.
 pub fn project_table(
     snapshots: &Vec<Snapshot>,
     project: Option<&Project>,
+    pinned: &HashSet<Id>,
 ) -> anyhow::Result<String> {
     let mut tw = TabWriter::new(vec![]).ansi(true);
 
     write!(
         &mut tw,
-        "\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+        "\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
         "ID".dimmed().bold(),
+        "Pin".dimmed().bold(),
         "Branch".dimmed().bold(),
+        "Tags".dimmed().bold(),
         "Files".dimmed().bold(),
         "New".dimmed().italic().cyan(),
         "Change".dimmed().italic().cyan(),
@@ -31,12 +40,12 @@ pub fn project_table(
             &mut tw,
             "{}",
             format!(
-                "{:^8}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                "{:^8}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
                 match project {
                     Some(project) => match project.config.snapshot {
                         None => "".normal(),
                         Some(snapshot) => {
-                            if snapshot == stash.id {
+                            if stash.has_id(snapshot) {
                                 "active ▶".green().dimmed().bold()
                             } else {
                                 "".normal()
@@ -46,7 +55,13 @@ pub fn project_table(
                     None => "".normal(),
                 },
                 stash.id.to_hex().to_string(),
+                if stash.is_any_of(pinned) {
+                    "pinned".yellow().bold()
+                } else {
+                    "".normal()
+                },
                 stash.get_branch().unwrap_or("???".to_string()),
+                stash.get_tags().join(", ").cyan(),
                 stash.get_total_files(),
                 match &stats {
                     Ok(stats) => format!(
@@ -77,3 +92,192 @@ pub fn project_table(
 
     Ok(String::from_utf8(tw.into_inner().unwrap()).unwrap())
 }
+
+/// Lists an annotation (eg. the message) of each snapshot which has one, under `heading`. Returns None if no snapshot
+/// had one.
+pub fn annotation_table(
+    snapshots: &Vec<Snapshot>,
+    heading: &str,
+    annotation: impl Fn(&Snapshot) -> Option<String>,
+) -> anyhow::Result<Option<String>> {
+    let mut tw = TabWriter::new(vec![]).ansi(true);
+    let mut any = false;
+
+    for snapshot in snapshots {
+        if let Some(value) = annotation(snapshot) {
+            any = true;
+            writeln!(
+                &mut tw,
+                "{}\t{}",
+                snapshot.id.to_hex().to_string().dimmed(),
+                value
+            )?;
+        }
+    }
+
+    tw.flush().unwrap();
+
+    if !any {
+        return Ok(None);
+    }
+
+    Ok(Some(format!(
+        "{}\n{}",
+        heading.dimmed().bold(),
+        String::from_utf8(tw.into_inner().unwrap()).unwrap()
+    )))
+}
+
+/// Generates a report of what a restore would change
+pub fn change_table(report: &ChangeReport) -> anyhow::Result<String> {
+    let mut tw = TabWriter::new(vec![]).ansi(true);
+
+    let sections: [(&str, &Vec<FileChange>, ColoredString); 3] = [
+        ("Added", &report.added, "+".green()),
+        ("Changed", &report.changed, "~".blue()),
+        ("Deleted", &report.deleted, "-".red()),
+    ];
+
+    for (label, files, _) in &sections {
+        writeln!(
+            &mut tw,
+            "{}\t{}\t{}",
+            label.dimmed().bold(),
+            files.len(),
+            format!("({})", HumanBytes(ChangeReport::total_size(files))).dimmed()
+        )?;
+    }
+
+    writeln!(
+        &mut tw,
+        "{}\t{}",
+        "Database".dimmed().bold(),
+        match report.database_size {
+            Some(size) => format!("{} to import", HumanBytes(size)),
+            None => "Not restored".to_string(),
+        }
+    )?;
+
+    writeln!(&mut tw)?;
+
+    for (_, files, marker) in &sections {
+        for file in files.iter() {
+            writeln!(
+                &mut tw,
+                "{}\t{}\t{}",
+                marker,
+                file.path.to_string_lossy(),
+                HumanBytes(file.size).to_string().dimmed()
+            )?;
+        }
+    }
+
+    tw.flush().unwrap();
+
+    Ok(String::from_utf8(tw.into_inner().unwrap()).unwrap())
+}
+
+/// Generates a summary of the differences between two snapshots
+pub fn diff_table(diff: &SnapshotDiff) -> anyhow::Result<String> {
+    let mut tw = TabWriter::new(vec![]).ansi(true);
+
+    writeln!(
+        &mut tw,
+        "{}\t{}\t{}\t{}",
+        "Uploads".dimmed().bold(),
+        format!("+{}", diff.added.len()).green(),
+        format!("~{}", diff.modified.len()).blue(),
+        format!("-{}", diff.removed.len()).red()
+    )?;
+
+    writeln!(
+        &mut tw,
+        "{}\t{}\t{}\t{}\t{}",
+        "Tables".dimmed().bold(),
+        format!(
+            "+{}",
+            diff.tables
+                .iter()
+                .filter(|t| t.status == TableStatus::Added)
+                .count()
+        )
+        .green(),
+        format!(
+            "~{}",
+            diff.tables
+                .iter()
+                .filter(|t| t.status == TableStatus::Modified)
+                .count()
+        )
+        .blue(),
+        format!(
+            "-{}",
+            diff.tables
+                .iter()
+                .filter(|t| t.status == TableStatus::Removed)
+                .count()
+        )
+        .red(),
+        format!("({} unchanged)", diff.tables_unchanged).dimmed()
+    )?;
+
+    writeln!(&mut tw)?;
+
+    let size = |size: Option<u64>| match size {
+        Some(size) => HumanBytes(size).to_string(),
+        None => "-".to_string(),
+    };
+
+    for file in &diff.added {
+        writeln!(
+            &mut tw,
+            "{}\t{}\t{}",
+            "+".green(),
+            file.path.to_string_lossy(),
+            HumanBytes(file.size).to_string().dimmed()
+        )?;
+    }
+
+    for file in &diff.modified {
+        writeln!(
+            &mut tw,
+            "{}\t{}\t{}",
+            "~".blue(),
+            file.path.to_string_lossy(),
+            format!(
+                "{} → {}",
+                HumanBytes(file.from_size),
+                HumanBytes(file.to_size)
+            )
+            .dimmed()
+        )?;
+    }
+
+    for file in &diff.removed {
+        writeln!(
+            &mut tw,
+            "{}\t{}\t{}",
+            "-".red(),
+            file.path.to_string_lossy(),
+            HumanBytes(file.size).to_string().dimmed()
+        )?;
+    }
+
+    for table in &diff.tables {
+        writeln!(
+            &mut tw,
+            "{}\t{}\t{}",
+            match table.status {
+                TableStatus::Added => "+".green(),
+                TableStatus::Modified => "~".blue(),
+                TableStatus::Removed => "-".red(),
+            },
+            format!("table {}", table.name),
+            format!("{} → {}", size(table.from_size), size(table.to_size)).dimmed()
+        )?;
+    }
+
+    tw.flush().unwrap();
+
+    Ok(String::from_utf8(tw.into_inner().unwrap()).unwrap())
+}
@@ -0,0 +1,220 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use rustic_core::{
+    repofile::{Node, NodeType},
+    Id, IndexedFull, LsOptions,
+};
+use serde::Serialize;
+use sha2::{Digest, Sha224};
+
+use crate::{
+    dump::{table_digests, table_name_from_file, TableDigest, DUMP_FILE, TABLES_DIR},
+    repo::{ProjectRepository, RusticRepo},
+    report::FileChange,
+    snapshot::Snapshot,
+    stream::run_pipeline,
+};
+
+/// An upload which exists in both snapshots, with different contents
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FileModified {
+    pub path: PathBuf,
+    pub from_size: u64,
+    pub to_size: u64,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TableStatus {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// A database table which differs between two snapshots. Sizes are of the table's section of the dump.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TableChange {
+    pub name: String,
+    pub status: TableStatus,
+    pub from_size: Option<u64>,
+    pub to_size: Option<u64>,
+}
+
+/// The differences between two snapshots. Parts missing from either snapshot (eg. a partial stash) are not compared.
+#[derive(Debug, Serialize, Clone)]
+pub struct SnapshotDiff {
+    pub from: Id,
+    pub to: Id,
+    pub added: Vec<FileChange>,
+    pub removed: Vec<FileChange>,
+    pub modified: Vec<FileModified>,
+    pub tables: Vec<TableChange>,
+    /// Number of tables present, and identical, in both snapshots
+    pub tables_unchanged: usize,
+}
+
+impl SnapshotDiff {
+    pub fn new(repo: &ProjectRepository, from: &Snapshot, to: &Snapshot) -> anyhow::Result<Self> {
+        let indexed = repo.indexed()?;
+        let rustic_repo = indexed.as_ref();
+
+        let mut diff = Self {
+            from: from.id,
+            to: to.id,
+            added: vec![],
+            removed: vec![],
+            modified: vec![],
+            tables: vec![],
+            tables_unchanged: 0,
+        };
+
+        if from.get_scope().uploads && to.get_scope().uploads {
+            diff.diff_uploads(
+                list_files(rustic_repo, &repo.get_uploads_node(from)?)?,
+                list_files(rustic_repo, &repo.get_uploads_node(to)?)?,
+            );
+        }
+
+        if from.get_scope().database && to.get_scope().database {
+            diff.diff_tables(
+                database_digests(repo, rustic_repo, from)?,
+                database_digests(repo, rustic_repo, to)?,
+            );
+        }
+
+        Ok(diff)
+    }
+
+    fn diff_uploads(&mut self, from: BTreeMap<PathBuf, Node>, to: BTreeMap<PathBuf, Node>) {
+        for (path, node) in &to {
+            match from.get(path) {
+                None => self.added.push(FileChange {
+                    path: path.to_owned(),
+                    size: node.meta.size,
+                }),
+                Some(old) if old.content != node.content => self.modified.push(FileModified {
+                    path: path.to_owned(),
+                    from_size: old.meta.size,
+                    to_size: node.meta.size,
+                }),
+                _ => {}
+            }
+        }
+
+        for (path, node) in &from {
+            if !to.contains_key(path) {
+                self.removed.push(FileChange {
+                    path: path.to_owned(),
+                    size: node.meta.size,
+                });
+            }
+        }
+    }
+
+    fn diff_tables(&mut self, from: Vec<TableDigest>, to: Vec<TableDigest>) {
+        let old: BTreeMap<&str, &TableDigest> = from.iter().map(|t| (t.name.as_str(), t)).collect();
+        let new: BTreeMap<&str, &TableDigest> = to.iter().map(|t| (t.name.as_str(), t)).collect();
+
+        let names: BTreeSet<&str> = old.keys().chain(new.keys()).copied().collect();
+
+        for name in names {
+            let (from_size, to_size) =
+                (old.get(name).map(|t| t.size), new.get(name).map(|t| t.size));
+
+            let status = match (old.get(name), new.get(name)) {
+                (None, Some(_)) => TableStatus::Added,
+                (Some(_), None) => TableStatus::Removed,
+                (Some(a), Some(b)) if a.digest != b.digest => TableStatus::Modified,
+                _ => {
+                    self.tables_unchanged += 1;
+                    continue;
+                }
+            };
+
+            self.tables.push(TableChange {
+                name: name.to_string(),
+                status,
+                from_size,
+                to_size,
+            });
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+            && self.tables.is_empty()
+    }
+}
+
+/// All files beneath a node, keyed by their path relative to it
+fn list_files<S: IndexedFull>(
+    rustic_repo: &RusticRepo<S>,
+    node: &Node,
+) -> anyhow::Result<BTreeMap<PathBuf, Node>> {
+    let mut files = BTreeMap::new();
+
+    for entry in rustic_repo.ls(node, &LsOptions::default())? {
+        let (path, node) = entry?;
+
+        if matches!(node.node_type, NodeType::File) {
+            files.insert(path, node);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Fingerprint each table in a snapshot's database, without writing it to disk. Per-table dumps are compared by
+/// their files' content IDs, and a single dump is streamed from the repo and hashed table by table. The two aren't
+/// comparable, so every table differs between snapshots in different layouts.
+fn database_digests<S: IndexedFull + Sync>(
+    repo: &ProjectRepository,
+    rustic_repo: &RusticRepo<S>,
+    snapshot: &Snapshot,
+) -> anyhow::Result<Vec<TableDigest>> {
+    let files = list_files(rustic_repo, &repo.get_db_node(snapshot)?)?;
+
+    if let Some(dump) = files.get(Path::new(DUMP_FILE)) {
+        let mut digests = vec![];
+
+        run_pipeline(
+            |mut writer| Ok(rustic_repo.dump(dump, &mut writer)?),
+            vec![],
+            |reader| {
+                digests = table_digests(reader)?;
+                Ok(())
+            },
+        )?;
+
+        return Ok(digests);
+    }
+
+    let tables: Vec<TableDigest> = files
+        .iter()
+        .filter(|(path, _)| path.parent() == Some(Path::new(TABLES_DIR)))
+        .filter_map(|(path, node)| {
+            let mut hasher = Sha224::new();
+
+            for id in node.content.iter().flatten() {
+                hasher.update(id.to_hex().to_string());
+            }
+
+            Some(TableDigest {
+                name: table_name_from_file(&path.file_stem()?.to_string_lossy()),
+                size: node.meta.size,
+                digest: format!("{:x}", hasher.finalize()),
+            })
+        })
+        .collect();
+
+    if tables.is_empty() {
+        return Err(anyhow::anyhow!("Snapshot does not contain a database dump"));
+    }
+
+    Ok(tables)
+}
@@ -0,0 +1,613 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha224};
+
+use crate::filter::PathFilter;
+
+/// How a database dump is stored in a snapshot
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseLayout {
+    /// One `database.sql` file
+    #[default]
+    Single,
+    /// One file per table under `tables/`, plus the session `header.sql` and `footer.sql`
+    Tables,
+}
+
+pub const DUMP_FILE: &str = "database.sql";
+pub const TABLES_DIR: &str = "tables";
+pub const HEADER_FILE: &str = "header.sql";
+pub const FOOTER_FILE: &str = "footer.sql";
+
+/// Fewest rows per `INSERT` statement when splitting a dump by table, unless the table runs out first
+pub const INSERT_BATCH_MIN_ROWS: usize = 32;
+
+/// Most rows per `INSERT` statement when splitting a dump by table
+pub const INSERT_BATCH_MAX_ROWS: usize = 512;
+
+/// A batch can end after a row whose hash has these bits clear, so batches average around 100 rows
+const INSERT_BATCH_MASK: u8 = 0x3F;
+
+/// Which part of a dump a line belongs to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Section<'a> {
+    /// Session setup before the first table
+    Header,
+    Table(&'a str),
+    /// Session teardown after the last table
+    Footer,
+}
+
+/// Statements which open, or belong to, a table's section of a mysqldump
+const TABLE_PREFIXES: [&str; 8] = [
+    "-- Table structure for table `",
+    "-- Dumping data for table `",
+    "DROP TABLE IF EXISTS `",
+    "CREATE TABLE IF NOT EXISTS `",
+    "CREATE TABLE `",
+    "LOCK TABLES `",
+    "/*!40000 ALTER TABLE `",
+    "INSERT INTO `",
+];
+
+/// The table a line belongs to, if it starts a table statement
+fn table_name(line: &[u8]) -> Option<String> {
+    let prefix = TABLE_PREFIXES
+        .iter()
+        .find(|prefix| line.starts_with(prefix.as_bytes()))?;
+
+    let mut name = vec![];
+    let mut rest = line[prefix.len()..].iter().peekable();
+
+    while let Some(&byte) = rest.next() {
+        if byte == b'`' {
+            if rest.peek() == Some(&&b'`') {
+                rest.next();
+            } else {
+                return Some(String::from_utf8_lossy(&name).to_string());
+            }
+        }
+
+        name.push(byte);
+    }
+
+    None
+}
+
+/// Whether a line can belong to the triggers mysqldump writes straight after a table's data. They're wrapped in
+/// `/*!50003 ... */` session settings, with the `CREATE TRIGGER` itself inside a `DELIMITER ;;` block.
+fn is_trigger_line(line: &[u8]) -> bool {
+    line.starts_with(b"/*!50003 ") || line.starts_with(b"DELIMITER ")
+}
+
+/// Split a mysqldump style SQL dump by table, streaming every line to `f` along with the section it belongs to.
+/// A table's section runs from its first statement to `UNLOCK TABLES;`, plus any triggers on it which follow.
+/// Anything else between two tables (eg. comments) is attached to the next table, and anything after the last table
+/// is the footer.
+pub fn split_dump<R: Read, F: FnMut(Section, &[u8]) -> anyhow::Result<()>>(
+    reader: R,
+    mut f: F,
+) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut line = vec![];
+    let mut current: Option<String> = None;
+    // The table whose data just ended, while its triggers may still follow
+    let mut trailing: Option<String> = None;
+    let mut in_trigger = false;
+    let mut seen_table = false;
+    let mut pending: Vec<Vec<u8>> = vec![];
+
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        if let Some(name) = table_name(&line) {
+            seen_table = true;
+            trailing = None;
+            in_trigger = false;
+
+            for pending_line in pending.drain(..) {
+                f(Section::Table(&name), &pending_line)?;
+            }
+
+            f(Section::Table(&name), &line)?;
+            current = Some(name);
+        } else if let Some(name) = &current {
+            f(Section::Table(name), &line)?;
+
+            if line.starts_with(b"UNLOCK TABLES;") {
+                trailing = current.take();
+            }
+        } else if let Some(name) = trailing
+            .as_deref()
+            .filter(|_| in_trigger || is_trigger_line(&line))
+        {
+            f(Section::Table(name), &line)?;
+
+            if line.starts_with(b"DELIMITER ") {
+                in_trigger = line.starts_with(b"DELIMITER ;;");
+            }
+        } else if !seen_table {
+            f(Section::Header, &line)?;
+        } else {
+            trailing = None;
+            pending.push(line.clone());
+        }
+
+        line.clear();
+    }
+
+    for pending_line in pending {
+        f(Section::Footer, &pending_line)?;
+    }
+
+    Ok(())
+}
+
+/// A fingerprint of one table within a dump
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TableDigest {
+    pub name: String,
+    pub size: u64,
+    pub digest: String,
+}
+
+/// Fingerprint every table in a dump, in the order they appear
+pub fn table_digests<R: Read>(reader: R) -> anyhow::Result<Vec<TableDigest>> {
+    let mut order: Vec<String> = vec![];
+    let mut tables: BTreeMap<String, (u64, Sha224)> = BTreeMap::new();
+
+    split_dump(reader, |section, line| {
+        if let Section::Table(name) = section {
+            let (size, hasher) = tables.entry(name.to_string()).or_insert_with(|| {
+                order.push(name.to_string());
+                (0, Sha224::new())
+            });
+
+            *size += line.len() as u64;
+            hasher.update(line);
+        }
+
+        Ok(())
+    })?;
+
+    Ok(order
+        .into_iter()
+        .filter_map(|name| {
+            let (size, hasher) = tables.remove(&name)?;
+
+            Some(TableDigest {
+                name,
+                size,
+                digest: format!("{:x}", hasher.finalize()),
+            })
+        })
+        .collect())
+}
+
+/// Split a dump into one file per table under `dir`, in the `DatabaseLayout::Tables` layout
+pub fn split_to_dir(input: &Path, dir: &Path) -> anyhow::Result<()> {
+    split_dump_to_dir(File::open(input)?, dir)
+}
+
+/// Split a dump streamed from `reader` into one file per table under `dir`, as with `split_to_dir`
+pub fn split_dump_to_dir<R: Read>(reader: R, dir: &Path) -> anyhow::Result<()> {
+    let tables_dir = dir.join(TABLES_DIR);
+    fs::create_dir_all(&tables_dir)?;
+
+    let mut header = BufWriter::new(File::create(dir.join(HEADER_FILE))?);
+    let mut footer = BufWriter::new(File::create(dir.join(FOOTER_FILE))?);
+    let mut current: Option<(String, BufWriter<File>, InsertBatcher)> = None;
+
+    split_dump(reader, |section, line| {
+        match section {
+            Section::Header => header.write_all(line)?,
+            Section::Footer => footer.write_all(line)?,
+            Section::Table(name) => {
+                if current.as_ref().map(|(n, _, _)| n.as_str()) != Some(name) {
+                    if let Some((_, mut writer, mut batcher)) = current.take() {
+                        batcher.flush(&mut writer)?;
+                        writer.flush()?;
+                    }
+
+                    // Append, in case a table shows up more than once
+                    let file = OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(tables_dir.join(table_file_name(name)))?;
+
+                    current = Some((
+                        name.to_string(),
+                        BufWriter::new(file),
+                        InsertBatcher::default(),
+                    ));
+                }
+
+                if let Some((_, writer, batcher)) = current.as_mut() {
+                    batcher.write(writer, line)?;
+                }
+            }
+        }
+
+        Ok(())
+    })?;
+
+    if let Some((_, mut writer, mut batcher)) = current {
+        batcher.flush(&mut writer)?;
+        writer.flush()?;
+    }
+
+    header.flush()?;
+    footer.flush()?;
+
+    Ok(())
+}
+
+/// Find the dump restored into `dir`. Per-table dumps are reassembled into a single `database.sql` first.
+pub fn database_file(dir: &Path) -> anyhow::Result<PathBuf> {
+    let dump = dir.join(DUMP_FILE);
+
+    if dump.is_file() {
+        return Ok(dump);
+    }
+
+    if !dir.join(TABLES_DIR).is_dir() {
+        return Err(anyhow::anyhow!("Snapshot does not contain a database dump"));
+    }
+
+    let mut writer = BufWriter::new(File::create(&dump)?);
+
+    reassemble_dump(dir, &mut writer)?;
+
+    writer.flush()?;
+
+    Ok(dump)
+}
+
+/// Stream the per-table dumps restored into `dir` into `writer`, as a single dump in restore order
+pub fn reassemble_dump<W: Write + ?Sized>(dir: &Path, writer: &mut W) -> anyhow::Result<()> {
+    let header = dir.join(HEADER_FILE);
+    if header.is_file() {
+        io::copy(&mut File::open(header)?, writer)?;
+    }
+
+    for table in restore_order(&dir.join(TABLES_DIR))? {
+        io::copy(&mut File::open(table)?, writer)?;
+    }
+
+    let footer = dir.join(FOOTER_FILE);
+    if footer.is_file() {
+        io::copy(&mut File::open(footer)?, writer)?;
+    }
+
+    Ok(())
+}
+
+/// Order per-table dumps so referenced tables are created before the tables with foreign keys to them.
+/// Ties (and any cycles) fall back to alphabetical order, so the result is deterministic.
+pub fn restore_order(tables_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let references = Regex::new(r"REFERENCES `((?:[^`]|``)+)`").unwrap();
+
+    let mut files: BTreeMap<String, PathBuf> = BTreeMap::new();
+
+    for entry in fs::read_dir(tables_dir)? {
+        let path = entry?.path();
+
+        if path.extension().is_some_and(|ext| ext == "sql") {
+            if let Some(stem) = path.file_stem() {
+                files.insert(table_name_from_file(&stem.to_string_lossy()), path);
+            }
+        }
+    }
+
+    let mut deps: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+
+    for (table, path) in &files {
+        let mut table_deps = BTreeSet::new();
+
+        // Foreign keys live in CREATE TABLE, which comes before any data
+        for line in BufReader::new(File::open(path)?).split(b'\n') {
+            let line = String::from_utf8_lossy(&line?).to_string();
+
+            if line.starts_with("INSERT INTO ") {
+                break;
+            }
+
+            for caps in references.captures_iter(&line) {
+                let referenced = caps[1].replace("``", "`");
+
+                if referenced != *table && files.contains_key(&referenced) {
+                    table_deps.insert(referenced);
+                }
+            }
+        }
+
+        deps.insert(table.to_owned(), table_deps);
+    }
+
+    let mut ordered = vec![];
+
+    while !deps.is_empty() {
+        let next = deps
+            .iter()
+            .find(|(_, table_deps)| table_deps.is_empty())
+            .or_else(|| deps.iter().next())
+            .map(|(table, _)| table.to_owned())
+            .unwrap();
+
+        deps.remove(&next);
+
+        for table_deps in deps.values_mut() {
+            table_deps.remove(&next);
+        }
+
+        if let Some(path) = files.remove(&next) {
+            ordered.push(path);
+        }
+    }
+
+    Ok(ordered)
+}
+
+/// File name for a table's dump. Anything other than `[A-Za-z0-9_$-]` is percent-encoded.
+pub fn table_file_name(table: &str) -> String {
+    let mut name = String::with_capacity(table.len() + 4);
+
+    for byte in table.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' | b'$' | b'-' => name.push(byte as char),
+            _ => name.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    name.push_str(".sql");
+    name
+}
+
+/// Reverse `table_file_name`, given the file stem
+pub fn table_name_from_file(stem: &str) -> String {
+    let bytes = stem.as_bytes();
+    let mut name = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(byte) = stem
+                .get(i + 1..i + 3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                name.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+
+        name.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&name).to_string()
+}
+
+/// Rewrites a table's single line extended `INSERT`s into batches, one row per line. Batches end where a row's
+/// content says so rather than after a fixed count, so adding or removing a row only moves the boundaries around it,
+/// and the rest of the table still dedupes. Rows are carried over from one statement to the next, so batches don't
+/// follow mysqldump's own splits.
+#[derive(Default)]
+struct InsertBatcher {
+    prefix: Vec<u8>,
+    rows: Vec<Vec<u8>>,
+}
+
+impl InsertBatcher {
+    /// Write a line of a table's dump. Anything we can't parse ends the current batch early, and is written
+    /// untouched.
+    fn write<W: Write>(&mut self, writer: &mut W, line: &[u8]) -> io::Result<()> {
+        let Some((prefix, rows)) = parse_insert(line) else {
+            self.flush(writer)?;
+            return writer.write_all(line);
+        };
+
+        if prefix != self.prefix.as_slice() {
+            self.flush(writer)?;
+            self.prefix = prefix.to_vec();
+        }
+
+        for row in rows {
+            self.rows.push(row.to_vec());
+
+            if self.rows.len() == INSERT_BATCH_MAX_ROWS
+                || (self.rows.len() >= INSERT_BATCH_MIN_ROWS && ends_batch(row))
+            {
+                self.flush(writer)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write out the rows batched so far
+    fn flush<W: Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+
+        writer.write_all(&self.prefix)?;
+        writer.write_all(b"\n")?;
+        writer.write_all(&self.rows.join(&b",\n"[..]))?;
+        writer.write_all(b";\n")?;
+
+        self.rows.clear();
+
+        Ok(())
+    }
+}
+
+/// Whether a batch can end after `row`
+fn ends_batch(row: &[u8]) -> bool {
+    Sha224::digest(row)[0] & INSERT_BATCH_MASK == 0
+}
+
+/// Parse a single line extended `INSERT`, returning the `INSERT INTO ... VALUES` prefix and each `(..)` row
+pub fn parse_insert(line: &[u8]) -> Option<(&[u8], Vec<&[u8]>)> {
+    if !line.starts_with(b"INSERT INTO `") {
+        return None;
+    }
+
+    let values_at = find_bytes(line, b" VALUES (")?;
+    let rows = split_rows(&line[values_at + 8..])?;
+
+    Some((&line[..values_at + 7], rows))
+}
+
+/// Split a `(..)` row into its raw values, eg. `1`, `NULL` or `'quoted'`
+pub fn split_values(row: &[u8]) -> Option<Vec<&[u8]>> {
+    let inner = row.strip_prefix(b"(")?.strip_suffix(b")")?;
+
+    let mut values = vec![];
+    let mut start = 0;
+    let mut quote: Option<u8> = None;
+    let mut i = 0;
+
+    while i < inner.len() {
+        let byte = inner[i];
+
+        match quote {
+            Some(q) => {
+                if byte == b'\\' {
+                    i += 1;
+                } else if byte == q {
+                    if inner.get(i + 1) == Some(&q) {
+                        i += 1;
+                    } else {
+                        quote = None;
+                    }
+                }
+            }
+            None => match byte {
+                b'\'' | b'"' => quote = Some(byte),
+                b',' => {
+                    values.push(&inner[start..i]);
+                    start = i + 1;
+                }
+                _ => {}
+            },
+        }
+
+        i += 1;
+    }
+
+    if quote.is_some() {
+        return None;
+    }
+
+    values.push(&inner[start..]);
+
+    Some(values)
+}
+
+/// Split the `(..),(..);` part of an extended INSERT into rows. Returns None unless the statement ends on this line.
+fn split_rows(values: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut rows = vec![];
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut quote: Option<u8> = None;
+    let mut i = 0;
+
+    while i < values.len() {
+        let byte = values[i];
+
+        match quote {
+            Some(q) => {
+                if byte == b'\\' {
+                    i += 1;
+                } else if byte == q {
+                    if values.get(i + 1) == Some(&q) {
+                        i += 1;
+                    } else {
+                        quote = None;
+                    }
+                }
+            }
+            None => match byte {
+                b'\'' | b'"' => quote = Some(byte),
+                b'(' => {
+                    if depth == 0 {
+                        start = i;
+                    }
+                    depth += 1;
+                }
+                b')' => {
+                    depth = depth.checked_sub(1)?;
+                    if depth == 0 {
+                        rows.push(&values[start..=i]);
+                    }
+                }
+                b';' if depth == 0 => {
+                    return values[i + 1..]
+                        .iter()
+                        .all(|b| b.is_ascii_whitespace())
+                        .then_some(rows);
+                }
+                b',' | b' ' if depth == 0 => {}
+                _ if depth == 0 => return None,
+                _ => {}
+            },
+        }
+
+        i += 1;
+    }
+
+    None
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Copy a dump, keeping only the tables matching `filter`. The session header and footer are always kept.
+/// Returns the names of the tables which were left out.
+pub fn filter_tables(
+    input: &Path,
+    output: &Path,
+    filter: &PathFilter,
+) -> anyhow::Result<Vec<String>> {
+    let mut writer = BufWriter::new(File::create(output)?);
+
+    let skipped = filter_tables_dump(File::open(input)?, &mut writer, filter)?;
+
+    writer.flush()?;
+
+    Ok(skipped)
+}
+
+/// Stream a dump from `reader` into `writer`, keeping only the tables matching `filter`, as with `filter_tables`
+pub fn filter_tables_dump<R: Read, W: Write + ?Sized>(
+    reader: R,
+    writer: &mut W,
+    filter: &PathFilter,
+) -> anyhow::Result<Vec<String>> {
+    let mut skipped: Vec<String> = vec![];
+
+    split_dump(reader, |section, line| {
+        match section {
+            Section::Table(name) if !filter.matches_name(name) => {
+                if skipped.last().map(String::as_str) != Some(name) {
+                    skipped.push(name.to_string());
+                }
+            }
+            _ => writer.write_all(line)?,
+        }
+
+        Ok(())
+    })?;
+
+    Ok(skipped)
+}
@@ -1,7 +1,13 @@
 use std::time::SystemTime;
-use std::{borrow::Cow, env, fs, path::PathBuf};
+use std::{
+    borrow::Cow,
+    env, fs,
+    path::{Path, PathBuf},
+};
 
+use bytesize::ByteSize;
 use log::info;
+use rustic_core::RepositoryOptions;
 use self_update::cargo_crate_version;
 use serde::{Deserialize, Serialize};
 
@@ -14,6 +20,34 @@ fn unix_epoch() -> SystemTime {
     SystemTime::UNIX_EPOCH
 }
 
+fn default_stash_max_count() -> Option<usize> {
+    Some(10)
+}
+
+/// Limits applied to the local stash after every stash, and by `sprout stash prune`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StashRetention {
+    /// Maximum number of stashes to keep for each project
+    #[serde(default = "default_stash_max_count")]
+    pub max_count: Option<usize>,
+    /// Drop stashes older than this many days
+    #[serde(default)]
+    pub max_age_days: Option<i64>,
+    /// Drop the oldest stashes until the stash directory is smaller than this, eg. "10 GB"
+    #[serde(default)]
+    pub max_size: Option<ByteSize>,
+}
+
+impl Default for StashRetention {
+    fn default() -> Self {
+        Self {
+            max_count: default_stash_max_count(),
+            max_age_days: None,
+            max_size: None,
+        }
+    }
+}
+
 /// Describes the sprout-config.yaml file, which stores information on how the current user has configured Sprout.
 #[derive(Serialize, Deserialize)]
 pub struct SproutConfig {
@@ -23,6 +57,17 @@ pub struct SproutConfig {
     pub last_update_check: SystemTime,
     #[serde(default)]
     pub update_available: Option<String>,
+    /// Project directories Sprout has written a sprout.yaml for. Used to protect the snapshots they refer to.
+    #[serde(default)]
+    pub known_projects: Vec<PathBuf>,
+    #[serde(default)]
+    pub stash_retention: StashRetention,
+    /// Where repo indexes and snapshots are cached between commands. Defaults to `~/.sprout/cache`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<PathBuf>,
+    /// Never cache anything locally, and fetch everything from the repo every time
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_cache: bool,
 }
 
 /// Represents core Sprout state and helper functions
@@ -40,6 +85,31 @@ impl Engine {
         self.sprout_home.clone().join("stash")
     }
 
+    /// Where repo indexes and snapshots are cached, unless caching is turned off
+    pub fn get_cache_path(&self) -> anyhow::Result<Option<PathBuf>> {
+        let config = self.get_config()?;
+
+        if config.no_cache {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            config
+                .cache_dir
+                .unwrap_or(self.sprout_home.clone().join("cache")),
+        ))
+    }
+
+    /// Options for opening a repo with `password`, using our local cache
+    pub fn repository_options(&self, password: &str) -> anyhow::Result<RepositoryOptions> {
+        let repo_opts = RepositoryOptions::default().password(password);
+
+        Ok(match self.get_cache_path()? {
+            Some(cache_dir) => repo_opts.cache_dir(cache_dir),
+            None => repo_opts.no_cache(true),
+        })
+    }
+
     pub fn ensure_home(&self) -> anyhow::Result<()> {
         let sprout_home = self.get_home();
 
@@ -65,6 +135,10 @@ impl Engine {
                 default_repo: "".to_string(),
                 last_update_check: SystemTime::UNIX_EPOCH, // We haven't ever checked!
                 update_available: None,
+                known_projects: vec![],
+                stash_retention: StashRetention::default(),
+                cache_dir: None,
+                no_cache: false,
             })?;
         }
 
@@ -84,6 +158,29 @@ impl Engine {
         )?)
     }
 
+    /// Remember a project directory, so we can find its sprout.yaml later
+    pub fn register_project(&self, path: &Path) -> anyhow::Result<()> {
+        let path = fs::canonicalize(path)?;
+        let mut config = self.get_config()?;
+
+        if !config.known_projects.contains(&path) {
+            config.known_projects.push(path);
+            self.write_config(&config)?;
+        }
+
+        Ok(())
+    }
+
+    /// All known project directories that still contain a sprout.yaml
+    pub fn get_known_projects(&self) -> anyhow::Result<Vec<PathBuf>> {
+        Ok(self
+            .get_config()?
+            .known_projects
+            .into_iter()
+            .filter(|path| path.join("sprout.yaml").is_file())
+            .collect())
+    }
+
     pub fn should_check_for_updates(&self) -> anyhow::Result<bool> {
         let config = self.get_config()?;
 
@@ -1,18 +1,55 @@
 use anyhow::Result;
 use core::fmt::Debug;
 use dyn_clone::DynClone;
-use std::path::Path;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha224};
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    thread,
+};
+use tempfile::NamedTempFile;
+use thiserror::Error;
 
+use crate::multisite::Site;
+
+pub mod mysql;
 pub mod wordpress;
 /// A trait to act as an interface between Sprout and WordPress. We can implement ProjectFactProvider for WP-CLI, and also for simple test stubbing.
 pub trait ProjectFactProvider: DynClone + Send + Sync {
     fn is_wordpress_installed(&self) -> Result<bool>;
     fn get_home_url(&self) -> Result<String>;
     fn get_uploads_dir(&self) -> Result<String>;
+    /// The `$table_prefix` from `wp-config.php`
+    fn get_table_prefix(&self) -> Result<String>;
+    /// Every site in a multisite network. Single site installs have none.
+    fn get_sites(&self) -> Result<Vec<Site>> {
+        Ok(vec![])
+    }
     fn generate_unique_hash(&self) -> Result<Option<String>>;
-    fn dump_database(&self, output_path: &Path, home_url: &str) -> Result<()>;
+    /// Write a plain SQL dump of the WordPress tables to `output_path`. URL rewriting is handled by Sprout.
+    fn dump_database(&self, output_path: &Path) -> Result<()>;
     fn import_database(&self, import_path: &Path) -> Result<()>;
-    fn postprocess_database(&self, home_url: &str) -> Result<()>;
+    /// Stream a plain SQL dump of the WordPress tables into `writer`. Providers which can't stream dump to a
+    /// temporary file first.
+    fn dump_database_to(&self, writer: &mut dyn Write) -> Result<()> {
+        let file = NamedTempFile::new()?;
+
+        self.dump_database(file.path())?;
+        io::copy(&mut File::open(file.path())?, writer)?;
+
+        Ok(())
+    }
+    /// Import a SQL dump streamed from `reader`. Providers which can't stream import from a temporary file.
+    fn import_database_from(&self, reader: &mut dyn Read) -> Result<()> {
+        let mut file = NamedTempFile::new()?;
+
+        io::copy(reader, &mut file)?;
+
+        self.import_database(file.path())
+    }
 }
 
 dyn_clone::clone_trait_object!(ProjectFactProvider);
@@ -22,3 +59,281 @@ impl Debug for dyn ProjectFactProvider {
         write!(f, "ProjectFact()")
     }
 }
+
+/// Errors raised while a ProjectFactProvider runs external commands, or produces a dump we can't trust
+#[derive(Debug, Error)]
+pub enum FactError {
+    #[error("Could not run `{command}`: {source}")]
+    Spawn {
+        command: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("`{command}` failed with exit code {}", .code.map_or("(killed)".to_string(), |c| c.to_string()))]
+    CommandFailed {
+        command: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+    #[error("The database dump at {0} is empty. Refusing to continue.")]
+    EmptyDump(PathBuf),
+    #[error("The database dump at {0} appears to be truncated. Refusing to continue.")]
+    TruncatedDump(PathBuf),
+    #[error("The database export is empty. Refusing to continue.")]
+    EmptyExport,
+    #[error("The database export appears to be truncated. Refusing to continue.")]
+    TruncatedExport,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl FactError {
+    /// Captured stderr from a failed command, if there was any
+    pub fn stderr(&self) -> Option<&str> {
+        match self {
+            Self::CommandFailed { stderr, .. } if !stderr.is_empty() => Some(stderr),
+            _ => None,
+        }
+    }
+}
+
+/// A short name for a command in errors, eg. `wp db export`
+fn command_name(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(
+            cmd.get_args()
+                .take_while(|arg| !arg.to_string_lossy().starts_with('-'))
+                .take(2),
+        )
+        .map(|part| part.to_string_lossy().to_string())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Run a command to completion, capturing stderr. A non-zero exit status is returned as FactError::CommandFailed.
+pub fn run_command(cmd: &mut Command) -> Result<Output, FactError> {
+    let command = command_name(cmd);
+
+    let output = cmd
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|source| FactError::Spawn {
+            command: command.clone(),
+            source,
+        })?;
+
+    if !output.status.success() {
+        return Err(FactError::CommandFailed {
+            command,
+            code: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    Ok(output)
+}
+
+/// Run a command, streaming its stdout into `writer`. Errors are reported as with `run_command`.
+pub fn run_command_to(cmd: &mut Command, writer: &mut dyn Write) -> Result<(), FactError> {
+    run_streaming(cmd.stdout(Stdio::piped()), |child| {
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        io::copy(&mut stdout, writer).map(|_| ())
+    })
+}
+
+/// Run a command, streaming `reader` into its stdin. Errors are reported as with `run_command`.
+pub fn run_command_from(cmd: &mut Command, reader: &mut dyn Read) -> Result<(), FactError> {
+    run_streaming(cmd.stdin(Stdio::piped()), |child| {
+        // Dropped once copied, so the command sees the end of its input
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        io::copy(reader, &mut stdin).map(|_| ())
+    })
+}
+
+fn run_streaming(
+    cmd: &mut Command,
+    stream: impl FnOnce(&mut Child) -> io::Result<()>,
+) -> Result<(), FactError> {
+    let command = command_name(cmd);
+
+    let mut child = cmd
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|source| FactError::Spawn {
+            command: command.clone(),
+            source,
+        })?;
+
+    // Drain stderr alongside the stream, so a chatty command can't block on it
+    let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+    let stderr = thread::spawn(move || {
+        let mut stderr = vec![];
+        let _ = stderr_pipe.read_to_end(&mut stderr);
+        String::from_utf8_lossy(&stderr).trim().to_string()
+    });
+
+    let streamed = stream(&mut child);
+
+    if streamed.is_err() {
+        let _ = child.kill();
+    }
+
+    let status = child.wait()?;
+    let stderr = stderr.join().unwrap_or_default();
+
+    let failed = FactError::CommandFailed {
+        command,
+        code: status.code(),
+        stderr,
+    };
+
+    match streamed {
+        // If the command gave up by itself, its error is more use than our broken pipe
+        Err(_) if status.code().is_some_and(|code| code != 0) => Err(failed),
+        Err(e) => Err(e.into()),
+        Ok(()) if !status.success() => Err(failed),
+        Ok(()) => Ok(()),
+    }
+}
+
+/// How a dump ends, judging by its last 64KB
+enum DumpEnd {
+    Complete,
+    Empty,
+    Truncated,
+}
+
+const DUMP_TAIL_LEN: usize = 64 * 1024;
+
+/// Check the `tail` of a dump ends on a complete statement. `whole` is set when the tail is the entire dump.
+fn dump_end(tail: &[u8], whole: bool) -> DumpEnd {
+    let tail = String::from_utf8_lossy(tail);
+
+    let last_statement = tail
+        .lines()
+        .map(|line| line.trim())
+        .rfind(|line| !line.is_empty() && !line.starts_with("--") && !line.starts_with('#'));
+
+    match last_statement {
+        Some(line) if line.ends_with(';') => DumpEnd::Complete,
+        None if whole => DumpEnd::Empty,
+        _ => DumpEnd::Truncated,
+    }
+}
+
+/// Sanity check a freshly dumped database. Rejects empty dumps, and dumps that don't end on a complete statement.
+pub fn verify_dump(path: &Path) -> Result<(), FactError> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    if len == 0 {
+        return Err(FactError::EmptyDump(path.to_owned()));
+    }
+
+    let tail_len = len.min(DUMP_TAIL_LEN as u64);
+    let mut tail = vec![0; tail_len as usize];
+
+    file.seek(SeekFrom::Start(len - tail_len))?;
+    file.read_exact(&mut tail)?;
+
+    match dump_end(&tail, tail_len == len) {
+        DumpEnd::Complete => Ok(()),
+        DumpEnd::Empty => Err(FactError::EmptyDump(path.to_owned())),
+        DumpEnd::Truncated => Err(FactError::TruncatedDump(path.to_owned())),
+    }
+}
+
+/// Copy a database dump as it's streamed from `reader` into `writer`, then make the same checks as `verify_dump`
+pub fn verify_dump_stream(reader: &mut dyn Read, writer: &mut dyn Write) -> Result<(), FactError> {
+    let mut buffer = vec![0; DUMP_TAIL_LEN];
+    let mut tail: Vec<u8> = Vec::with_capacity(DUMP_TAIL_LEN * 2);
+    let mut len = 0;
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        writer.write_all(&buffer[..read])?;
+        len += read;
+
+        tail.extend_from_slice(&buffer[..read]);
+
+        if tail.len() > DUMP_TAIL_LEN {
+            tail.drain(..tail.len() - DUMP_TAIL_LEN);
+        }
+    }
+
+    if len == 0 {
+        return Err(FactError::EmptyExport);
+    }
+
+    match dump_end(&tail, tail.len() == len) {
+        DumpEnd::Complete => Ok(()),
+        DumpEnd::Empty => Err(FactError::EmptyExport),
+        DumpEnd::Truncated => Err(FactError::TruncatedExport),
+    }
+}
+
+/// Selects which ProjectFactProvider a project uses. Set via `facts` in sprout.yaml.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum FactProviderKind {
+    /// Use WP-CLI (requires PHP and `wp` on the PATH)
+    #[default]
+    #[serde(rename = "wp-cli")]
+    WpCli,
+    /// Talk to MySQL / MariaDB directly using credentials from wp-config.php or .env
+    #[serde(rename = "mysql")]
+    MySql,
+}
+
+impl FactProviderKind {
+    pub fn provider(&self, path: &Path) -> Box<dyn ProjectFactProvider> {
+        match self {
+            Self::WpCli => Box::new(wordpress::WordPress {
+                path: path.to_owned(),
+            }),
+            Self::MySql => Box::new(mysql::MySql {
+                path: path.to_owned(),
+            }),
+        }
+    }
+}
+
+/// Generates a project identity hash from the first commit in the project's git history
+pub fn git_unique_hash(path: &Path) -> Result<Option<String>> {
+    let mut cmd = Command::new("git");
+
+    cmd.current_dir(path)
+        .arg("rev-list")
+        .arg("--parents")
+        .arg("HEAD")
+        .stderr(Stdio::null())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped());
+
+    let child = cmd.spawn()?;
+
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let output = String::from_utf8_lossy(&output.stdout);
+
+    let first_sha = output
+        .to_string()
+        .trim()
+        .split('\n')
+        .next_back()
+        .unwrap()
+        .to_string();
+
+    let hash = Sha224::digest(first_sha);
+
+    Ok(Some(format!("{:x}", hash)))
+}
@@ -0,0 +1,408 @@
+use super::{run_command, run_command_from, run_command_to, ProjectFactProvider};
+use crate::multisite::Site;
+use anyhow::Result;
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tempfile::NamedTempFile;
+
+/// Database connection details, as read from `wp-config.php` or a Bedrock `.env` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbCredentials {
+    pub name: String,
+    pub user: String,
+    pub password: String,
+    pub host: String,
+    pub port: Option<u16>,
+    pub socket: Option<String>,
+    pub table_prefix: String,
+}
+
+impl DbCredentials {
+    /// Split a WordPress `DB_HOST` value into host, port and socket parts.
+    /// WordPress accepts `host`, `host:port` and `host:/path/to/socket`.
+    fn from_parts(
+        name: String,
+        user: String,
+        password: String,
+        db_host: &str,
+        table_prefix: String,
+    ) -> Self {
+        let (host, port, socket) = match db_host.split_once(':') {
+            None => (db_host.to_string(), None, None),
+            Some((host, rest)) => match rest.parse::<u16>() {
+                Ok(port) => (host.to_string(), Some(port), None),
+                Err(_) => (host.to_string(), None, Some(rest.to_string())),
+            },
+        };
+
+        Self {
+            name,
+            user,
+            password,
+            host: if host.is_empty() {
+                "localhost".to_string()
+            } else {
+                host
+            },
+            port,
+            socket,
+            table_prefix,
+        }
+    }
+
+    /// Render a MySQL option file, so credentials never appear in the process list.
+    fn to_option_file(&self) -> String {
+        let mut opts = format!(
+            "[client]\nuser=\"{}\"\npassword=\"{}\"\nhost=\"{}\"\n",
+            escape_option(&self.user),
+            escape_option(&self.password),
+            escape_option(&self.host)
+        );
+
+        if let Some(port) = self.port {
+            opts.push_str(&format!("port={}\n", port));
+        }
+
+        if let Some(socket) = &self.socket {
+            opts.push_str(&format!("socket=\"{}\"\n", escape_option(socket)));
+        }
+
+        opts
+    }
+}
+
+fn escape_option(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Quote a string literal for use in a MySQL statement
+fn sql_string(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "''"))
+}
+
+/// Quote an identifier for use in a MySQL statement
+fn sql_ident(value: &str) -> String {
+    format!("`{}`", value.replace('`', "``"))
+}
+
+/// Talks to MySQL / MariaDB directly via the `mysql` and `mysqldump` clients, without PHP or WP-CLI.
+/// Credentials are read from `wp-config.php`, or from `.env` on Bedrock projects. This struct implements the ProjectFactProvider trait.
+#[derive(Clone)]
+pub struct MySql {
+    pub path: PathBuf,
+}
+
+impl MySql {
+    fn is_bedrock(&self) -> bool {
+        self.path.join(".env").is_file() && self.path.join("web/app").is_dir()
+    }
+
+    /// Read the database credentials for this project.
+    pub fn credentials(&self) -> Result<DbCredentials> {
+        for config in [
+            self.path.join("wp-config.php"),
+            self.path.join("../wp-config.php"),
+        ] {
+            if let Ok(contents) = fs::read_to_string(&config) {
+                if let Some(creds) = Self::parse_wp_config(&contents) {
+                    return Ok(creds);
+                }
+            }
+        }
+
+        if let Ok(contents) = fs::read_to_string(self.path.join(".env")) {
+            if let Some(creds) = Self::parse_dotenv(&contents) {
+                return Ok(creds);
+            }
+        }
+
+        Err(anyhow::anyhow!(
+            "Could not read database credentials from wp-config.php or .env in {}",
+            self.path.display()
+        ))
+    }
+
+    pub fn parse_wp_config(contents: &str) -> Option<DbCredentials> {
+        let define = Regex::new(
+            r#"define\s*\(\s*['"](DB_NAME|DB_USER|DB_PASSWORD|DB_HOST)['"]\s*,\s*(?:'((?:[^'\\]|\\.)*)'|"((?:[^"\\]|\\.)*)")\s*\)"#,
+        )
+        .unwrap();
+
+        let prefix = Regex::new(r#"\$table_prefix\s*=\s*['"]([^'"]*)['"]"#).unwrap();
+
+        let mut values: HashMap<String, String> = HashMap::new();
+
+        for caps in define.captures_iter(contents) {
+            let value = caps.get(2).or(caps.get(3)).map_or("", |m| m.as_str());
+            values.insert(
+                caps[1].to_string(),
+                value.replace("\\'", "'").replace("\\\"", "\""),
+            );
+        }
+
+        let table_prefix = prefix
+            .captures(contents)
+            .map_or("wp_".to_string(), |caps| caps[1].to_string());
+
+        Some(DbCredentials::from_parts(
+            values.remove("DB_NAME")?,
+            values.remove("DB_USER").unwrap_or_default(),
+            values.remove("DB_PASSWORD").unwrap_or_default(),
+            &values.remove("DB_HOST").unwrap_or("localhost".to_string()),
+            table_prefix,
+        ))
+    }
+
+    pub fn parse_dotenv(contents: &str) -> Option<DbCredentials> {
+        let mut values = Self::dotenv_values(contents);
+
+        Some(DbCredentials::from_parts(
+            values.remove("DB_NAME")?,
+            values.remove("DB_USER").unwrap_or_default(),
+            values.remove("DB_PASSWORD").unwrap_or_default(),
+            &values.remove("DB_HOST").unwrap_or("localhost".to_string()),
+            values.remove("DB_PREFIX").unwrap_or("wp_".to_string()),
+        ))
+    }
+
+    fn dotenv_values(contents: &str) -> HashMap<String, String> {
+        contents
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| line.strip_prefix("export ").unwrap_or(line).split_once('='))
+            .map(|(key, value)| {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .or(value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                    .unwrap_or(value);
+
+                (key.trim().to_string(), value.to_string())
+            })
+            .collect()
+    }
+
+    fn option_file(&self, creds: &DbCredentials) -> Result<NamedTempFile> {
+        let mut file = NamedTempFile::new()?;
+        file.write_all(creds.to_option_file().as_bytes())?;
+        file.flush()?;
+        Ok(file)
+    }
+
+    fn client(&self, program: &str, option_file: &NamedTempFile) -> Command {
+        let mut cmd = Command::new(program);
+
+        cmd.current_dir(&self.path).arg(format!(
+            "--defaults-extra-file={}",
+            option_file.path().to_string_lossy()
+        ));
+
+        cmd
+    }
+
+    /// `mysqldump` for the WordPress tables, to stdout
+    fn dump_command(&self, creds: &DbCredentials, option_file: &NamedTempFile) -> Result<Command> {
+        let tables = self.get_tables(creds)?;
+        let mut cmd = self.client("mysqldump", option_file);
+
+        cmd.arg("--single-transaction")
+            .arg("--quick")
+            .arg("--skip-lock-tables")
+            .arg("--no-tablespaces")
+            .arg("--skip-comments")
+            .arg("--add-drop-table")
+            .arg("--default-character-set=utf8mb4")
+            .arg(&creds.name)
+            .args(&tables)
+            .stdin(Stdio::null());
+
+        Ok(cmd)
+    }
+
+    /// `mysql`, importing from stdin
+    fn import_command(&self, creds: &DbCredentials, option_file: &NamedTempFile) -> Command {
+        let mut cmd = self.client("mysql", option_file);
+
+        cmd.arg("--default-character-set=utf8mb4")
+            .arg(&creds.name)
+            .stdout(Stdio::null());
+
+        cmd
+    }
+
+    /// Run a single statement and return the raw, tab separated output
+    fn query(&self, creds: &DbCredentials, sql: &str) -> Result<String> {
+        let option_file = self.option_file(creds)?;
+        let mut cmd = self.client("mysql", &option_file);
+
+        cmd.arg("--batch")
+            .arg("--skip-column-names")
+            .arg("--raw")
+            .arg("-e")
+            .arg(sql)
+            .arg(&creds.name)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped());
+
+        let output = run_command(&mut cmd)?;
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    fn get_option(&self, creds: &DbCredentials, option: &str) -> Result<String> {
+        Ok(self
+            .query(
+                creds,
+                &format!(
+                    "SELECT option_value FROM {} WHERE option_name = {} LIMIT 1",
+                    sql_ident(&format!("{}options", creds.table_prefix)),
+                    sql_string(option)
+                ),
+            )?
+            .trim()
+            .to_string())
+    }
+
+    fn get_tables(&self, creds: &DbCredentials) -> Result<Vec<String>> {
+        let like = creds
+            .table_prefix
+            .replace('\\', "\\\\")
+            .replace('_', "\\_")
+            .replace('%', "\\%");
+
+        Ok(self
+            .query(
+                creds,
+                &format!("SHOW TABLES LIKE {}", sql_string(&format!("{}%", like))),
+            )?
+            .lines()
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect())
+    }
+}
+
+impl ProjectFactProvider for MySql {
+    fn is_wordpress_installed(&self) -> Result<bool> {
+        let creds = match self.credentials() {
+            Ok(creds) => creds,
+            Err(_) => return Ok(false),
+        };
+
+        Ok(self.get_option(&creds, "siteurl").is_ok())
+    }
+
+    fn get_home_url(&self) -> Result<String> {
+        let creds = self.credentials()?;
+
+        match self.get_option(&creds, "home")? {
+            home if !home.is_empty() => Ok(home),
+            _ => Err(anyhow::anyhow!(
+                "Could not determine WordPress home URL from database {}",
+                creds.name
+            )),
+        }
+    }
+
+    fn get_uploads_dir(&self) -> Result<String> {
+        let creds = self.credentials()?;
+        let root = fs::canonicalize(&self.path)?;
+
+        let upload_path = self.get_option(&creds, "upload_path").unwrap_or_default();
+
+        if !upload_path.is_empty() {
+            return Ok(root.join(upload_path).to_string_lossy().to_string());
+        }
+
+        Ok(if self.is_bedrock() {
+            root.join("web/app/uploads")
+        } else {
+            root.join("wp-content/uploads")
+        }
+        .to_string_lossy()
+        .to_string())
+    }
+
+    fn get_table_prefix(&self) -> Result<String> {
+        Ok(self.credentials()?.table_prefix)
+    }
+
+    fn get_sites(&self) -> Result<Vec<Site>> {
+        let creds = self.credentials()?;
+        let blogs = format!("{}blogs", creds.table_prefix);
+
+        if !self.get_tables(&creds)?.contains(&blogs) {
+            return Ok(vec![]);
+        }
+
+        Ok(self
+            .query(
+                &creds,
+                &format!(
+                    "SELECT blog_id, domain, path FROM {} ORDER BY blog_id",
+                    sql_ident(&blogs)
+                ),
+            )?
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.trim().splitn(3, '\t');
+
+                Some(Site {
+                    id: fields.next()?.parse().ok()?,
+                    domain: fields.next()?.to_string(),
+                    path: fields.next()?.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    fn generate_unique_hash(&self) -> Result<Option<String>> {
+        super::git_unique_hash(&self.path)
+    }
+
+    fn dump_database(&self, output_path: &Path) -> Result<()> {
+        let creds = self.credentials()?;
+        let option_file = self.option_file(&creds)?;
+        let mut cmd = self.dump_command(&creds, &option_file)?;
+
+        run_command(cmd.stdout(Stdio::from(File::create(output_path)?)))?;
+
+        Ok(())
+    }
+
+    fn import_database(&self, import_path: &Path) -> Result<()> {
+        let creds = self.credentials()?;
+        let option_file = self.option_file(&creds)?;
+        let mut cmd = self.import_command(&creds, &option_file);
+
+        run_command(cmd.stdin(Stdio::from(File::open(import_path)?)))?;
+
+        Ok(())
+    }
+
+    fn dump_database_to(&self, writer: &mut dyn Write) -> Result<()> {
+        let creds = self.credentials()?;
+        let option_file = self.option_file(&creds)?;
+
+        run_command_to(&mut self.dump_command(&creds, &option_file)?, writer)?;
+
+        Ok(())
+    }
+
+    fn import_database_from(&self, reader: &mut dyn Read) -> Result<()> {
+        let creds = self.credentials()?;
+        let option_file = self.option_file(&creds)?;
+
+        run_command_from(&mut self.import_command(&creds, &option_file), reader)?;
+
+        Ok(())
+    }
+}
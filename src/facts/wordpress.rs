@@ -1,7 +1,9 @@
-use super::ProjectFactProvider;
+use super::{run_command, run_command_from, run_command_to, ProjectFactProvider};
+use crate::multisite::Site;
 use anyhow::Result;
-use sha2::{Digest, Sha224};
 use std::{
+    ffi::OsStr,
+    io::{Read, Write},
     path::{Path, PathBuf},
     process::{Command, Stdio},
 };
@@ -20,19 +22,56 @@ impl WordPress {
             .arg("config")
             .arg("get")
             .arg("WP_CONTENT_DIR")
-            .stderr(Stdio::null())
             .stdin(Stdio::null())
             .stdout(Stdio::piped());
 
-        let child = cmd.spawn()?;
-
-        let output = child.wait_with_output()?;
+        let output = run_command(&mut cmd)?;
 
         Ok(String::from_utf8_lossy(&output.stdout)
             .to_string()
             .trim()
             .to_string())
     }
+
+    /// `wp db export` for the WordPress tables, into `file`. `-` exports to stdout.
+    fn export_command(&self, file: &OsStr) -> Result<Command> {
+        let mut cmd = Command::new("wp");
+
+        cmd.current_dir(&self.path)
+            .arg("db")
+            .arg("tables")
+            .arg("--format=csv")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped());
+
+        let output = run_command(&mut cmd)?;
+
+        let tables = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+        let mut cmd = Command::new("wp");
+
+        cmd.current_dir(&self.path)
+            .arg("db")
+            .arg("export")
+            .arg(file)
+            .arg(format!("--tables={}", tables))
+            .stdin(Stdio::null());
+
+        Ok(cmd)
+    }
+
+    /// `wp db import` from `file`. `-` imports from stdin.
+    fn import_command(&self, file: &OsStr) -> Command {
+        let mut cmd = Command::new("wp");
+
+        cmd.current_dir(&self.path)
+            .arg("db")
+            .arg("import")
+            .arg(file)
+            .stdout(Stdio::null());
+
+        cmd
+    }
 }
 
 impl ProjectFactProvider for WordPress {
@@ -60,19 +99,10 @@ impl ProjectFactProvider for WordPress {
             .arg("option")
             .arg("get")
             .arg("home")
-            .stderr(Stdio::null())
             .stdin(Stdio::null())
             .stdout(Stdio::piped());
 
-        let child = cmd.spawn()?;
-
-        let output = child.wait_with_output()?;
-
-        if !output.status.success() {
-            return Err(anyhow::anyhow!(
-                "Could not determine WordPress home URL via WP-CLI"
-            ));
-        }
+        let output = run_command(&mut cmd)?;
 
         Ok(String::from_utf8_lossy(&output.stdout)
             .to_string()
@@ -87,13 +117,10 @@ impl ProjectFactProvider for WordPress {
             .arg("option")
             .arg("get")
             .arg("upload_path")
-            .stderr(Stdio::null())
             .stdin(Stdio::null())
             .stdout(Stdio::piped());
 
-        let child = cmd.spawn()?;
-
-        let output = child.wait_with_output()?;
+        let output = run_command(&mut cmd)?;
 
         let upload_path = String::from_utf8_lossy(&output.stdout)
             .to_string()
@@ -107,87 +134,93 @@ impl ProjectFactProvider for WordPress {
         Ok(upload_path)
     }
 
-    fn generate_unique_hash(&self) -> Result<Option<String>> {
-        let mut cmd = Command::new("git");
+    fn get_table_prefix(&self) -> Result<String> {
+        let mut cmd = Command::new("wp");
 
         cmd.current_dir(&self.path)
-            .arg("rev-list")
-            .arg("--parents")
-            .arg("HEAD")
-            .stderr(Stdio::null())
+            .arg("db")
+            .arg("prefix")
             .stdin(Stdio::null())
             .stdout(Stdio::piped());
 
-        let child = cmd.spawn()?;
-
-        let output = child.wait_with_output()?;
-
-        if !output.status.success() {
-            return Ok(None);
-        }
-
-        let output = String::from_utf8_lossy(&output.stdout);
+        let output = run_command(&mut cmd)?;
 
-        let first_sha = output
+        Ok(String::from_utf8_lossy(&output.stdout)
             .to_string()
             .trim()
-            .split('\n')
-            .last()
-            .unwrap()
-            .to_string();
-
-        let hash = Sha224::digest(first_sha);
-
-        Ok(Some(format!("{:x}", hash)))
+            .to_string())
     }
 
-    fn dump_database(&self, output_path: &Path, home_url: &str) -> Result<()> {
+    fn get_sites(&self) -> Result<Vec<Site>> {
         let mut cmd = Command::new("wp");
 
         cmd.current_dir(&self.path)
-            .arg("search-replace")
-            .arg(home_url)
-            .arg("__SPROUT__HOME__")
-            .arg(format!("--export={}", output_path.to_string_lossy()))
+            .arg("core")
+            .arg("is-installed")
+            .arg("--network")
             .stderr(Stdio::null())
             .stdin(Stdio::null())
             .stdout(Stdio::null());
 
-        let mut child = cmd.spawn()?;
-        child.wait()?;
+        if !cmd.status()?.success() {
+            return Ok(vec![]);
+        }
 
-        Ok(())
-    }
-    fn import_database(&self, import_path: &Path) -> Result<()> {
         let mut cmd = Command::new("wp");
 
         cmd.current_dir(&self.path)
-            .arg("db")
-            .arg("import")
-            .arg(import_path.as_os_str())
-            .stderr(Stdio::null())
+            .arg("site")
+            .arg("list")
+            .arg("--fields=blog_id,domain,path")
+            .arg("--format=csv")
             .stdin(Stdio::null())
-            .stdout(Stdio::null());
+            .stdout(Stdio::piped());
 
-        let mut child = cmd.spawn()?;
-        child.wait()?;
+        let output = run_command(&mut cmd)?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .skip(1)
+            .filter_map(|line| {
+                let mut fields = line.trim().splitn(3, ',');
+
+                Some(Site {
+                    id: fields.next()?.parse().ok()?,
+                    domain: fields.next()?.to_string(),
+                    path: fields.next()?.to_string(),
+                })
+            })
+            .collect())
+    }
+
+    fn generate_unique_hash(&self) -> Result<Option<String>> {
+        super::git_unique_hash(&self.path)
+    }
+
+    fn dump_database(&self, output_path: &Path) -> Result<()> {
+        let mut cmd = self.export_command(output_path.as_os_str())?;
+
+        run_command(cmd.stdout(Stdio::null()))?;
 
         Ok(())
     }
 
-    fn postprocess_database(&self, home_url: &str) -> Result<()> {
-        let mut cmd = Command::new("wp");
+    fn import_database(&self, import_path: &Path) -> Result<()> {
+        let mut cmd = self.import_command(import_path.as_os_str());
 
-        cmd.current_dir(&self.path)
-            .arg("search-replace")
-            .arg("__SPROUT__HOME__")
-            .arg(home_url)
-            .stderr(Stdio::null())
-            .stdin(Stdio::null())
-            .stdout(Stdio::null());
+        run_command(cmd.stdin(Stdio::null()))?;
 
-        let mut child = cmd.spawn()?;
-        child.wait()?;
+        Ok(())
+    }
+
+    fn dump_database_to(&self, writer: &mut dyn Write) -> Result<()> {
+        run_command_to(&mut self.export_command(OsStr::new("-"))?, writer)?;
+
+        Ok(())
+    }
+
+    fn import_database_from(&self, reader: &mut dyn Read) -> Result<()> {
+        run_command_from(&mut self.import_command(OsStr::new("-")), reader)?;
 
         Ok(())
     }
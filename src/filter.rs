@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+
+use bytesize::ByteSize;
+use capturing_glob::{glob, Pattern};
+use rustic_core::LocalSourceFilterOptions;
+use serde::{Deserialize, Serialize};
+
+use crate::snapshot::{decode_tag_value, encode_tag_value};
+
+/// Include / exclude glob filters for paths relative to the uploads directory, or for table names.
+/// An empty include list matches everything. Excludes always win over includes.
+#[derive(Debug, Clone, Default)]
+pub struct PathFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl PathFilter {
+    pub fn new<S: AsRef<str>>(include: &[S], exclude: &[S]) -> anyhow::Result<Self> {
+        Ok(Self {
+            include: Self::compile(include)?,
+            exclude: Self::compile(exclude)?,
+        })
+    }
+
+    fn compile<S: AsRef<str>>(patterns: &[S]) -> anyhow::Result<Vec<Pattern>> {
+        patterns
+            .iter()
+            .map(|pattern| {
+                let pattern = pattern.as_ref().trim_start_matches("./");
+                Pattern::new(pattern)
+                    .map_err(|e| anyhow::anyhow!("Invalid glob pattern '{}': {}", pattern, e))
+            })
+            .collect()
+    }
+
+    /// True when no filters are set, and every path is in scope
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Is this relative path in scope?
+    pub fn matches(&self, path: &Path) -> bool {
+        self.matches_name(&path.to_string_lossy())
+    }
+
+    /// Is this name (eg. a database table) in scope?
+    pub fn matches_name(&self, name: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(name)))
+            && !self.exclude.iter().any(|p| p.matches(name))
+    }
+}
+
+/// Database tables to restore when seeding, configured under `tables` in `sprout.yaml`. Globs are allowed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TableSelection {
+    /// Only restore these tables. Empty means every table
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Never restore these tables, leaving the local copy untouched
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+impl TableSelection {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn to_filter(&self) -> anyhow::Result<PathFilter> {
+        PathFilter::new(&self.include, &self.exclude)
+    }
+}
+
+/// Per-directory ignore files, using `.gitignore` syntax, honoured when snapshotting uploads
+pub const IGNORE_FILE: &str = ".sproutignore";
+
+/// Uploads to leave out of snapshots, configured under `uploads_rules` in `sprout.yaml`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct UploadsRules {
+    /// `.gitignore` style globs. Globs containing a `/` are anchored to the uploads directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Skip any file larger than this
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<ByteSize>,
+}
+
+impl UploadsRules {
+    pub fn is_empty(&self) -> bool {
+        self.exclude.is_empty() && self.max_file_size.is_none()
+    }
+
+    /// Map these rules onto rustic's backup filter options, for a backup of `uploads_dir`
+    pub fn to_filter_opts(&self, uploads_dir: &Path) -> LocalSourceFilterOptions {
+        let globs: Vec<String> = self
+            .exclude
+            .iter()
+            .map(|pattern| match pattern.trim_start_matches('/') {
+                anchored if anchored.contains('/') || pattern.starts_with('/') => format!(
+                    "!{}/{}",
+                    uploads_dir.to_string_lossy().trim_end_matches('/'),
+                    anchored
+                ),
+                _ => format!("!{}", pattern),
+            })
+            .collect();
+
+        LocalSourceFilterOptions::default()
+            .globs(globs)
+            .custom_ignorefiles(vec![IGNORE_FILE.to_string()])
+            .exclude_larger_than(self.max_file_size)
+    }
+}
+
+/// The rules in effect when a snapshot was taken, stored in its `sprt_rules` tag
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AppliedRules {
+    #[serde(flatten)]
+    pub rules: UploadsRules,
+    /// Ignore files found in the uploads directory, relative to it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignore_files: Vec<PathBuf>,
+}
+
+impl AppliedRules {
+    /// Capture the rules for a snapshot of `uploads_dir`
+    pub fn collect(rules: &UploadsRules, uploads_dir: &Path) -> Self {
+        let mut ignore_files: Vec<PathBuf> = glob(&format!(
+            "{}/**/{}",
+            uploads_dir.to_string_lossy(),
+            IGNORE_FILE
+        ))
+        .expect("Failed to read glob pattern")
+        .flatten()
+        .filter_map(|e| {
+            e.path()
+                .strip_prefix(uploads_dir)
+                .ok()
+                .map(Path::to_path_buf)
+        })
+        .collect();
+
+        ignore_files.sort();
+
+        Self {
+            rules: rules.clone(),
+            ignore_files,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty() && self.ignore_files.is_empty()
+    }
+
+    pub fn encode(&self) -> anyhow::Result<String> {
+        encode_tag_value(self)
+    }
+
+    pub fn decode(value: &str) -> anyhow::Result<Self> {
+        decode_tag_value(value)
+    }
+}
+
+impl std::fmt::Display for AppliedRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+
+        if !self.rules.exclude.is_empty() {
+            parts.push(format!("exclude {}", self.rules.exclude.join(" ")));
+        }
+
+        if let Some(max) = self.rules.max_file_size {
+            parts.push(format!("max file size {}", max));
+        }
+
+        if !self.ignore_files.is_empty() {
+            parts.push(format!(
+                "{} {}",
+                IGNORE_FILE,
+                self.ignore_files
+                    .iter()
+                    .map(|p| p.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ));
+        }
+
+        write!(f, "{}", parts.join("; "))
+    }
+}
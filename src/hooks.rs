@@ -0,0 +1,190 @@
+use std::{fmt::Display, path::Path, process::Command};
+
+use log::info;
+use rustic_core::Id;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// A point in Sprout's lifecycle where hook commands can run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hook {
+    PreSnap,
+    PostSnap,
+    PreSeed,
+    PostSeed,
+    PreStash,
+    PostUnstash,
+}
+
+impl Display for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Hook::PreSnap => "pre_snap",
+                Hook::PostSnap => "post_snap",
+                Hook::PreSeed => "pre_seed",
+                Hook::PostSeed => "post_seed",
+                Hook::PreStash => "pre_stash",
+                Hook::PostUnstash => "post_unstash",
+            }
+        )
+    }
+}
+
+/// Whether the operation a hook follows succeeded, passed to hook commands as `SPROUT_HOOK_STATUS`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HookStatus {
+    Success,
+    Failure,
+}
+
+impl Display for HookStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                HookStatus::Success => "success",
+                HookStatus::Failure => "failure",
+            }
+        )
+    }
+}
+
+/// Context passed to hook commands as `SPROUT_*` environment variables
+#[derive(Debug, Clone)]
+pub struct HookContext<'a> {
+    pub project_name: &'a str,
+    pub project_path: &'a Path,
+    pub branch: &'a str,
+    pub home_url: &'a str,
+    pub snapshot: Option<Id>,
+    pub status: HookStatus,
+}
+
+/// Shell commands to run around snaps, seeds and stashes, configured under `hooks` in `sprout.yaml`.
+/// Each hook takes a single command or a list, run in order from the project directory.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Hooks {
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub pre_snap: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub post_snap: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub pre_seed: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub post_seed: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub pre_stash: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub post_unstash: Vec<String>,
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(command) => vec![command],
+        OneOrMany::Many(commands) => commands,
+    })
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn commands(&self, hook: Hook) -> &[String] {
+        match hook {
+            Hook::PreSnap => &self.pre_snap,
+            Hook::PostSnap => &self.post_snap,
+            Hook::PreSeed => &self.pre_seed,
+            Hook::PostSeed => &self.post_seed,
+            Hook::PreStash => &self.pre_stash,
+            Hook::PostUnstash => &self.post_unstash,
+        }
+    }
+
+    /// Run each command for `hook`, stopping at the first which exits non-zero
+    pub fn run(&self, hook: Hook, context: &HookContext) -> anyhow::Result<()> {
+        for command in self.commands(hook) {
+            info!("Running {} hook: {}", hook, command);
+
+            let mut cmd = if cfg!(windows) {
+                let mut cmd = Command::new("cmd");
+                cmd.arg("/C");
+                cmd
+            } else {
+                let mut cmd = Command::new("sh");
+                cmd.arg("-c");
+                cmd
+            };
+
+            let status = cmd
+                .arg(command)
+                .current_dir(context.project_path)
+                .env("SPROUT_HOOK", hook.to_string())
+                .env("SPROUT_HOOK_STATUS", context.status.to_string())
+                .env("SPROUT_PROJECT", context.project_name)
+                .env("SPROUT_PROJECT_PATH", context.project_path)
+                .env("SPROUT_BRANCH", context.branch)
+                .env("SPROUT_HOME_URL", context.home_url)
+                .env(
+                    "SPROUT_SNAPSHOT_ID",
+                    context
+                        .snapshot
+                        .map(|id| id.to_hex().to_string())
+                        .unwrap_or_default(),
+                )
+                // Keep stdout clean for Sprout's JSON output
+                .stdout(std::io::stderr())
+                .status()
+                .map_err(|e| {
+                    anyhow::anyhow!("Could not run the {} hook `{}`: {}", hook, command, e)
+                })?;
+
+            if !status.success() {
+                return Err(anyhow::anyhow!(
+                    "The {} hook `{}` failed with exit code {}",
+                    hook,
+                    command,
+                    status
+                        .code()
+                        .map_or("(killed)".to_string(), |c| c.to_string())
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,11 +1,25 @@
+pub mod branch;
 pub mod cli;
+pub mod diff;
+pub mod dump;
 pub mod engine;
 pub mod facts;
+pub mod filter;
+pub mod hooks;
+pub mod multisite;
+pub mod placeholder;
+pub mod prefix;
 pub mod progress;
 pub mod project;
 pub mod repo;
+pub mod report;
+pub mod retention;
+pub mod sanitise;
+pub mod search_replace;
 pub mod snapshot;
+pub mod staging;
 pub mod stash;
+pub mod stream;
 pub mod theme;
 
 include!(concat!(env!("OUT_DIR"), "/built.rs"));
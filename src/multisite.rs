@@ -0,0 +1,255 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dump::{parse_insert, split_dump, split_values, Section},
+    snapshot::{decode_tag_value, encode_tag_value},
+};
+
+/// How a domain can be anchored in a URL - plain, JSON escaped and URL encoded
+const URL_ANCHORS: [&str; 3] = ["//", "\\/\\/", "%2F%2F"];
+
+/// A site in a multisite network, from `wp_blogs`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Site {
+    pub id: u64,
+    pub domain: String,
+    pub path: String,
+}
+
+/// How network domains are mapped onto local domains when seeding, configured under `multisite` in `sprout.yaml`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MultisiteConfig {
+    /// Local domain for each site. `{subdomain}` is the site's subdomain of the main site (or its whole domain,
+    /// with dots replaced by dashes, for mapped domains). `{home}` is the local home URL's host.
+    #[serde(default = "MultisiteConfig::default_domain_template")]
+    pub domain_template: String,
+    /// Explicit local domains, by source domain. These win over the template.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub domains: BTreeMap<String, String>,
+}
+
+impl MultisiteConfig {
+    fn default_domain_template() -> String {
+        "{subdomain}.{home}".to_string()
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for MultisiteConfig {
+    fn default() -> Self {
+        Self {
+            domain_template: Self::default_domain_template(),
+            domains: BTreeMap::new(),
+        }
+    }
+}
+
+/// Every site in a network, as recorded in a snapshot's `sprt_sites` tag. Empty for single site installs.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct Sites(pub Vec<Site>);
+
+impl Sites {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The token standing in for the domain of site `id` inside stored database dumps
+    pub fn token(id: u64) -> String {
+        format!("__SPROUT__SITE_{}__", id)
+    }
+
+    /// The network's main site - blog 1, or failing that the first we know of
+    pub fn main(&self) -> Option<&Site> {
+        self.0.iter().find(|s| s.id == 1).or(self.0.first())
+    }
+
+    /// Each distinct domain, with the lowest site ID using it. Subdirectory networks share a single domain.
+    fn domains(&self) -> BTreeMap<&str, u64> {
+        let mut domains: BTreeMap<&str, u64> = BTreeMap::new();
+
+        for site in &self.0 {
+            let id = domains.entry(site.domain.as_str()).or_insert(site.id);
+            *id = (*id).min(site.id);
+        }
+
+        domains
+    }
+
+    /// Search-replace pairs swapping each domain in a URL for its token, when snapping. Only `//domain` is matched,
+    /// and should be replaced with `SearchReplace::whole_hosts`, so other hosts sharing part of a domain (eg.
+    /// `cdn.example.com` or `example.com.au`) and email addresses are left alone.
+    pub fn capture_pairs(&self) -> Vec<(String, String)> {
+        self.domains()
+            .into_iter()
+            .flat_map(|(domain, id)| {
+                URL_ANCHORS.map(|anchor| {
+                    (
+                        format!("{}{}", anchor, domain),
+                        format!("{}{}", anchor, Self::token(id)),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Stream a dump from `reader` into `writer`, swapping each site's domain for its token in the network tables
+    /// (`<prefix>blogs` and `<prefix>site`). Bare domains only appear there, and are matched exactly.
+    pub fn capture_domains_dump<R: Read, W: Write + ?Sized>(
+        &self,
+        reader: R,
+        writer: &mut W,
+        table_prefix: &str,
+    ) -> anyhow::Result<()> {
+        let domains: BTreeMap<Vec<u8>, Vec<u8>> = self
+            .domains()
+            .into_iter()
+            .map(|(domain, id)| {
+                (
+                    format!("'{}'", domain).into_bytes(),
+                    format!("'{}'", Self::token(id)).into_bytes(),
+                )
+            })
+            .collect();
+
+        split_dump(reader, |section, line| {
+            let index = match section {
+                Section::Table(table) => domain_column(table, table_prefix),
+                _ => None,
+            };
+
+            let Some(index) = index else {
+                writer.write_all(line)?;
+                return Ok(());
+            };
+
+            let Some((prefix, rows)) = parse_insert(line) else {
+                writer.write_all(line)?;
+                return Ok(());
+            };
+
+            let rows: Vec<Vec<u8>> = rows
+                .iter()
+                .map(|row| -> anyhow::Result<Vec<u8>> {
+                    let mut values: Vec<&[u8]> = split_values(row).ok_or(anyhow::anyhow!(
+                        "Could not parse a row while capturing network domains"
+                    ))?;
+
+                    if let Some(token) = values.get(index).and_then(|value| domains.get(*value)) {
+                        values[index] = token.as_slice();
+                    }
+
+                    Ok([b"(", values.join(&b","[..]).as_slice(), b")"].concat())
+                })
+                .collect::<anyhow::Result<_>>()?;
+
+            writer.write_all(prefix)?;
+            writer.write_all(b" ")?;
+            writer.write_all(&rows.join(&b","[..]))?;
+            writer.write_all(b";\n")?;
+
+            Ok(())
+        })
+    }
+
+    /// The local domain for a source domain. The main site always maps onto the local home URL's host.
+    pub fn local_domain(&self, domain: &str, config: &MultisiteConfig, home_host: &str) -> String {
+        if let Some(local) = config.domains.get(domain) {
+            return local.to_owned();
+        }
+
+        let main = self.main().map(|s| s.domain.as_str()).unwrap_or_default();
+
+        if domain == main {
+            return home_host.to_string();
+        }
+
+        let subdomain = match domain.strip_suffix(&format!(".{}", main)) {
+            Some(subdomain) if !main.is_empty() => subdomain.to_string(),
+            _ => domain.replace('.', "-"),
+        };
+
+        config
+            .domain_template
+            .replace("{subdomain}", &subdomain)
+            .replace("{home}", home_host)
+    }
+
+    /// Source and local domain for each distinct domain in the network
+    pub fn mapping(&self, config: &MultisiteConfig, home_url: &str) -> Vec<(String, String)> {
+        let home_host = url_host(home_url);
+
+        self.domains()
+            .into_keys()
+            .map(|domain| {
+                (
+                    domain.to_string(),
+                    self.local_domain(domain, config, home_host),
+                )
+            })
+            .collect()
+    }
+
+    /// Search-replace pairs swapping each domain token for its local domain, when seeding
+    pub fn seed_pairs(&self, config: &MultisiteConfig, home_url: &str) -> Vec<(String, String)> {
+        let home_host = url_host(home_url);
+
+        self.domains()
+            .into_iter()
+            .map(|(domain, id)| {
+                (
+                    Self::token(id),
+                    self.local_domain(domain, config, home_host),
+                )
+            })
+            .collect()
+    }
+
+    pub fn encode(&self) -> anyhow::Result<String> {
+        encode_tag_value(self)
+    }
+
+    pub fn decode(value: &str) -> anyhow::Result<Self> {
+        decode_tag_value(value)
+    }
+}
+
+/// Which value in a row holds the domain, for the network tables
+fn domain_column(table: &str, table_prefix: &str) -> Option<usize> {
+    match table.strip_prefix(table_prefix)? {
+        "blogs" => Some(2),
+        "site" => Some(1),
+        _ => None,
+    }
+}
+
+/// The host (and port) of a URL, eg. `my-project.test` for `https://my-project.test/wp`
+pub fn url_host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+
+    rest.split('/').next().unwrap_or(rest)
+}
+
+/// Multisite subsites keep their uploads in `uploads/sites/N`. If we've been pointed at one of those, back up to
+/// the network's uploads directory, so every site's uploads are captured.
+pub fn network_uploads_dir(path: &Path) -> PathBuf {
+    let components: Vec<Component> = path.components().collect();
+
+    match components.as_slice() {
+        [parent @ .., Component::Normal(sites), Component::Normal(id)]
+            if *sites == "sites" && id.to_string_lossy().parse::<u64>().is_ok() =>
+        {
+            parent.iter().collect()
+        }
+        _ => path.to_path_buf(),
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::snapshot::{decode_tag_value, encode_tag_value};
+
+/// Overrides a placeholder's value for this environment, eg. `SPROUT_PLACEHOLDER_CDN`
+pub const PLACEHOLDER_ENV_PREFIX: &str = "SPROUT_PLACEHOLDER_";
+
+/// Named values which differ between environments (a CDN host, `siteurl`, an absolute path...), configured under
+/// `placeholders` in `sprout.yaml`. Each is swapped for a `__SPROUT__<NAME>__` token in stored dumps, alongside
+/// the home URL, and swapped back for this environment's value when seeding.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(transparent)]
+pub struct Placeholders(pub BTreeMap<String, String>);
+
+impl Placeholders {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The token standing in for `name` inside stored database dumps
+    pub fn token(name: &str) -> String {
+        format!("__SPROUT__{}__", name.to_uppercase())
+    }
+
+    fn env_var(name: &str) -> String {
+        format!("{}{}", PLACEHOLDER_ENV_PREFIX, name.to_uppercase())
+    }
+
+    /// Names may only contain lowercase letters, digits and single underscores. `home` is reserved for the home URL,
+    /// and `site_N` for multisite domains.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for name in self.0.keys() {
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            {
+                return Err(anyhow::anyhow!(
+                    "Invalid placeholder name '{}'. Use lowercase letters, digits and underscores.",
+                    name
+                ));
+            }
+
+            if name.contains("__") {
+                return Err(anyhow::anyhow!(
+                    "Invalid placeholder name '{}'. Names can't contain consecutive underscores.",
+                    name
+                ));
+            }
+
+            if name == "home"
+                || name
+                    .strip_prefix("site_")
+                    .is_some_and(|id| id.parse::<u64>().is_ok())
+            {
+                return Err(anyhow::anyhow!(
+                    "The placeholder name '{}' is reserved",
+                    name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// This environment's values, with any environment variable overrides applied. Empty values are dropped.
+    pub fn resolve(&self) -> anyhow::Result<Self> {
+        self.validate()?;
+
+        Ok(Self(
+            self.0
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.to_owned(),
+                        std::env::var(Self::env_var(name)).unwrap_or(value.to_owned()),
+                    )
+                })
+                .filter(|(_, value)| !value.is_empty())
+                .collect(),
+        ))
+    }
+
+    /// Search-replace pairs swapping each value for its token, when snapping
+    pub fn capture_pairs(&self) -> Vec<(String, String)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (value.to_owned(), Self::token(name)))
+            .collect()
+    }
+
+    /// Search-replace pairs swapping each token captured in a snapshot for this environment's value, when seeding.
+    /// Placeholders we have no value for fall back to the value they were captured with.
+    pub fn seed_pairs(&self, captured: &Placeholders) -> Vec<(String, String)> {
+        captured
+            .0
+            .iter()
+            .map(|(name, source)| {
+                let value = match self.0.get(name) {
+                    Some(value) => value.to_owned(),
+                    None => {
+                        warn!(
+                            "No value for placeholder '{}' in sprout.yaml or ${}. Restoring the snapshot's value, {}",
+                            name,
+                            Self::env_var(name),
+                            source
+                        );
+                        source.to_owned()
+                    }
+                };
+
+                (Self::token(name), value)
+            })
+            .collect()
+    }
+
+    pub fn encode(&self) -> anyhow::Result<String> {
+        encode_tag_value(self)
+    }
+
+    pub fn decode(value: &str) -> anyhow::Result<Self> {
+        decode_tag_value(value)
+    }
+}
+
+/// Order search-replace pairs longest search first, so a value containing another (eg. an uploads URL beneath the
+/// home URL) is replaced whole
+pub fn longest_first(mut pairs: Vec<(String, String)>) -> Vec<(String, String)> {
+    pairs.sort_by_key(|(search, _)| std::cmp::Reverse(search.len()));
+    pairs
+}
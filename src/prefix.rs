@@ -0,0 +1,148 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::dump::{parse_insert, split_dump, split_values, Section};
+
+/// Which value in a row holds a prefix-bearing key, for tables which have them
+fn key_column(table: &str, from: &str) -> Option<usize> {
+    let name = table.strip_prefix(from)?;
+
+    // Multisite subsites have their own options table, eg. `wp_2_options`
+    let options = name == "options"
+        || name
+            .strip_suffix("_options")
+            .is_some_and(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()));
+
+    match name {
+        _ if options => Some(1),
+        "usermeta" => Some(2),
+        _ => None,
+    }
+}
+
+/// Rename a key, if it carries the old prefix. In options only `<prefix>user_roles` (or `<prefix>N_user_roles`)
+/// does. In usermeta every key starting with the prefix does, eg. `<prefix>capabilities` and `<prefix>user_level`.
+fn rewrite_key(value: &[u8], from: &str, to: &str, options: bool) -> Option<Vec<u8>> {
+    let key = value.strip_prefix(b"'")?.strip_suffix(b"'")?;
+    let rest = key.strip_prefix(from.as_bytes())?;
+
+    if options && !rest.ends_with(b"user_roles") {
+        return None;
+    }
+
+    Some([b"'", to.as_bytes(), rest, b"'"].concat())
+}
+
+/// Rewrite a single `(..)` row, renaming the key in column `index`
+fn rewrite_row(row: &[u8], index: usize, from: &str, to: &str, options: bool) -> Option<Vec<u8>> {
+    let mut values: Vec<Vec<u8>> = split_values(row)?.into_iter().map(<[u8]>::to_vec).collect();
+
+    if let Some(key) = rewrite_key(values.get(index)?, from, to, options) {
+        values[index] = key;
+    }
+
+    Some([b"(", values.join(&b","[..]).as_slice(), b")"].concat())
+}
+
+/// A table's name once it's renamed from one `$table_prefix` to another
+pub fn rename_table(table: &str, from: &str, to: &str) -> String {
+    match table.strip_prefix(from) {
+        Some(rest) => format!("{}{}", to, rest),
+        None => table.to_string(),
+    }
+}
+
+/// Replace every backtick quoted identifier starting with `from`
+fn rename_identifiers(line: &[u8], from: &str, to: &str) -> Vec<u8> {
+    let search = format!("`{}", from);
+    let replace = format!("`{}", to);
+
+    String::from_utf8_lossy(line)
+        .replace(&search, &replace)
+        .into_bytes()
+}
+
+/// Copy a dump, renaming tables from one `$table_prefix` to another. Keys in the options and usermeta tables
+/// which carry the prefix (eg. `wp_user_roles` and `wp_capabilities`) are renamed too, so roles and capabilities
+/// keep working. Handles both single line extended `INSERT`s, and one row per line dumps.
+pub fn rewrite_prefix(input: &Path, output: &Path, from: &str, to: &str) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(output)?);
+
+    rewrite_prefix_dump(File::open(input)?, &mut writer, from, to)?;
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Stream a dump from `reader` into `writer`, renaming tables as with `rewrite_prefix`
+pub fn rewrite_prefix_dump<R: Read, W: Write + ?Sized>(
+    reader: R,
+    writer: &mut W,
+    from: &str,
+    to: &str,
+) -> anyhow::Result<()> {
+    split_dump(reader, |section, line| {
+        let key = match section {
+            Section::Table(table) if table.starts_with(from) => {
+                key_column(table, from).map(|index| (index, index == 1))
+            }
+            _ => {
+                writer.write_all(line)?;
+                return Ok(());
+            }
+        };
+
+        if let Some((prefix, rows)) = parse_insert(line) {
+            let mut out = rename_identifiers(prefix, from, to);
+            out.push(b' ');
+
+            match key {
+                Some((index, options)) => {
+                    let rows: Vec<Vec<u8>> = rows
+                        .iter()
+                        .map(|row| {
+                            rewrite_row(row, index, from, to, options).ok_or(anyhow::anyhow!(
+                                "Could not parse a row while renaming the table prefix"
+                            ))
+                        })
+                        .collect::<anyhow::Result<_>>()?;
+
+                    out.extend(rows.join(&b","[..]));
+                    out.extend_from_slice(b";\n");
+                }
+                None => out.extend_from_slice(&line[prefix.len() + 1..]),
+            }
+
+            writer.write_all(&out)?;
+        } else if line.starts_with(b"(") {
+            // One row per line, as reassembled from a per-table dump. Rows end in `,` or `;`
+            match key {
+                Some((index, options)) => {
+                    let row = line
+                        .iter()
+                        .rposition(|b| *b == b')')
+                        .and_then(|end| {
+                            Some((rewrite_row(&line[..=end], index, from, to, options)?, end))
+                        })
+                        .ok_or(anyhow::anyhow!(
+                            "Could not parse a row while renaming the table prefix"
+                        ))?;
+
+                    writer.write_all(&row.0)?;
+                    writer.write_all(&line[row.1 + 1..])?;
+                }
+                None => writer.write_all(line)?,
+            }
+        } else {
+            writer.write_all(&rename_identifiers(line, from, to))?;
+        }
+
+        Ok(())
+    })?;
+
+    Ok(())
+}
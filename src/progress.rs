@@ -1,9 +1,17 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, sync::OnceLock};
 
 use duration_macro::duration;
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use rustic_core::{Progress, ProgressBars};
 
+/// Every visible bar is drawn through one `MultiProgress`, so work running in parallel (eg. the database dump and
+/// uploads backup while snapping) stacks its bars rather than drawing over each other
+fn multi_progress() -> &'static MultiProgress {
+    static MULTI: OnceLock<MultiProgress> = OnceLock::new();
+
+    MULTI.get_or_init(MultiProgress::new)
+}
+
 #[derive(Clone, Debug)]
 pub struct SproutProgressBar {}
 #[derive(Clone, Debug)]
@@ -14,7 +22,7 @@ pub struct SproutProgress {
 impl SproutProgress {
     pub fn new() -> Self {
         Self {
-            bar: ProgressBar::new(1024),
+            bar: multi_progress().add(ProgressBar::new(1024)),
         }
     }
 
@@ -26,7 +34,7 @@ impl SproutProgress {
 
     pub fn spinner() -> Self {
         Self {
-            bar: ProgressBar::new_spinner(),
+            bar: multi_progress().add(ProgressBar::new_spinner()),
         }
     }
 }
@@ -102,5 +110,6 @@ impl Progress for SproutProgress {
     }
     fn finish(&self) {
         self.bar.finish_and_clear();
+        multi_progress().remove(&self.bar);
     }
 }
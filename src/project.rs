@@ -1,30 +1,49 @@
 use std::{
-    collections::HashSet,
-    fs,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufWriter, Read, Write},
     path::{Path, PathBuf},
 };
 
-use capturing_glob::glob;
+use chrono::{DateTime, Local};
 use dialoguer::Input;
 
 use log::{info, warn};
 use rustic_core::{
-    Id, LocalDestination, LsOptions, Progress, ProgressBars, RepositoryOptions, RestoreOptions,
+    repofile::{Node, NodeType},
+    Id, IndexedFull, LocalDestination, LsOptions, Progress, ProgressBars, RestoreOptions,
 };
 use serde::{Deserialize, Serialize};
 use tempfile::tempdir;
 
 use crate::{
+    dump::{
+        filter_tables_dump, reassemble_dump, table_name_from_file, DatabaseLayout, DUMP_FILE,
+        TABLES_DIR,
+    },
     engine::Engine,
-    facts::ProjectFactProvider,
+    facts::{verify_dump_stream, FactProviderKind, ProjectFactProvider},
+    filter::{PathFilter, TableSelection, UploadsRules},
+    hooks::{Hook, HookContext, HookStatus, Hooks},
+    multisite::{network_uploads_dir, MultisiteConfig, Sites},
+    placeholder::{longest_first, Placeholders},
+    prefix::{rename_table, rewrite_prefix_dump},
     progress::SproutProgressBar,
-    repo::{definition::RepositoryDefinition, ProjectRepository},
-    snapshot::Snapshot,
+    repo::{definition::RepositoryDefinition, restore_node, ProjectRepository, RusticRepo},
+    report::{ChangeReport, FileChange},
+    sanitise::{SanitiseProfile, Sanitiser},
+    search_replace::SearchReplace,
+    snapshot::{Scope, Snapshot},
+    staging::{self, RestoreLock, StagingDir},
+    stream::{run_pipeline, stage, Stage},
     theme::CliTheme,
 };
 
 use colored::*;
 
+/// Stands in for the home URL inside stored database dumps
+pub const HOME_URL_PLACEHOLDER: &str = "__SPROUT__HOME__";
+
 #[derive(Debug, Serialize, Clone)]
 pub struct Project {
     pub path: PathBuf,
@@ -37,6 +56,17 @@ pub struct Project {
     engine: Engine,
 }
 
+/// Options controlling how a snapshot is restored into the project
+#[derive(Debug, Clone, Default)]
+pub struct SeedOptions {
+    /// Which parts of the snapshot to restore (and which parts to stash beforehand)
+    pub scope: Scope,
+    /// Only restore (and delete) uploads matching this filter
+    pub filter: PathFilter,
+    /// Only restore database tables matching this filter
+    pub tables: PathFilter,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ProjectConfig {
     pub name: String,
@@ -44,6 +74,25 @@ pub struct ProjectConfig {
     pub snapshot: Option<Id>,
     pub uploads_path: PathBuf,
     pub repo: String,
+    #[serde(default)]
+    pub facts: FactProviderKind,
+    #[serde(default)]
+    pub database_layout: DatabaseLayout,
+    #[serde(default, skip_serializing_if = "TableSelection::is_empty")]
+    pub tables: TableSelection,
+    #[serde(default, skip_serializing_if = "UploadsRules::is_empty")]
+    pub uploads_rules: UploadsRules,
+    /// Sanitisation profile to apply to the database when snapping
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sanitise: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sanitise_profiles: BTreeMap<String, SanitiseProfile>,
+    #[serde(default, skip_serializing_if = "Placeholders::is_empty")]
+    pub placeholders: Placeholders,
+    #[serde(default, skip_serializing_if = "MultisiteConfig::is_default")]
+    pub multisite: MultisiteConfig,
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    pub hooks: Hooks,
 }
 
 impl Project {
@@ -96,9 +145,9 @@ impl Project {
         if let Ok(installed) = facts.is_wordpress_installed() {
             if installed {
                 if let Ok(detected_uploads_path) = facts.get_uploads_dir() {
-                    uploads_path = PathBuf::from(detected_uploads_path)
-                        .strip_prefix(&path)?
-                        .to_path_buf();
+                    uploads_path = network_uploads_dir(
+                        PathBuf::from(detected_uploads_path).strip_prefix(&path)?,
+                    );
                 }
             }
         }
@@ -109,10 +158,21 @@ impl Project {
             snapshot: None,
             uploads_path,
             repo: sprout_config.default_repo,
+            facts: FactProviderKind::default(),
+            database_layout: DatabaseLayout::default(),
+            tables: TableSelection::default(),
+            uploads_rules: UploadsRules::default(),
+            sanitise: None,
+            sanitise_profiles: BTreeMap::new(),
+            placeholders: Placeholders::default(),
+            multisite: MultisiteConfig::default(),
+            hooks: Hooks::default(),
         };
 
         fs::write(path.join("sprout.yaml"), serde_yaml::to_string(&config)?)?;
 
+        engine.register_project(&path)?;
+
         Project::new(engine, path, facts)
     }
 
@@ -147,30 +207,201 @@ impl Project {
         Ok(())
     }
 
+    /// Dump the database to `path`, replacing the home URL and any configured placeholders with tokens
     pub fn dump_database(&self, path: &Path) -> anyhow::Result<()> {
+        self.dump_database_into(|reader| {
+            let mut writer = BufWriter::new(File::create(path)?);
+
+            io::copy(reader, &mut writer)?;
+            writer.flush()?;
+
+            Ok(())
+        })
+    }
+
+    /// Stream a dump of the database into `sink`. It's verified, sanitised, and has the home URL and any
+    /// configured placeholders replaced with tokens on the way, in a single pass.
+    pub fn dump_database_into(
+        &self,
+        sink: impl FnOnce(&mut dyn Read) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let profile = self.sanitise_profile()?;
+        let placeholders = self.config.placeholders.resolve()?;
+        let sites = self.network_sites()?;
         let progress = SproutProgressBar {};
         let spinner = progress.progress_spinner("Exporting database...");
-        let ret = self.facts.dump_database(path, &self.home_url);
+
+        let mut stages: Vec<Stage> = vec![stage(|reader, writer| {
+            Ok(verify_dump_stream(reader, writer)?)
+        })];
+
+        if let Some((name, profile)) = profile {
+            spinner.bar.set_message(format!(
+                "Exporting database, sanitising with profile {}...",
+                name
+            ));
+
+            let sanitiser = Sanitiser::new(profile, &self.table_prefix()?, &self.sanitise_salt()?);
+            stages.push(stage(move |reader, writer| {
+                sanitiser.rewrite_dump(reader, writer)
+            }));
+        }
+
+        let mut pairs = placeholders.capture_pairs();
+        pairs.push((self.home_url.to_owned(), HOME_URL_PLACEHOLDER.to_string()));
+
+        let replace = SearchReplace::new(&longest_first(pairs));
+        stages.push(stage(move |reader, writer| {
+            replace.rewrite_dump(reader, writer)
+        }));
+
+        // Network domains are tokenised after the home URL, structurally in the network tables and as whole hosts
+        // in URLs everywhere else
+        if !sites.is_empty() {
+            let table_prefix = self.table_prefix()?;
+            let domains = SearchReplace::new(&sites.capture_pairs()).whole_hosts();
+
+            stages.push(stage(move |reader, writer| {
+                sites.capture_domains_dump(reader, writer, &table_prefix)
+            }));
+            stages.push(stage(move |reader, writer| {
+                domains.rewrite_dump(reader, writer)
+            }));
+        }
+
+        let ret = run_pipeline(|writer| self.facts.dump_database_to(writer), stages, sink);
 
         spinner.finish();
 
         ret
     }
 
+    /// Run the commands configured for a lifecycle hook. `snapshot` is the snapshot being snapped, seeded or restored.
+    pub fn run_hook(&self, hook: Hook, snapshot: Option<Id>) -> anyhow::Result<()> {
+        self.run_hook_with_status(hook, snapshot, HookStatus::Success)
+    }
+
+    /// Run a post hook after its operation failed, so it can undo what the pre hook did (eg. maintenance mode)
+    pub fn run_hook_after_failure(&self, hook: Hook, snapshot: Option<Id>) -> anyhow::Result<()> {
+        self.run_hook_with_status(hook, snapshot, HookStatus::Failure)
+    }
+
+    fn run_hook_with_status(
+        &self,
+        hook: Hook,
+        snapshot: Option<Id>,
+        status: HookStatus,
+    ) -> anyhow::Result<()> {
+        self.config.hooks.run(
+            hook,
+            &HookContext {
+                project_name: &self.config.name,
+                project_path: &self.path,
+                branch: &self.config.branch,
+                home_url: &self.home_url,
+                snapshot,
+                status,
+            },
+        )
+    }
+
+    /// The local `$table_prefix`
+    pub fn table_prefix(&self) -> anyhow::Result<String> {
+        self.facts.get_table_prefix()
+    }
+
+    /// Every site in the network, if this is a multisite install
+    pub fn network_sites(&self) -> anyhow::Result<Sites> {
+        Ok(Sites(self.facts.get_sites()?))
+    }
+
+    /// The sanitisation profile configured for this project, resolved against the built-in presets
+    pub fn sanitise_profile(&self) -> anyhow::Result<Option<(&str, SanitiseProfile)>> {
+        self.config
+            .sanitise
+            .as_deref()
+            .map(|name| {
+                Ok((
+                    name,
+                    SanitiseProfile::resolve(name, &self.config.sanitise_profiles)?,
+                ))
+            })
+            .transpose()
+    }
+
+    /// The secret mixed into sanitised fakes. It's the repo key, so fakes are consistent for everyone sharing the
+    /// repo, but it's never stored in a snapshot.
+    pub fn sanitise_salt(&self) -> anyhow::Result<String> {
+        let (_, definition) = RepositoryDefinition::get(&self.engine, self.config.repo.as_str())?;
+
+        Ok(definition.repo_key)
+    }
+
+    /// Import the database dump at `path`, replacing the placeholder with our home URL
     pub fn import_database(&self, path: PathBuf) -> anyhow::Result<()> {
+        self.import_database_with(
+            path,
+            &self.config.placeholders.resolve()?,
+            &self.network_sites()?,
+        )
+    }
+
+    /// Import the database dump at `path`, replacing the home URL token, and the tokens for the placeholders and
+    /// network sites `captured` in its snapshot, with this environment's values
+    pub fn import_database_with(
+        &self,
+        path: PathBuf,
+        captured: &Placeholders,
+        sites: &Sites,
+    ) -> anyhow::Result<()> {
+        self.import_database_from(
+            |writer| {
+                io::copy(&mut File::open(&path)?, writer)?;
+                Ok(())
+            },
+            vec![],
+            captured,
+            sites,
+        )
+    }
+
+    /// Stream a dump from `source`, through `stages`, into the database. Tokens are swapped for this
+    /// environment's values on the way, as with `import_database_with`.
+    fn import_database_from<'a>(
+        &self,
+        source: impl FnOnce(&mut dyn Write) -> anyhow::Result<()> + Send + 'a,
+        mut stages: Vec<Stage<'a>>,
+        captured: &Placeholders,
+        sites: &Sites,
+    ) -> anyhow::Result<()> {
+        let placeholders = self.config.placeholders.resolve()?;
         let progress = SproutProgressBar {};
-        let spinner = progress.progress_spinner("Importing database...");
+        let spinner = progress.progress_spinner(format!(
+            "Importing database, setting home URL to {}...",
+            &self.home_url
+        ));
 
-        self.facts.import_database(&path)?;
+        let mut pairs = placeholders.seed_pairs(captured);
+        pairs.extend(sites.seed_pairs(&self.config.multisite, &self.home_url));
+        pairs.push((HOME_URL_PLACEHOLDER.to_string(), self.home_url.to_owned()));
 
-        spinner
-            .bar
-            .set_message(format!("Setting home URL to {}", &self.home_url));
+        for (from, to) in sites.mapping(&self.config.multisite, &self.home_url) {
+            info!("Mapping site {} to {}", from, to);
+        }
 
-        self.facts.postprocess_database(&self.home_url)?;
+        let replace = SearchReplace::new(&pairs);
+        stages.push(stage(move |reader, writer| {
+            replace.rewrite_dump(reader, writer)
+        }));
+
+        let ret = run_pipeline(source, stages, |reader| {
+            self.facts.import_database_from(reader)
+        });
 
         spinner.finish();
 
+        ret?;
+
         info!("Database installed and URL set to {}", &self.home_url);
 
         Ok(())
@@ -185,11 +416,35 @@ impl Project {
             serde_yaml::to_string(&self.config)?,
         )?;
 
+        self.engine.register_project(&self.path)?;
+
         Ok(())
     }
 
+    /// Snapshot IDs referenced by any sprout.yaml we know about for this project and repo. These must never be forgotten.
+    pub fn get_referenced_snapshots(&self) -> anyhow::Result<HashSet<Id>> {
+        let mut referenced: HashSet<Id> = self.config.snapshot.into_iter().collect();
+
+        for path in self.engine.get_known_projects()? {
+            match Self::load_project_config(&path.join("sprout.yaml")) {
+                Ok(config) => {
+                    if config.name == self.config.name && config.repo == self.config.repo {
+                        referenced.extend(config.snapshot);
+                    }
+                }
+                Err(e) => warn!(
+                    "Could not read {}: {}",
+                    path.join("sprout.yaml").display(),
+                    e
+                ),
+            }
+        }
+
+        Ok(referenced)
+    }
+
     pub fn open_repo(&self, repo_key: &str) -> anyhow::Result<ProjectRepository> {
-        let repo_opts = RepositoryOptions::default().password(repo_key);
+        let repo_opts = self.engine.repository_options(repo_key)?;
         let (_, definition) = RepositoryDefinition::get(&self.engine, self.config.repo.as_str())?;
         let repo = ProjectRepository::new(self, definition.repo, repo_opts)?;
 
@@ -198,7 +453,7 @@ impl Project {
 
     pub fn get_active_snapshot(&self, repo: &ProjectRepository) -> anyhow::Result<Snapshot> {
         if self.config.snapshot.is_some() {
-            Snapshot::from_snapshot_id(&repo.repo, self.config.snapshot.unwrap())
+            repo.get_snapshot(self.config.snapshot.unwrap())
         } else {
             repo.get_latest_snapshot_for_branch(self, &self.config.branch)
         }
@@ -211,25 +466,196 @@ impl Project {
         repo.get_all_snapshots_for_project(self)
     }
 
+    /// Local uploads that aren't in the snapshot. When a filter is set, only files within the filter are
+    /// considered, so anything outside of it is left alone.
     fn local_uploads_to_delete(
         &self,
         destination: &PathBuf,
         from_remote: HashSet<PathBuf>,
+        filter: &PathFilter,
     ) -> anyhow::Result<HashSet<PathBuf>> {
-        let local: HashSet<PathBuf> = glob(&format!("{}/(**/*)", destination.to_string_lossy()))
-            .expect("Failed to read glob pattern")
-            .flatten()
-            .map(|e| e.path().to_path_buf())
+        let local: HashSet<PathBuf> = staging::walk(destination)?
+            .into_iter()
+            .filter(|path| {
+                filter.is_empty()
+                    || (path.is_file()
+                        && path
+                            .strip_prefix(destination)
+                            .is_ok_and(|relative| filter.matches(relative)))
+            })
             .collect();
 
         Ok(&local - &from_remote)
     }
 
+    /// Apply a filter to a snapshot listing. Directories are kept only when they contain a matching file.
+    fn filter_entries(entries: Vec<(PathBuf, Node)>, filter: &PathFilter) -> Vec<(PathBuf, Node)> {
+        if filter.is_empty() {
+            return entries;
+        }
+
+        let files: HashSet<PathBuf> = entries
+            .iter()
+            .filter(|(path, node)| !node.is_dir() && filter.matches(path))
+            .map(|(path, _)| path.to_owned())
+            .collect();
+
+        let parents: HashSet<&Path> = files.iter().flat_map(|path| path.ancestors()).collect();
+
+        entries
+            .iter()
+            .filter(|(path, node)| {
+                if node.is_dir() {
+                    parents.contains(path.as_path())
+                } else {
+                    files.contains(path)
+                }
+            })
+            .cloned()
+            .collect()
+    }
+
     pub fn restore_from_snapshot(
         &self,
         repo: &ProjectRepository,
         snapshot: &Snapshot,
     ) -> anyhow::Result<()> {
+        self.restore_from_snapshot_with(repo, snapshot, &SeedOptions::default())
+    }
+
+    /// Restore the parts of a snapshot selected by `options`. Parts missing from the snapshot (eg. a partial stash) are skipped.
+    pub fn restore_from_snapshot_with(
+        &self,
+        repo: &ProjectRepository,
+        snapshot: &Snapshot,
+        options: &SeedOptions,
+    ) -> anyhow::Result<()> {
+        let indexed = repo.indexed()?;
+        let rustic_repo = indexed.as_ref();
+        let scope = snapshot.get_scope();
+
+        if options.scope.uploads {
+            if scope.uploads {
+                self.restore_uploads(repo, rustic_repo, snapshot, &options.filter)?;
+            } else {
+                warn!(
+                    "Snapshot {} does not contain uploads. Skipping.",
+                    snapshot.id
+                );
+            }
+        }
+
+        if options.scope.database {
+            if scope.database {
+                self.restore_database(repo, rustic_repo, snapshot, &options.tables)?;
+            } else {
+                warn!(
+                    "Snapshot {} does not contain a database. Skipping.",
+                    snapshot.id
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Report what restoring a snapshot with `options` would change, without touching disk or the database
+    pub fn plan_restore_from_snapshot(
+        &self,
+        repo: &ProjectRepository,
+        snapshot: &Snapshot,
+        options: &SeedOptions,
+    ) -> anyhow::Result<ChangeReport> {
+        let indexed = repo.indexed()?;
+        let rustic_repo = indexed.as_ref();
+        let scope = snapshot.get_scope();
+
+        let mut report = ChangeReport {
+            snapshot: snapshot.id,
+            ..Default::default()
+        };
+
+        if options.scope.uploads && scope.uploads {
+            let destination = self.uploads_destination()?;
+
+            let (_, entries, to_remove) =
+                self.plan_uploads(repo, rustic_repo, snapshot, &options.filter, &destination)?;
+
+            for (path, node) in entries {
+                if !matches!(node.node_type, NodeType::File) {
+                    continue;
+                }
+
+                let change = FileChange {
+                    path: path.to_owned(),
+                    size: node.meta.size,
+                };
+
+                match fs::metadata(destination.join(&path)) {
+                    Err(_) => report.added.push(change),
+                    Ok(local) => {
+                        if !Self::is_unchanged(&local, &node) {
+                            report.changed.push(change);
+                        }
+                    }
+                }
+            }
+
+            report.deleted = to_remove
+                .iter()
+                .filter(|path| path.is_file())
+                .map(|path| FileChange {
+                    path: path
+                        .strip_prefix(&destination)
+                        .unwrap_or(path)
+                        .to_path_buf(),
+                    size: fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+                })
+                .collect();
+
+            report.deleted.sort();
+        }
+
+        if options.scope.database && scope.database {
+            let db_node = repo.get_db_node(snapshot)?;
+
+            // Tables are selected by the name they're imported under, after any prefix rewriting
+            let prefixes = match snapshot.get_table_prefix() {
+                Some(from) if !options.tables.is_empty() => Some((from, self.table_prefix()?)),
+                _ => None,
+            };
+
+            let selected = |stem: &str| {
+                let table = table_name_from_file(stem);
+
+                options.tables.matches_name(&match &prefixes {
+                    Some((from, to)) => rename_table(&table, from, to),
+                    None => table,
+                })
+            };
+
+            // Per-table dumps let us size just the selected tables. A single dump is sized as a whole.
+            report.database_size = Some(
+                rustic_repo
+                    .ls(&db_node, &LsOptions::default())?
+                    .flatten()
+                    .filter(|(path, node)| {
+                        matches!(node.node_type, NodeType::File)
+                            && (!path.starts_with(TABLES_DIR)
+                                || path
+                                    .file_stem()
+                                    .is_some_and(|stem| selected(&stem.to_string_lossy())))
+                    })
+                    .map(|(_, node)| node.meta.size)
+                    .sum(),
+            );
+        }
+
+        Ok(report)
+    }
+
+    /// Resolve the uploads directory we restore into, making sure it's safely inside the project
+    fn uploads_destination(&self) -> anyhow::Result<PathBuf> {
         let destination = fs::canonicalize(&self.path)?.join(&self.config.uploads_path);
 
         /*
@@ -255,34 +681,105 @@ impl Project {
             return Err(anyhow::anyhow!("Project uploads path must not evaluate to the same directory as the project itself"));
         }
 
-        let rustic_repo = repo.repo.clone().open()?.to_indexed()?;
+        Ok(destination)
+    }
+
+    /// Work out what restoring uploads into `destination` involves, without touching disk. Returns the uploads node,
+    /// the (filtered) snapshot listing and the local paths which would be deleted.
+    fn plan_uploads<S: IndexedFull>(
+        &self,
+        repo: &ProjectRepository,
+        rustic_repo: &RusticRepo<S>,
+        snapshot: &Snapshot,
+        filter: &PathFilter,
+        destination: &PathBuf,
+    ) -> anyhow::Result<(Node, Vec<(PathBuf, Node)>, HashSet<PathBuf>)> {
         let uploads_node = repo.get_uploads_node(snapshot)?;
-        let db_node = repo.get_db_node(snapshot)?;
 
-        // use list of the snapshot contents using no additional filtering
+        // list the snapshot contents, then narrow down to any include / exclude filters
         let streamer_opts = LsOptions::default();
-        let ls = rustic_repo.ls(&uploads_node, &streamer_opts)?;
-
-        let from_remote: HashSet<PathBuf> = ls
-            .clone()
+        let entries: Vec<_> = rustic_repo
+            .ls(&uploads_node, &streamer_opts)?
             .take_while(|x| x.is_ok())
-            .map(|x| destination.join(&x.unwrap().0))
+            .map(|x| x.unwrap())
+            .collect();
+
+        let entries = Self::filter_entries(entries, filter);
+
+        let from_remote: HashSet<PathBuf> = entries
+            .iter()
+            .map(|(path, _)| destination.join(path))
+            .collect();
+
+        let to_remove = self.local_uploads_to_delete(destination, from_remote, filter)?;
+
+        Ok((uploads_node, entries, to_remove))
+    }
+
+    /// Restore uploads into a staging directory beside the uploads directory, then swap it in. Unchanged local
+    /// files are carried over rather than restored, so an interrupted restore never leaves uploads half written.
+    fn restore_uploads<S: IndexedFull>(
+        &self,
+        repo: &ProjectRepository,
+        rustic_repo: &RusticRepo<S>,
+        snapshot: &Snapshot,
+        filter: &PathFilter,
+    ) -> anyhow::Result<()> {
+        // Held until we've swapped, so anything `recover` finds really was left by an interrupted restore
+        let _lock = if self.config.uploads_path.is_relative() {
+            let target = fs::canonicalize(&self.path)?.join(&self.config.uploads_path);
+            let lock = RestoreLock::acquire(&target)?;
+            staging::recover(&target)?;
+            Some(lock)
+        } else {
+            None
+        };
+
+        let destination = self.uploads_destination()?;
+
+        let (uploads_node, entries, to_remove) =
+            self.plan_uploads(repo, rustic_repo, snapshot, filter, &destination)?;
+
+        let staging = StagingDir::new(&destination)?;
+
+        let remote: HashMap<PathBuf, &Node> = entries
+            .iter()
+            .map(|(path, node)| (destination.join(path), node))
             .collect();
 
-        let to_remove = self.local_uploads_to_delete(&destination, from_remote)?;
+        // Carry over anything outside the filter, and anything already matching the snapshot. Whatever isn't
+        // carried over is gone after the swap, so any local file we can't read or carry over aborts the restore.
+        let local = staging::walk(&destination)?
+            .into_iter()
+            .filter(|path| !to_remove.contains(path));
+
+        for path in local {
+            let metadata = fs::symlink_metadata(&path)
+                .map_err(|e| anyhow::anyhow!("Could not read {}: {}", path.display(), e))?;
+
+            let keep = match remote.get(&path) {
+                None => true,
+                Some(node) if node.is_dir() => metadata.is_dir(),
+                Some(node) => {
+                    matches!(node.node_type, NodeType::File)
+                        && metadata.is_file()
+                        && Self::is_unchanged(&metadata, node)
+                }
+            };
 
-        for path in to_remove {
-            if path.is_dir() {
-                fs::remove_dir_all(path)?;
-            } else if path.is_file() {
-                fs::remove_file(path)?;
+            if keep {
+                staging
+                    .carry_over(path.strip_prefix(&destination)?)
+                    .map_err(|e| anyhow::anyhow!("Could not keep {}: {}", path.display(), e))?;
             }
         }
 
-        // restore to this destination dir
+        let ls = entries.into_iter().map(Ok);
+
+        // restore to the staging dir. Files we carried over already match, so are skipped.
         let create = true; // create destination dir, if it doesn't exist
         let dest = LocalDestination::new(
-            &destination.to_string_lossy(),
+            &staging.path().to_string_lossy(),
             create,
             !uploads_node.is_dir(),
         )?;
@@ -294,26 +791,89 @@ impl Project {
 
         rustic_repo.restore(restore_infos, &opts, ls, &dest)?;
 
-        let dir = tempdir()?;
-        // use list of the snapshot contents using no additional filtering
-        let streamer_opts = LsOptions::default();
-        let ls = rustic_repo.ls(&db_node, &streamer_opts)?;
+        staging.swap()?;
 
-        let destination = dir.path(); // restore to this destination dir
-        let create = true; // create destination dir, if it doesn't exist
-        let dest =
-            LocalDestination::new(&destination.to_string_lossy(), create, !db_node.is_dir())?;
+        Ok(())
+    }
 
-        let opts = RestoreOptions::default();
-        let dry_run = false;
-        // create restore infos. Note: this also already creates needed dirs in the destination
-        let restore_infos = rustic_repo.prepare_restore(&opts, ls.clone(), &dest, dry_run)?;
+    /// Whether a local file matches a snapshot node, by size and modification time
+    fn is_unchanged(local: &fs::Metadata, node: &Node) -> bool {
+        let mtime = local.modified().ok().map(DateTime::<Local>::from);
 
-        rustic_repo.restore(restore_infos, &opts, ls, &dest)?;
+        local.len() == node.meta.size && mtime == node.meta.mtime
+    }
 
-        self.import_database(destination.join("database.sql"))?;
+    /// Stream a snapshot's database into the local database. A single dump streams straight from the repo, with
+    /// any table prefix rewriting and table filtering applied on the way.
+    fn restore_database<S: IndexedFull + Sync>(
+        &self,
+        repo: &ProjectRepository,
+        rustic_repo: &RusticRepo<S>,
+        snapshot: &Snapshot,
+        tables: &PathFilter,
+    ) -> anyhow::Result<()> {
+        let db_node = repo.get_db_node(snapshot)?;
+        let mut stages: Vec<Stage> = vec![];
 
-        Ok(())
+        if let Some(from) = snapshot.get_table_prefix() {
+            let to = self.table_prefix()?;
+
+            if from != to {
+                info!("Renaming tables from {}* to {}*", from, to);
+
+                stages.push(stage(move |reader, writer| {
+                    rewrite_prefix_dump(reader, writer, &from, &to)
+                }));
+            }
+        }
+
+        if !tables.is_empty() {
+            stages.push(stage(|reader, writer| {
+                let skipped = filter_tables_dump(reader, writer, tables)?;
+
+                if !skipped.is_empty() {
+                    info!("Leaving tables untouched: {}", skipped.join(", "));
+                }
+
+                Ok(())
+            }));
+        }
+
+        let placeholders = snapshot.get_placeholders();
+        let sites = snapshot.get_sites();
+
+        let dump_node = rustic_repo
+            .ls(&db_node, &LsOptions::default())?
+            .flatten()
+            .find(|(path, node)| {
+                path == Path::new(DUMP_FILE) && matches!(node.node_type, NodeType::File)
+            })
+            .map(|(_, node)| node);
+
+        match dump_node {
+            Some(node) => self.import_database_from(
+                |mut writer| Ok(rustic_repo.dump(&node, &mut writer)?),
+                stages,
+                &placeholders,
+                &sites,
+            ),
+            None => {
+                // Per-table dumps are restored first, so we can work out the order to import them in
+                let dir = tempdir()?;
+                restore_node(rustic_repo, &db_node, dir.path())?;
+
+                if !dir.path().join(TABLES_DIR).is_dir() {
+                    return Err(anyhow::anyhow!("Snapshot does not contain a database dump"));
+                }
+
+                self.import_database_from(
+                    |writer| reassemble_dump(dir.path(), writer),
+                    stages,
+                    &placeholders,
+                    &sites,
+                )
+            }
+        }
     }
 
     #[allow(clippy::format_in_format_args)]
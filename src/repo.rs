@@ -1,21 +1,47 @@
-use crate::{progress::SproutProgressBar, project::Project, snapshot::Snapshot, PKG_VERSION};
+use crate::{
+    branch::{group_branches, validate_branch, Branch},
+    dump::{split_dump_to_dir, DatabaseLayout, DUMP_FILE},
+    filter::AppliedRules,
+    progress::SproutProgressBar,
+    project::Project,
+    retention::KeepPolicy,
+    snapshot::{validate_tag, Annotation, Scope, Snapshot},
+    stream::AbortableReader,
+    PKG_VERSION,
+};
 
+use chrono::Local;
 use log::{info, warn};
 use rustic_backend::BackendOptions;
 use rustic_core::{
     last_modified_node,
     repofile::{Node, SnapshotFile},
-    BackupOptions, ConfigOptions, Id, KeyOptions, LocalSourceSaveOptions, OpenStatus,
-    ParentOptions, PathList, RepositoryOptions, SnapshotOptions,
+    BackupOptions, ConfigOptions, FullIndex, Id, IndexedFull, IndexedStatus, KeyOptions,
+    LocalDestination, LocalSourceSaveOptions, LsOptions, Open, OpenStatus, ParentOptions, PathList,
+    PruneOptions, RepositoryOptions, RestoreOptions, SnapshotOptions, StringList,
 };
 
-use std::{fs, path::PathBuf};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    thread::{self, ScopedJoinHandle},
+};
 use tempfile::tempdir;
 
 pub mod definition;
 
 pub type RusticRepo<O> = rustic_core::Repository<SproutProgressBar, O>;
 
+/// A repo with its full index loaded, ready to list, restore and back up
+pub type IndexedRepo = RusticRepo<IndexedStatus<FullIndex, OpenStatus>>;
+
 pub trait RusticRepoFactory {
     fn open_repo(
         backend: BackendOptions,
@@ -31,9 +57,149 @@ pub trait RusticRepoFactory {
 
 impl<O> RusticRepoFactory for RusticRepo<O> {}
 
+/// Remove packs which are no longer referenced by any snapshot
+pub fn prune_repo(repo: &RusticRepo<OpenStatus>) -> anyhow::Result<()> {
+    let prune_opts = PruneOptions::default();
+    let prune_plan = repo.prune_plan(&prune_opts)?;
+
+    prune_plan.do_prune(repo, &prune_opts)?;
+
+    info!("Pruned unreferenced data from the repo");
+
+    Ok(())
+}
+
+/// Pin records in the repo, optionally only those for one project. A pin record is a snapshot file tagged
+/// `sprt_obj:pin`, which names the pinned snapshot in `sprt_pin` and shares its tree, so pruning keeps its data.
+pub fn get_pin_records<S: Open>(
+    repo: &RusticRepo<S>,
+    hostname: Option<&str>,
+) -> anyhow::Result<Vec<SnapshotFile>> {
+    Ok(repo.get_matching_snapshots(|snap| {
+        snap.tags.contains("sprt_obj:pin")
+            && match hostname {
+                Some(hostname) => snap.hostname == hostname,
+                None => true,
+            }
+    })?)
+}
+
+/// The IDs pinned by a set of pin records
+pub fn pinned_ids(records: &[SnapshotFile]) -> HashSet<Id> {
+    records
+        .iter()
+        .filter_map(|record| Snapshot::get_sprout_tag(record, "sprt_pin").ok())
+        .filter_map(|id| Id::from_hex(&id).ok())
+        .collect()
+}
+
+/// Fail if any of `snapshots` is pinned, unless `force` is set
+pub fn refuse_pinned(
+    snapshots: &[Snapshot],
+    pinned: &HashSet<Id>,
+    force: bool,
+) -> anyhow::Result<()> {
+    let refused: Vec<String> = snapshots
+        .iter()
+        .filter(|snap| snap.is_any_of(pinned))
+        .map(|snap| snap.id.to_hex().to_string())
+        .collect();
+
+    if !refused.is_empty() && !force {
+        return Err(anyhow::anyhow!(
+            "Refusing to remove pinned snapshot(s) {}. Unpin them first, or override with --force / --include-pinned.",
+            refused.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fail if any of `snapshots` is referenced by a known sprout.yaml. Unlike pins, there's no override - another
+/// checkout relies on them.
+pub fn refuse_referenced(snapshots: &[Snapshot], referenced: &HashSet<Id>) -> anyhow::Result<()> {
+    let refused: Vec<String> = snapshots
+        .iter()
+        .filter(|snap| snap.is_any_of(referenced))
+        .map(|snap| snap.id.to_hex().to_string())
+        .collect();
+
+    if !refused.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Refusing to remove snapshot(s) {}, which are referenced by known sprout.yaml files. Seed those projects from another snapshot first.",
+            refused.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+/// Delete the pin records for `snapshots`, so their data can be pruned once they're gone
+pub fn delete_pin_records(
+    repo: &RusticRepo<OpenStatus>,
+    snapshots: &[Snapshot],
+) -> anyhow::Result<()> {
+    let ids: Vec<Id> = get_pin_records(repo, None)?
+        .into_iter()
+        .filter(|record| {
+            let pinned = pinned_ids(std::slice::from_ref(record));
+            snapshots.iter().any(|snap| snap.is_any_of(&pinned))
+        })
+        .map(|record| record.id)
+        .collect();
+
+    if !ids.is_empty() {
+        repo.delete_snapshots(&ids)?;
+    }
+
+    Ok(())
+}
+
+/// Restore a node (and anything beneath it) from the repo into a local directory
+pub fn restore_node<S: IndexedFull>(
+    rustic_repo: &RusticRepo<S>,
+    node: &Node,
+    destination: &Path,
+) -> anyhow::Result<()> {
+    // use list of the snapshot contents using no additional filtering
+    let streamer_opts = LsOptions::default();
+    let ls = rustic_repo.ls(node, &streamer_opts)?;
+
+    let create = true; // create destination dir, if it doesn't exist
+    let dest = LocalDestination::new(&destination.to_string_lossy(), create, !node.is_dir())?;
+
+    let opts = RestoreOptions::default();
+    let dry_run = false;
+    // create restore infos. Note: this also already creates needed dirs in the destination
+    let restore_infos = rustic_repo.prepare_restore(&opts, ls.clone(), &dest, dry_run)?;
+
+    rustic_repo.restore(restore_infos, &opts, ls, &dest)?;
+
+    Ok(())
+}
+
+/// Raise `abort` if a parallel step failed, so the others stop as soon as they can
+fn raise_on_error<T>(result: anyhow::Result<T>, abort: &AtomicBool) -> anyhow::Result<T> {
+    if result.is_err() {
+        abort.store(true, Ordering::SeqCst);
+    }
+
+    result
+}
+
+fn join_fragment<T>(handle: ScopedJoinHandle<'_, anyhow::Result<T>>) -> anyhow::Result<T> {
+    handle
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
 pub struct ProjectRepository {
     pub repo: RusticRepo<()>,
     project: Project,
+    /// Opened on first use, then shared by every method
+    opened: OnceLock<RusticRepo<OpenStatus>>,
+    /// Indexed on first use, then shared until we write new data to the repo
+    indexed: Mutex<Option<Arc<IndexedRepo>>>,
 }
 
 impl ProjectRepository {
@@ -47,9 +213,49 @@ impl ProjectRepository {
         Ok(Self {
             repo,
             project: project.clone(),
+            opened: OnceLock::new(),
+            indexed: Mutex::new(None),
         })
     }
 
+    /// The repo, opened the first time it's needed. Opening reads the config and decrypts the key, so it's only
+    /// done once per command.
+    pub fn open(&self) -> anyhow::Result<&RusticRepo<OpenStatus>> {
+        if let Some(repo) = self.opened.get() {
+            return Ok(repo);
+        }
+
+        let repo = self.repo.clone().open()?;
+
+        Ok(self.opened.get_or_init(|| repo))
+    }
+
+    /// The repo with its index loaded, built the first time it's needed. Reading the index means listing and
+    /// fetching every index file, so it's shared by every lookup until `invalidate_index` is called.
+    pub fn indexed(&self) -> anyhow::Result<Arc<IndexedRepo>> {
+        // Held while indexing, so parallel callers wait for one index rather than each building their own
+        let mut indexed = self.indexed.lock().unwrap();
+
+        if let Some(repo) = indexed.as_ref() {
+            return Ok(repo.clone());
+        }
+
+        let repo = Arc::new(self.open()?.clone().to_indexed()?);
+        *indexed = Some(repo.clone());
+
+        Ok(repo)
+    }
+
+    /// Forget the loaded index after writing packs to the repo, so later lookups can see them
+    fn invalidate_index(&self) {
+        *self.indexed.lock().unwrap() = None;
+    }
+
+    /// Look up one of this repo's bundle snapshots
+    pub fn get_snapshot(&self, snapshot_id: Id) -> anyhow::Result<Snapshot> {
+        Snapshot::from_snapshot_id(self.open()?, snapshot_id)
+    }
+
     /// Initialise a new repo
     pub fn initialise(
         backend: BackendOptions,
@@ -66,19 +272,22 @@ impl ProjectRepository {
         Ok(repo.init(&key_opts, &config_opts)?)
     }
 
+    /// Dump the database and back it up as a fragment. The dump stops part way if `abort` is raised, and `None` is
+    /// returned.
     fn snapshot_db(
         &self,
-        repo: &RusticRepo<()>,
         automatic_parent: bool,
-    ) -> anyhow::Result<SnapshotFile> {
-        let repo = repo.clone().open()?.to_indexed_ids()?;
-        let dir = tempdir()?;
-        let db_filename = dir.path().join("database.sql");
+        abort: &AtomicBool,
+    ) -> anyhow::Result<Option<SnapshotFile>> {
+        let repo = self.indexed()?;
 
-        self.project.dump_database(&db_filename)?;
+        let as_path = match self.project.config.database_layout {
+            DatabaseLayout::Single => "/.sprout/database/database.sql",
+            DatabaseLayout::Tables => "/.sprout/database",
+        };
 
         let mut backup_opts = BackupOptions::default()
-            .as_path(PathBuf::from("/.sprout/database/database.sql"))
+            .as_path(PathBuf::from(as_path))
             .ignore_save_opts(LocalSourceSaveOptions::default().ignore_devid(true));
 
         if !automatic_parent {
@@ -92,8 +301,6 @@ impl ProjectRepository {
             );
         }
 
-        let source = PathList::from_string(&db_filename.to_string_lossy())?;
-
         let mut snap = SnapshotOptions::default()
             .add_tags(
                 format!(
@@ -111,27 +318,107 @@ impl ProjectRepository {
 
         snap.program_version = format!("sprout {}", PKG_VERSION);
 
-        // Create snapshot
-        let snap = repo.backup(&backup_opts, &source, snap)?;
+        let backed_up = match self.project.config.database_layout {
+            DatabaseLayout::Single => self.backup_dump_file(&repo, &backup_opts, snap, abort),
+            DatabaseLayout::Tables => self.backup_dump_tables(&repo, &backup_opts, snap, abort),
+        };
+
+        let snap = match backed_up {
+            Ok(snap) => snap,
+            // The error's just fallout from whatever raised `abort`
+            Err(_) if abort.load(Ordering::SeqCst) => return Ok(None),
+            Err(e) => return Err(e),
+        };
 
         info!("Successfully created DB snapshot fragment");
 
-        Ok(snap)
+        Ok(Some(snap))
+    }
+
+    /// Back up the dump as a single file. rustic only backs up paths (or the whole process's stdin), so the dump goes
+    /// via a temporary file.
+    fn backup_dump_file(
+        &self,
+        repo: &IndexedRepo,
+        backup_opts: &BackupOptions,
+        snap: SnapshotFile,
+        abort: &AtomicBool,
+    ) -> anyhow::Result<SnapshotFile> {
+        let dir = tempdir()?;
+        let dump = dir.path().join(DUMP_FILE);
+
+        self.project.dump_database_into(|reader| {
+            let mut writer = BufWriter::new(File::create(&dump)?);
+            io::copy(&mut AbortableReader::new(reader, abort), &mut writer)?;
+            writer.flush()?;
+            Ok(())
+        })?;
+
+        if abort.load(Ordering::SeqCst) {
+            return Err(anyhow::anyhow!(
+                "Stopped, as a step running alongside failed"
+            ));
+        }
+
+        Ok(repo.backup(
+            backup_opts,
+            &PathList::from_string(&dump.to_string_lossy())?,
+            snap,
+        )?)
+    }
+
+    /// Back up the dump as a file per table, split into a temporary directory first
+    fn backup_dump_tables(
+        &self,
+        repo: &IndexedRepo,
+        backup_opts: &BackupOptions,
+        snap: SnapshotFile,
+        abort: &AtomicBool,
+    ) -> anyhow::Result<SnapshotFile> {
+        let dir = tempdir()?;
+        let tables = dir.path().join("database");
+
+        self.project.dump_database_into(|reader| {
+            split_dump_to_dir(AbortableReader::new(reader, abort), &tables)
+        })?;
+
+        if abort.load(Ordering::SeqCst) {
+            return Err(anyhow::anyhow!(
+                "Stopped, as a step running alongside failed"
+            ));
+        }
+
+        Ok(repo.backup(
+            backup_opts,
+            &PathList::from_string(&tables.to_string_lossy())?,
+            snap,
+        )?)
     }
 
+    /// Back up the uploads directory as a fragment. Returns `None` if `abort` was raised before the backup started.
+    /// rustic can't stop a backup part way, so once it's started it runs to the end.
     fn snapshot_uploads(
         &self,
-        repo: &RusticRepo<()>,
-        database_snapshot_id: Id,
         automatic_parent: bool,
-    ) -> anyhow::Result<SnapshotFile> {
+        abort: &AtomicBool,
+    ) -> anyhow::Result<Option<(SnapshotFile, AppliedRules)>> {
+        let resolved_uploads_path =
+            fs::canonicalize(&self.project.path)?.join(&self.project.config.uploads_path);
+
+        if !resolved_uploads_path.exists() {
+            fs::create_dir_all(&resolved_uploads_path)?;
+        }
+
+        let rules = &self.project.config.uploads_rules;
+
         let mut backup_opts = BackupOptions::default()
             .as_path(PathBuf::from("/.sprout/uploads"))
-            .ignore_save_opts(LocalSourceSaveOptions::default().ignore_devid(true));
+            .ignore_save_opts(LocalSourceSaveOptions::default().ignore_devid(true))
+            .ignore_filter_opts(rules.to_filter_opts(&resolved_uploads_path));
 
         if !automatic_parent {
             if let Some(parent_id) = self.project.config.snapshot {
-                if let Ok(parent_snapshot) = Snapshot::from_snapshot_id(repo, parent_id) {
+                if let Ok(parent_snapshot) = self.get_snapshot(parent_id) {
                     backup_opts = backup_opts.parent_opts(
                         ParentOptions::default()
                             .parent(Some(parent_snapshot.snapshot.id.to_hex().to_string())),
@@ -142,100 +429,216 @@ impl ProjectRepository {
             }
         }
 
-        let repo = repo.clone().open()?.to_indexed_ids()?;
-
-        let resolved_uploads_path =
-            fs::canonicalize(&self.project.path)?.join(&self.project.config.uploads_path);
-
-        if !resolved_uploads_path.exists() {
-            fs::create_dir_all(&resolved_uploads_path)?;
-        }
+        let repo = self.indexed()?;
 
         let source = PathList::from_string(&resolved_uploads_path.to_string_lossy())?;
 
+        let tags = format!(
+            "sprt_obj:uploads,sprt_uniq:{},sprt_branch:{}",
+            self.project
+                .unique_hash
+                .as_ref()
+                .unwrap_or(&"_none_".to_string()),
+            self.project.config.branch
+        );
+
         let mut snap = SnapshotOptions::default()
-            .add_tags(
-                format!(
-                    "sprt_obj:uploads,sprt_db:{},sprt_uniq:{},sprt_branch:{}",
-                    database_snapshot_id.to_hex().as_str(),
-                    self.project
-                        .unique_hash
-                        .as_ref()
-                        .unwrap_or(&"_none_".to_string()),
-                    self.project.config.branch
-                )
-                .as_str(),
-            )?
+            .add_tags(tags.as_str())?
             .host(self.project.config.name.to_owned())
             .to_snapshot()?;
 
         snap.program_version = format!("sprout {}", PKG_VERSION);
 
+        if abort.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+
         let snap = repo.backup(&backup_opts, &source, snap)?;
 
         info!("Successfully created uploads snapshot fragment");
 
-        Ok(snap)
+        Ok(Some((
+            snap,
+            AppliedRules::collect(rules, &resolved_uploads_path),
+        )))
     }
 
     pub fn snapshot(&self, automatic_parent: bool) -> anyhow::Result<Snapshot> {
-        let db_snapshot = self.snapshot_db(&self.repo, automatic_parent)?;
-        let uploads_snapshot =
-            self.snapshot_uploads(&self.repo, db_snapshot.id, automatic_parent)?;
+        self.snapshot_with(automatic_parent, &Scope::default())
+    }
+
+    /// Snapshot the parts of the project selected by `scope`, and bundle them into a single snapshot
+    pub fn snapshot_with(&self, automatic_parent: bool, scope: &Scope) -> anyhow::Result<Snapshot> {
+        self.snapshot_annotated(automatic_parent, scope, &Annotation::default())
+    }
+
+    /// Snapshot the parts of the project selected by `scope`, recording a message and tag on the bundle. The tag
+    /// isn't checked here - see `check_tag`.
+    pub fn snapshot_annotated(
+        &self,
+        automatic_parent: bool,
+        scope: &Scope,
+        annotation: &Annotation,
+    ) -> anyhow::Result<Snapshot> {
+        if !scope.database && !scope.uploads {
+            return Err(anyhow::anyhow!("Nothing to snapshot"));
+        }
+
+        let abort = AtomicBool::new(false);
+
+        // The database dump and the uploads backup are independent, so run them side by side. If one fails, the
+        // other stops as soon as it can: the dump part way, and the uploads backup if it hasn't started yet. An
+        // uploads backup already running finishes first, and its fragment is then deleted.
+        let (db_snapshot, uploads_snapshot) = thread::scope(|s| {
+            let db = scope.database.then(|| {
+                s.spawn(|| raise_on_error(self.snapshot_db(automatic_parent, &abort), &abort))
+            });
+
+            let uploads = scope.uploads.then(|| {
+                s.spawn(|| raise_on_error(self.snapshot_uploads(automatic_parent, &abort), &abort))
+            });
+
+            (db.map(join_fragment), uploads.map(join_fragment))
+        });
+
+        let mut snapshots = vec![];
+        let mut rules = AppliedRules::default();
+        let mut errors = vec![];
+
+        match db_snapshot {
+            Some(Ok(Some(db_snapshot))) => snapshots.push(db_snapshot),
+            Some(Err(e)) => errors.push(e),
+            _ => {}
+        }
+
+        match uploads_snapshot {
+            Some(Ok(Some((uploads_snapshot, applied)))) => {
+                snapshots.push(uploads_snapshot);
+                rules = applied;
+            }
+            Some(Err(e)) => errors.push(e),
+            _ => {}
+        }
+
+        // Fragments are only needed until they're bundled, so they're deleted whether or not that happens
+        let bundle = match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => self.bundle_fragments(&snapshots, scope, &rules, annotation),
+        };
+
+        // Whatever happened, new packs may have been written
+        self.invalidate_index();
+
+        if let Err(e) = self.delete_fragments(&snapshots) {
+            if bundle.is_err() {
+                warn!("Could not tidy up snapshot fragments: {}", e);
+            } else {
+                return Err(e);
+            }
+        }
+
+        bundle
+    }
+
+    /// Merge fragments into a bundle snapshot, tagged with everything needed to seed it
+    fn bundle_fragments(
+        &self,
+        snapshots: &[SnapshotFile],
+        scope: &Scope,
+        rules: &AppliedRules,
+        annotation: &Annotation,
+    ) -> anyhow::Result<Snapshot> {
+        let mut tags = format!(
+            "sprt_obj:bundle,sprt_uniq:{},sprt_branch:{},sprt_stats:{}",
+            self.project
+                .unique_hash
+                .as_ref()
+                .unwrap_or(&"_none_".to_string()),
+            self.project.config.branch,
+            Snapshot::pack_stats(snapshots)?
+        );
+
+        if let Some(scope) = scope.tag() {
+            tags.push_str(&format!(",sprt_scope:{}", scope));
+        }
+
+        if !rules.is_empty() {
+            tags.push_str(&format!(",sprt_rules:{}", rules.encode()?));
+        }
+
+        if let (true, Some(profile)) = (scope.database, &self.project.config.sanitise) {
+            tags.push_str(&format!(",sprt_sanitise:{}", profile));
+        }
+
+        let placeholders = self.project.config.placeholders.resolve()?;
+
+        if scope.database && !placeholders.is_empty() {
+            tags.push_str(&format!(",sprt_placeholders:{}", placeholders.encode()?));
+        }
+
+        if scope.database {
+            tags.push_str(&format!(",sprt_prefix:{}", self.project.table_prefix()?));
+        }
+
+        let sites = self.project.network_sites()?;
+
+        if scope.database && !sites.is_empty() {
+            tags.push_str(&format!(",sprt_sites:{}", sites.encode()?));
+        }
+
+        if let Some(tag) = &annotation.tag {
+            tags.push_str(&format!(",sprt_tag:{}", tag));
+        }
+
+        // The snapshot's hostname is the project name, so record the machine it was taken on separately
+        let machine = SnapshotOptions::default().to_snapshot()?.hostname;
+
+        if !machine.is_empty() {
+            tags.push_str(&format!(",sprt_host:{}", machine));
+        }
 
         let mut merged = SnapshotOptions::default()
-            .add_tags(
-                format!(
-                    "sprt_obj:bundle,sprt_uniq:{},sprt_branch:{},sprt_stats:{}",
-                    self.project
-                        .unique_hash
-                        .as_ref()
-                        .unwrap_or(&"_none_".to_string()),
-                    self.project.config.branch,
-                    Snapshot::pack_stats(&db_snapshot, &uploads_snapshot)?
-                )
-                .as_str(),
-            )?
+            .add_tags(tags.as_str())?
             .host(self.project.config.name.to_owned())
             .to_snapshot()?;
 
-        let snapshots = &[db_snapshot, uploads_snapshot];
-
         merged.program_version = format!("sprout {}", PKG_VERSION);
+        merged.description = annotation.message.to_owned();
 
-        let repo = self.repo.clone().open()?.to_indexed_ids()?;
+        let repo = self.indexed()?;
 
         let merged = repo.merge_snapshots(snapshots, &last_modified_node, merged)?;
 
-        let snap_ids: Vec<_> = snapshots.iter().map(|sn| sn.id).collect();
-
         info!("Bundled fragments into snapshot");
 
-        repo.delete_snapshots(&snap_ids)?;
-
-        info!("Tidied up snapshot fragments");
-
         Ok(Snapshot {
             id: merged.id,
             snapshot: merged,
         })
     }
 
+    fn delete_fragments(&self, snapshots: &[SnapshotFile]) -> anyhow::Result<()> {
+        if snapshots.is_empty() {
+            return Ok(());
+        }
+
+        let snap_ids: Vec<_> = snapshots.iter().map(|sn| sn.id).collect();
+
+        self.open()?.delete_snapshots(&snap_ids)?;
+
+        info!("Tidied up snapshot fragments");
+
+        Ok(())
+    }
+
     pub fn get_latest_snapshot(&self) -> anyhow::Result<Snapshot> {
-        let db_snapshot = self
-            .repo
-            .clone()
-            .open()?
-            .to_indexed_ids()?
-            .get_snapshot_from_str("latest", |snap| {
-                if snap.hostname == self.project.config.name
-                    && snap.tags.contains("sprt_obj:bundle")
-                {
-                    return true;
-                }
+        let db_snapshot = self.open()?.get_snapshot_from_str("latest", |snap| {
+            if snap.hostname == self.project.config.name && snap.tags.contains("sprt_obj:bundle") {
+                return true;
+            }
 
-                false
-            })?;
+            false
+        })?;
 
         Snapshot::from_snapshot(&db_snapshot)
     }
@@ -245,21 +648,16 @@ impl ProjectRepository {
         project: &Project,
         branch: &str,
     ) -> anyhow::Result<Snapshot> {
-        let snapshot = self
-            .repo
-            .clone()
-            .open()?
-            .to_indexed_ids()?
-            .get_snapshot_from_str("latest", |snap| {
-                if snap.hostname == project.config.name
-                    && snap.tags.contains("sprt_obj:bundle")
-                    && snap.tags.contains(&format!("sprt_branch:{}", branch))
-                {
-                    return true;
-                }
+        let snapshot = self.open()?.get_snapshot_from_str("latest", |snap| {
+            if snap.hostname == project.config.name
+                && snap.tags.contains("sprt_obj:bundle")
+                && snap.tags.contains(&format!("sprt_branch:{}", branch))
+            {
+                return true;
+            }
 
-                false
-            })?;
+            false
+        })?;
 
         Snapshot::from_snapshot(&snapshot)
     }
@@ -268,18 +666,13 @@ impl ProjectRepository {
         &self,
         project: &Project,
     ) -> anyhow::Result<(Vec<Snapshot>, Vec<anyhow::Error>)> {
-        let snapshots = self
-            .repo
-            .clone()
-            .open()?
-            .to_indexed_ids()?
-            .get_matching_snapshots(|snap| {
-                if snap.hostname == project.config.name && snap.tags.contains("sprt_obj:bundle") {
-                    return true;
-                }
+        let snapshots = self.open()?.get_matching_snapshots(|snap| {
+            if snap.hostname == project.config.name && snap.tags.contains("sprt_obj:bundle") {
+                return true;
+            }
 
-                false
-            })?;
+            false
+        })?;
 
         let errors = vec![];
 
@@ -293,24 +686,359 @@ impl ProjectRepository {
         Ok((snapshots, errors))
     }
 
+    /// The snapshot tagged `name` on `branch`, if any
+    pub fn find_tag(&self, name: &str, branch: &str) -> anyhow::Result<Option<Snapshot>> {
+        let (snapshots, _) = self.get_all_snapshots_for_project(&self.project)?;
+
+        Ok(snapshots
+            .into_iter()
+            .find(|snap| snap.has_tag(name) && snap.get_branch().is_ok_and(|b| b == branch)))
+    }
+
+    /// Find a snapshot by tag or ID. Tags on the project's branch win, then tags on any other branch, as long as
+    /// only one branch has it.
+    pub fn resolve_snapshot(&self, reference: &str) -> anyhow::Result<Snapshot> {
+        let (snapshots, _) = self.get_all_snapshots_for_project(&self.project)?;
+        let tagged: Vec<Snapshot> = snapshots
+            .into_iter()
+            .filter(|snap| snap.has_tag(reference))
+            .collect();
+
+        if let Some(snapshot) = tagged.iter().find(|snap| {
+            snap.get_branch()
+                .is_ok_and(|b| b == self.project.config.branch)
+        }) {
+            return Ok(snapshot.clone());
+        }
+
+        match tagged.as_slice() {
+            [snapshot] => return Ok(snapshot.clone()),
+            [] => {}
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "The tag {} is on more than one branch ({}). Use a snapshot ID instead.",
+                    reference,
+                    tagged
+                        .iter()
+                        .map(|snap| snap.get_branch().unwrap_or("???".to_string()))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }
+        }
+
+        let id = Id::from_hex(reference)
+            .map_err(|_| anyhow::anyhow!("Could not find a snapshot or tag {}", reference))?;
+
+        self.get_snapshot(id)
+            .map_err(|_| anyhow::anyhow!("Could not find snapshot {}", reference))
+    }
+
+    /// Check `name` can be given to a snapshot on `branch`. Tags are unique per project and branch, so if another
+    /// snapshot already has it, this fails unless `force` is set, and returns the snapshot it must be moved from.
+    pub fn check_tag(
+        &self,
+        name: &str,
+        branch: &str,
+        force: bool,
+    ) -> anyhow::Result<Option<Snapshot>> {
+        validate_tag(name)?;
+
+        match self.find_tag(name, branch)? {
+            Some(holder) if !force => Err(anyhow::anyhow!(
+                "{} is already tagged {} on {}. Use --force to move the tag.",
+                holder.id,
+                name,
+                branch
+            )),
+            holder => Ok(holder),
+        }
+    }
+
+    /// Tag a snapshot with `name`, moving the tag from another snapshot on the same branch if `force` is set.
+    /// Returns the retagged snapshot, which has a new ID.
+    pub fn tag(&self, snapshot: &Snapshot, name: &str, force: bool) -> anyhow::Result<Snapshot> {
+        if snapshot.has_tag(name) {
+            return Ok(snapshot.clone());
+        }
+
+        let holder = self.check_tag(name, &snapshot.get_branch()?, force)?;
+
+        let tag = format!("sprt_tag:{}", name);
+        let tagged = self.rewrite_tags(snapshot, |tags| tags.push(tag))?;
+
+        if let Some(holder) = holder {
+            self.untag(&holder, name)?;
+            info!("Moved tag {} from {}", name, holder.id);
+        }
+
+        Ok(tagged)
+    }
+
+    /// Remove the tag `name` from a snapshot
+    pub fn untag(&self, snapshot: &Snapshot, name: &str) -> anyhow::Result<Snapshot> {
+        let tag = format!("sprt_tag:{}", name);
+        self.rewrite_tags(snapshot, |tags| tags.retain(|t| *t != tag))
+    }
+
+    /// Snapshot files are content addressed, so changing a snapshot's tags saves a copy under a new ID and deletes
+    /// the old one. The copy remembers the ID it was first saved under, so sprout.yaml files still find it.
+    fn rewrite_tags(
+        &self,
+        snapshot: &Snapshot,
+        edit: impl FnOnce(&mut Vec<String>),
+    ) -> anyhow::Result<Snapshot> {
+        let repo = self.open()?;
+
+        let mut tags: Vec<String> = snapshot.snapshot.tags.iter().cloned().collect();
+        edit(&mut tags);
+
+        let mut file = snapshot.snapshot.clone();
+        let original = *file.original.get_or_insert(snapshot.id);
+        file.tags = StringList::from_str(&tags.join(","))?;
+
+        repo.save_snapshots(vec![file])?;
+        repo.delete_snapshots(&[snapshot.id])?;
+
+        // rustic doesn't hand back the new ID, so look the copy up
+        let rewritten = repo
+            .get_matching_snapshots(|snap| {
+                snap.original == Some(original) && snap.id != snapshot.id
+            })?
+            .into_iter()
+            .next()
+            .ok_or(anyhow::anyhow!(
+                "Could not find snapshot {} after retagging it",
+                snapshot.id
+            ))?;
+
+        Snapshot::from_snapshot(&rewritten)
+    }
+
+    /// This project's content branches, most recently updated first
+    pub fn get_branches(&self) -> anyhow::Result<Vec<Branch>> {
+        let (snapshots, _) = self.get_all_snapshots_for_project(&self.project)?;
+
+        Ok(group_branches(snapshots))
+    }
+
+    /// This project's snapshots on `branch`, newest first
+    pub fn get_branch_snapshots(&self, branch: &str) -> anyhow::Result<Vec<Snapshot>> {
+        let (snapshots, _) = self.get_all_snapshots_for_project(&self.project)?;
+
+        Ok(snapshots
+            .into_iter()
+            .filter(|snap| snap.get_branch().is_ok_and(|b| b == branch))
+            .collect())
+    }
+
+    fn ensure_new_branch(&self, branch: &str) -> anyhow::Result<()> {
+        validate_branch(branch)?;
+
+        if !self.get_branch_snapshots(branch)?.is_empty() {
+            return Err(anyhow::anyhow!("The branch {} already exists", branch));
+        }
+
+        Ok(())
+    }
+
+    /// Move every snapshot on `from` to the new branch `to`. Returns the moved snapshots, which have new IDs.
+    pub fn rename_branch(&self, from: &str, to: &str) -> anyhow::Result<Vec<Snapshot>> {
+        let snapshots = self.get_branch_snapshots(from)?;
+
+        if snapshots.is_empty() {
+            return Err(anyhow::anyhow!("The branch {} has no snapshots", from));
+        }
+
+        self.ensure_new_branch(to)?;
+
+        let (old, new) = (
+            format!("sprt_branch:{}", from),
+            format!("sprt_branch:{}", to),
+        );
+
+        snapshots
+            .iter()
+            .map(|snapshot| {
+                self.rewrite_tags(snapshot, |tags| {
+                    tags.retain(|t| *t != old);
+                    tags.push(new.to_owned());
+                })
+            })
+            .collect()
+    }
+
+    /// Forget every snapshot on `branch`, then prune. Fails if any are referenced by a known sprout.yaml, and pinned
+    /// snapshots are only forgotten if `include_pinned` is set. Returns the forgotten snapshots.
+    pub fn delete_branch(
+        &self,
+        branch: &str,
+        include_pinned: bool,
+    ) -> anyhow::Result<Vec<Snapshot>> {
+        let snapshots = self.get_branch_snapshots(branch)?;
+
+        if snapshots.is_empty() {
+            return Err(anyhow::anyhow!("The branch {} has no snapshots", branch));
+        }
+
+        refuse_referenced(&snapshots, &self.project.get_referenced_snapshots()?)?;
+        refuse_pinned(&snapshots, &self.get_pinned()?, include_pinned)?;
+
+        self.forget(&snapshots)?;
+
+        Ok(snapshots)
+    }
+
+    /// Start the new branch `to` from an existing snapshot. The snapshot file is copied onto the branch, sharing the
+    /// original's tree, so nothing is uploaded again. Tags aren't copied, as they belong to the original's branch.
+    pub fn fork_branch(&self, from: &Snapshot, to: &str) -> anyhow::Result<Snapshot> {
+        self.ensure_new_branch(to)?;
+
+        let repo = self.open()?;
+
+        let mut tags: Vec<String> = from
+            .snapshot
+            .tags
+            .iter()
+            .filter(|t| {
+                !t.starts_with("sprt_branch:")
+                    && !t.starts_with("sprt_tag:")
+                    && !t.starts_with("sprt_fork:")
+                    && !t.starts_with("sprt_stats:")
+            })
+            .cloned()
+            .collect();
+
+        tags.push(format!("sprt_branch:{}", to));
+        tags.push(format!("sprt_fork:{}", from.id.to_hex().as_str()));
+
+        // Every file is unmodified, and no data was added
+        if let Ok(stats) = from.get_stats() {
+            tags.push(format!(
+                "sprt_stats:0/0/{}/0",
+                stats.new + stats.changed + stats.unmodified
+            ));
+        }
+
+        let mut file = from.snapshot.clone();
+        file.tags = StringList::from_str(&tags.join(","))?;
+        file.original = None;
+        file.time = Local::now();
+
+        repo.save_snapshots(vec![file])?;
+
+        // rustic doesn't hand back the new ID, so look the copy up
+        let fork = self
+            .get_branch_snapshots(to)?
+            .into_iter()
+            .next()
+            .ok_or(anyhow::anyhow!("Could not find the new branch {}", to))?;
+
+        info!("Forked {} from {}", to, from.id);
+
+        Ok(fork)
+    }
+
+    /// IDs of this project's pinned snapshots
+    pub fn get_pinned(&self) -> anyhow::Result<HashSet<Id>> {
+        Ok(pinned_ids(&get_pin_records(
+            self.open()?,
+            Some(&self.project.config.name),
+        )?))
+    }
+
+    /// Pin a snapshot, so it can't be forgotten or dropped without an override. Returns false if it was already
+    /// pinned.
+    pub fn pin(&self, snapshot: &Snapshot) -> anyhow::Result<bool> {
+        if snapshot.is_any_of(&self.get_pinned()?) {
+            return Ok(false);
+        }
+
+        let mut record = SnapshotOptions::default()
+            .add_tags(&format!(
+                "sprt_obj:pin,sprt_pin:{},sprt_branch:{}",
+                snapshot.original_id().to_hex().as_str(),
+                snapshot.get_branch()?
+            ))?
+            .host(self.project.config.name.to_owned())
+            .to_snapshot()?;
+
+        record.program_version = format!("sprout {}", PKG_VERSION);
+        record.tree = snapshot.snapshot.tree;
+
+        self.open()?.save_snapshots(vec![record])?;
+
+        Ok(true)
+    }
+
+    /// Unpin a snapshot. Returns false if it wasn't pinned.
+    pub fn unpin(&self, snapshot: &Snapshot) -> anyhow::Result<bool> {
+        if !snapshot.is_any_of(&self.get_pinned()?) {
+            return Ok(false);
+        }
+
+        delete_pin_records(self.open()?, std::slice::from_ref(snapshot))?;
+
+        Ok(true)
+    }
+
+    /// Work out which of this project's snapshots a keep policy would forget, optionally limited to one branch
+    pub fn plan_forget(
+        &self,
+        policy: &KeepPolicy,
+        branch: Option<&str>,
+        protected: &HashSet<Id>,
+    ) -> anyhow::Result<(Vec<Snapshot>, Vec<Snapshot>)> {
+        let (snapshots, _) = self.get_all_snapshots_for_project(&self.project)?;
+
+        let snapshots = snapshots
+            .into_iter()
+            .filter(|snap| match branch {
+                Some(branch) => snap.get_branch().is_ok_and(|b| b == branch),
+                None => true,
+            })
+            .collect();
+
+        Ok(policy.apply(snapshots, protected))
+    }
+
+    /// Remove snapshots from the repo, then prune any data no longer referenced
+    pub fn forget(&self, snapshots: &[Snapshot]) -> anyhow::Result<()> {
+        let repo = self.open()?;
+
+        let ids: Vec<Id> = snapshots.iter().map(|snap| snap.id).collect();
+
+        repo.delete_snapshots(&ids)?;
+        delete_pin_records(repo, snapshots)?;
+
+        info!("Forgot {} snapshots", ids.len());
+
+        self.prune()
+    }
+
+    /// Remove packs which are no longer referenced by any snapshot
+    pub fn prune(&self) -> anyhow::Result<()> {
+        prune_repo(self.open()?)?;
+
+        // Pruning repacks, so the index we loaded may point at packs which are gone
+        self.invalidate_index();
+
+        Ok(())
+    }
+
     pub fn get_latest_unique_hash(&self) -> anyhow::Result<Option<String>> {
-        let node = self
-            .repo
-            .clone()
-            .open()?
-            .to_indexed_ids()?
-            .get_snapshot_from_str("latest", |snap| {
-                if snap.hostname == self.project.config.name
-                    && snap.tags.contains("sprt_obj:bundle")
-                    && snap
-                        .tags
-                        .contains(&format!("sprt_branch:{}", self.project.config.branch))
-                {
-                    return true;
-                }
+        let node = self.open()?.get_snapshot_from_str("latest", |snap| {
+            if snap.hostname == self.project.config.name
+                && snap.tags.contains("sprt_obj:bundle")
+                && snap
+                    .tags
+                    .contains(&format!("sprt_branch:{}", self.project.config.branch))
+            {
+                return true;
+            }
 
-                false
-            });
+            false
+        });
 
         match node {
             Err(_) => Ok(None),
@@ -327,7 +1055,7 @@ impl ProjectRepository {
     }
 
     pub fn get_uploads_node(&self, snapshot: &Snapshot) -> anyhow::Result<Node> {
-        let repo = self.repo.clone().open()?.to_indexed()?;
+        let repo = self.indexed()?;
 
         Ok(repo.node_from_snapshot_path(
             &format!("{}:/.sprout/uploads", snapshot.id.to_hex().as_str()),
@@ -347,7 +1075,7 @@ impl ProjectRepository {
     }
 
     pub fn get_db_node(&self, snapshot: &Snapshot) -> anyhow::Result<Node> {
-        let repo = self.repo.clone().open()?.to_indexed()?;
+        let repo = self.indexed()?;
 
         Ok(repo.node_from_snapshot_path(
             &format!("{}:/.sprout/database", snapshot.id.to_hex().as_str()),
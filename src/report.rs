@@ -0,0 +1,28 @@
+use std::path::PathBuf;
+
+use rustic_core::Id;
+use serde::Serialize;
+
+/// A single file in a change report, relative to the uploads directory
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileChange {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// What restoring a snapshot would change. Produced by a dry run.
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct ChangeReport {
+    pub snapshot: Id,
+    pub added: Vec<FileChange>,
+    pub changed: Vec<FileChange>,
+    pub deleted: Vec<FileChange>,
+    /// Size of the database dump to import, if the database would be restored
+    pub database_size: Option<u64>,
+}
+
+impl ChangeReport {
+    pub fn total_size(files: &[FileChange]) -> u64 {
+        files.iter().map(|f| f.size).sum()
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::Datelike;
+use rustic_core::Id;
+use serde::{Deserialize, Serialize};
+
+use crate::snapshot::Snapshot;
+
+/// Describes which snapshots survive a forget. A snapshot matched by any rule is kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KeepPolicy {
+    /// Keep the N most recent snapshots
+    #[serde(default)]
+    pub last: Option<usize>,
+    /// Keep the most recent snapshot for each of the last N days which have one
+    #[serde(default)]
+    pub daily: Option<usize>,
+    /// Keep the most recent snapshot for each of the last N weeks which have one
+    #[serde(default)]
+    pub weekly: Option<usize>,
+    /// Keep the N most recent snapshots on every content branch
+    #[serde(default)]
+    pub per_branch: Option<usize>,
+}
+
+impl KeepPolicy {
+    pub fn is_empty(&self) -> bool {
+        self.last.is_none()
+            && self.daily.is_none()
+            && self.weekly.is_none()
+            && self.per_branch.is_none()
+    }
+
+    /// Split snapshots into those to keep and those to forget, newest first. Protected IDs are always kept.
+    pub fn apply(
+        &self,
+        mut snapshots: Vec<Snapshot>,
+        protected: &HashSet<Id>,
+    ) -> (Vec<Snapshot>, Vec<Snapshot>) {
+        snapshots.sort_by(|a, b| b.snapshot.time.cmp(&a.snapshot.time));
+
+        let mut days = HashSet::new();
+        let mut weeks = HashSet::new();
+        let mut branches: HashMap<String, usize> = HashMap::new();
+
+        let (keep, forget): (Vec<_>, Vec<_>) =
+            snapshots
+                .into_iter()
+                .enumerate()
+                .partition(|(index, snap)| {
+                    let mut keep = snap.is_any_of(protected);
+
+                    if let Some(last) = self.last {
+                        keep |= *index < last;
+                    }
+
+                    if let Some(daily) = self.daily {
+                        let day = snap.snapshot.time.date_naive();
+                        if !days.contains(&day) && days.len() < daily {
+                            days.insert(day);
+                            keep = true;
+                        }
+                    }
+
+                    if let Some(weekly) = self.weekly {
+                        let week = snap.snapshot.time.iso_week();
+                        let week = (week.year(), week.week());
+                        if !weeks.contains(&week) && weeks.len() < weekly {
+                            weeks.insert(week);
+                            keep = true;
+                        }
+                    }
+
+                    if let Some(per_branch) = self.per_branch {
+                        let count = branches
+                            .entry(snap.get_branch().unwrap_or_default())
+                            .or_insert(0);
+                        if *count < per_branch {
+                            *count += 1;
+                            keep = true;
+                        }
+                    }
+
+                    keep
+                });
+
+        (
+            keep.into_iter().map(|(_, snap)| snap).collect(),
+            forget.into_iter().map(|(_, snap)| snap).collect(),
+        )
+    }
+}
@@ -0,0 +1,535 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha224};
+
+use crate::dump::{parse_insert, split_dump, split_values, Section};
+
+/// MD5 of `password`. WordPress accepts plain MD5 hashes, and upgrades them on the next login.
+const RESET_PASSWORD_HASH: &str = "5f4dcc3b5aa765d61d8327deb882cf99";
+
+const FIRST_NAMES: [&str; 8] = [
+    "Alex", "Sam", "Jo", "Charlie", "Robin", "Jamie", "Morgan", "Taylor",
+];
+const LAST_NAMES: [&str; 8] = [
+    "Smith", "Jones", "Taylor", "Brown", "Williams", "Wilson", "Evans", "Walker",
+];
+const CITIES: [&str; 4] = ["Exampleton", "Sampleford", "Testbury", "Demoville"];
+
+/// How to fake a value. Fakes are derived from a salted hash of the original value, so the same input always gives
+/// the same output - even across tables - but can't be matched against guesses without the salt.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Fake {
+    Email,
+    /// A login style name. Unique for each original value, so it's safe in columns with a unique index.
+    Username,
+    Name,
+    FirstName,
+    LastName,
+    Phone,
+    Address,
+    City,
+    Postcode,
+    Company,
+    Ip,
+    Text,
+    Blank,
+    Null,
+}
+
+impl Fake {
+    /// Render a fake SQL value for the raw (quoted) original. NULLs stay NULL.
+    pub fn render(&self, original: &[u8], salt: &str) -> Vec<u8> {
+        if original == b"NULL" {
+            return original.to_vec();
+        }
+
+        let mut hasher = Sha224::new();
+        hasher.update(salt);
+        hasher.update(format!("{:?}", self));
+        hasher.update(original);
+        let hash = format!("{:x}", hasher.finalize());
+        let n = usize::from_str_radix(&hash[..8], 16).unwrap_or(0);
+
+        let value = match self {
+            Fake::Email => format!("user-{}@example.test", &hash[..10]),
+            Fake::Username => format!("user-{}", &hash[..16]),
+            Fake::Name => format!(
+                "{} {}",
+                FIRST_NAMES[n % FIRST_NAMES.len()],
+                LAST_NAMES[(n / FIRST_NAMES.len()) % LAST_NAMES.len()]
+            ),
+            Fake::FirstName => FIRST_NAMES[n % FIRST_NAMES.len()].to_string(),
+            Fake::LastName => LAST_NAMES[n % LAST_NAMES.len()].to_string(),
+            Fake::Phone => format!("555-01{:02}", n % 100),
+            Fake::Address => format!("{} Example Street", n % 999 + 1),
+            Fake::City => CITIES[n % CITIES.len()].to_string(),
+            Fake::Postcode => format!("{:05}", n % 100_000),
+            Fake::Company => format!("Example Company {}", &hash[..6]),
+            Fake::Ip => format!("192.0.2.{}", n % 254 + 1),
+            Fake::Text => format!("Redacted {}", &hash[..12]),
+            Fake::Blank => String::new(),
+            Fake::Null => return b"NULL".to_vec(),
+        };
+
+        format!("'{}'", value).into_bytes()
+    }
+}
+
+/// Anonymisation rules applied to the database dump at snap time. Table names are given without the table
+/// prefix, and match `<prefix><name>` - and multisite subsite tables (eg. `wp_2_options`) too.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SanitiseProfile {
+    /// Profiles (built-in or from sprout.yaml) to apply first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extends: Vec<String>,
+    /// Tables to empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub truncate: Vec<String>,
+    /// Columns to fake, by table
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fake: BTreeMap<String, BTreeMap<String, Fake>>,
+    /// Values to fake in key / value meta tables (eg. `usermeta`), by table and `meta_key`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fake_meta: BTreeMap<String, BTreeMap<String, Fake>>,
+    /// Reset every user's password to `password`
+    #[serde(default)]
+    pub reset_passwords: bool,
+    /// Options to blank in the `options` table
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blank_options: Vec<String>,
+}
+
+impl SanitiseProfile {
+    /// The built-in presets
+    pub fn builtin(name: &str) -> Option<Self> {
+        let columns = |pairs: &[(&str, Fake)]| -> BTreeMap<String, Fake> {
+            pairs.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+        };
+
+        let strings =
+            |values: &[&str]| -> Vec<String> { values.iter().map(|v| v.to_string()).collect() };
+
+        match name {
+            "wordpress" => Some(Self {
+                fake: BTreeMap::from([
+                    (
+                        "users".to_string(),
+                        columns(&[
+                            ("user_login", Fake::Username),
+                            ("user_nicename", Fake::Username),
+                            ("user_email", Fake::Email),
+                            ("display_name", Fake::Name),
+                            ("user_url", Fake::Blank),
+                            ("user_activation_key", Fake::Blank),
+                        ]),
+                    ),
+                    (
+                        "comments".to_string(),
+                        columns(&[
+                            ("comment_author", Fake::Name),
+                            ("comment_author_email", Fake::Email),
+                            ("comment_author_url", Fake::Blank),
+                            ("comment_author_IP", Fake::Ip),
+                            ("comment_agent", Fake::Blank),
+                        ]),
+                    ),
+                ]),
+                fake_meta: BTreeMap::from([(
+                    "usermeta".to_string(),
+                    columns(&[
+                        ("first_name", Fake::FirstName),
+                        ("last_name", Fake::LastName),
+                        ("nickname", Fake::Name),
+                        ("description", Fake::Blank),
+                        ("session_tokens", Fake::Blank),
+                    ]),
+                )]),
+                reset_passwords: true,
+                blank_options: strings(&["mailserver_login", "mailserver_pass", "recovery_keys"]),
+                ..Default::default()
+            }),
+            "woocommerce" => {
+                let address = [
+                    ("first_name", Fake::FirstName),
+                    ("last_name", Fake::LastName),
+                    ("company", Fake::Company),
+                    ("address_1", Fake::Address),
+                    ("address_2", Fake::Blank),
+                    ("city", Fake::City),
+                    ("postcode", Fake::Postcode),
+                    ("email", Fake::Email),
+                    ("phone", Fake::Phone),
+                ];
+
+                let meta = |prefix: &str| -> Vec<(String, Fake)> {
+                    ["billing", "shipping"]
+                        .iter()
+                        .flat_map(|kind| {
+                            address.iter().map(move |(field, fake)| {
+                                (format!("{}{}_{}", prefix, kind, field), *fake)
+                            })
+                        })
+                        .collect()
+                };
+
+                Some(Self {
+                    extends: strings(&["wordpress"]),
+                    truncate: strings(&[
+                        "woocommerce_sessions",
+                        "woocommerce_api_keys",
+                        "woocommerce_payment_tokens",
+                        "woocommerce_payment_tokenmeta",
+                        "wc_download_log",
+                        "wc_webhooks",
+                    ]),
+                    fake: BTreeMap::from([
+                        ("wc_order_addresses".to_string(), columns(&address)),
+                        (
+                            "wc_orders".to_string(),
+                            columns(&[
+                                ("billing_email", Fake::Email),
+                                ("ip_address", Fake::Ip),
+                                ("user_agent", Fake::Blank),
+                                ("customer_note", Fake::Blank),
+                            ]),
+                        ),
+                        (
+                            "wc_customer_lookup".to_string(),
+                            columns(&[
+                                ("username", Fake::Name),
+                                ("first_name", Fake::FirstName),
+                                ("last_name", Fake::LastName),
+                                ("email", Fake::Email),
+                                ("postcode", Fake::Postcode),
+                                ("city", Fake::City),
+                            ]),
+                        ),
+                    ]),
+                    fake_meta: BTreeMap::from([
+                        ("usermeta".to_string(), meta("").into_iter().collect()),
+                        (
+                            "postmeta".to_string(),
+                            meta("_")
+                                .into_iter()
+                                .chain([
+                                    ("_billing_address_index".to_string(), Fake::Blank),
+                                    ("_shipping_address_index".to_string(), Fake::Blank),
+                                    ("_customer_ip_address".to_string(), Fake::Ip),
+                                    ("_customer_user_agent".to_string(), Fake::Blank),
+                                ])
+                                .collect(),
+                        ),
+                    ]),
+                    blank_options: strings(&[
+                        "woocommerce_stripe_settings",
+                        "woocommerce_ppcp-gateway_settings",
+                        "woocommerce_paypal_settings",
+                    ]),
+                    ..Default::default()
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Resolve a profile by name, from sprout.yaml or the built-in presets, merging anything it extends
+    pub fn resolve(name: &str, custom: &BTreeMap<String, SanitiseProfile>) -> anyhow::Result<Self> {
+        Self::resolve_inner(name, custom, &mut vec![])
+    }
+
+    fn resolve_inner(
+        name: &str,
+        custom: &BTreeMap<String, SanitiseProfile>,
+        seen: &mut Vec<String>,
+    ) -> anyhow::Result<Self> {
+        if seen.iter().any(|s| s == name) {
+            return Err(anyhow::anyhow!(
+                "Sanitisation profile '{}' extends itself",
+                name
+            ));
+        }
+
+        seen.push(name.to_string());
+
+        let profile = custom
+            .get(name)
+            .cloned()
+            .or_else(|| Self::builtin(name))
+            .ok_or(anyhow::anyhow!("Unknown sanitisation profile '{}'", name))?;
+
+        let mut resolved = Self::default();
+
+        for parent in &profile.extends {
+            resolved.merge(Self::resolve_inner(parent, custom, seen)?);
+        }
+
+        resolved.merge(profile);
+
+        seen.pop();
+
+        Ok(resolved)
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.truncate.extend(other.truncate);
+
+        for (table, columns) in other.fake {
+            self.fake.entry(table).or_default().extend(columns);
+        }
+
+        for (table, keys) in other.fake_meta {
+            self.fake_meta.entry(table).or_default().extend(keys);
+        }
+
+        self.reset_passwords |= other.reset_passwords;
+        self.blank_options.extend(other.blank_options);
+    }
+}
+
+/// Does `table` match an unprefixed rule `name`? Only `<prefix><name>` and multisite `<prefix><N>_<name>` do, so
+/// plugin tables which happen to end the same way (eg. `wp_foo_users`) are left alone.
+fn table_matches(table: &str, prefix: &str, name: &str) -> bool {
+    let Some(rest) = table.strip_prefix(prefix) else {
+        return false;
+    };
+
+    rest == name
+        || rest
+            .strip_suffix(name)
+            .and_then(|site| site.strip_suffix('_'))
+            .is_some_and(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// The rules which apply to one table
+#[derive(Default)]
+struct TableRules {
+    truncate: bool,
+    columns: Vec<(String, Fake)>,
+    reset_passwords: bool,
+    /// Key column, value column, and the fake for each key
+    meta: Option<(String, String, HashMap<String, Fake>)>,
+}
+
+impl TableRules {
+    fn for_table(profile: &SanitiseProfile, prefix: &str, table: &str) -> Self {
+        let matches = |name: &str| table_matches(table, prefix, name);
+
+        let mut rules = Self {
+            truncate: profile.truncate.iter().any(|t| matches(t)),
+            reset_passwords: profile.reset_passwords && matches("users"),
+            ..Default::default()
+        };
+
+        for (name, columns) in &profile.fake {
+            if matches(name) {
+                rules
+                    .columns
+                    .extend(columns.iter().map(|(c, f)| (c.to_owned(), *f)));
+            }
+        }
+
+        let mut keys: HashMap<String, Fake> = HashMap::new();
+        let mut key_columns = ("meta_key", "meta_value");
+
+        for (name, meta) in &profile.fake_meta {
+            if matches(name) {
+                keys.extend(meta.iter().map(|(k, f)| (k.to_owned(), *f)));
+            }
+        }
+
+        if matches("options") && !profile.blank_options.is_empty() {
+            key_columns = ("option_name", "option_value");
+            keys.extend(
+                profile
+                    .blank_options
+                    .iter()
+                    .map(|o| (o.to_owned(), Fake::Blank)),
+            );
+        }
+
+        if !keys.is_empty() {
+            rules.meta = Some((key_columns.0.to_string(), key_columns.1.to_string(), keys));
+        }
+
+        rules
+    }
+
+    fn is_empty(&self) -> bool {
+        !self.truncate && !self.reset_passwords && self.columns.is_empty() && self.meta.is_none()
+    }
+}
+
+/// Applies a sanitisation profile to a SQL dump
+pub struct Sanitiser {
+    profile: SanitiseProfile,
+    /// The `$table_prefix` of the dumped database
+    table_prefix: String,
+    /// Secret mixed into every fake. It must never be stored alongside the sanitised dump.
+    salt: String,
+}
+
+impl Sanitiser {
+    pub fn new(profile: SanitiseProfile, table_prefix: &str, salt: &str) -> Self {
+        Self {
+            profile,
+            table_prefix: table_prefix.to_string(),
+            salt: salt.to_string(),
+        }
+    }
+
+    /// Sanitise a dump file into a new file
+    pub fn rewrite_file(&self, input: &Path, output: &Path) -> anyhow::Result<()> {
+        let mut writer = BufWriter::new(File::create(output)?);
+
+        self.rewrite_dump(File::open(input)?, &mut writer)?;
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Sanitise a SQL dump from `reader` into `writer`. Fails, rather than leaking data, if a table with rules
+    /// can't be parsed. Rules for columns a table doesn't have are skipped.
+    pub fn rewrite_dump<R: Read, W: Write + ?Sized>(
+        &self,
+        reader: R,
+        writer: &mut W,
+    ) -> anyhow::Result<()> {
+        let mut rules: HashMap<String, TableRules> = HashMap::new();
+        let mut columns: HashMap<String, Vec<String>> = HashMap::new();
+        let mut in_create = false;
+
+        split_dump(reader, |section, line| {
+            let Section::Table(table) = section else {
+                writer.write_all(line)?;
+                return Ok(());
+            };
+
+            let table_rules = rules
+                .entry(table.to_string())
+                .or_insert_with(|| TableRules::for_table(&self.profile, &self.table_prefix, table));
+
+            // Track column order from CREATE TABLE, so we know which value is which
+            if line.starts_with(b"CREATE TABLE") {
+                in_create = true;
+                columns.insert(table.to_string(), vec![]);
+            } else if in_create {
+                if line.starts_with(b")") {
+                    in_create = false;
+                } else if let Some(column) = line
+                    .strip_prefix(b"  `")
+                    .and_then(|rest| rest.iter().position(|b| *b == b'`').map(|end| &rest[..end]))
+                {
+                    columns
+                        .entry(table.to_string())
+                        .or_default()
+                        .push(String::from_utf8_lossy(column).to_string());
+                }
+            }
+
+            if table_rules.is_empty() || !line.starts_with(b"INSERT INTO ") {
+                writer.write_all(line)?;
+                return Ok(());
+            }
+
+            if table_rules.truncate {
+                return Ok(());
+            }
+
+            let table_columns = columns.get(table).map(Vec::as_slice).unwrap_or_default();
+
+            let sanitised = self
+                .sanitise_insert(line, table_rules, table_columns)
+                .ok_or(anyhow::anyhow!(
+                    "Could not sanitise an INSERT into `{}`",
+                    table
+                ))?;
+
+            writer.write_all(&sanitised)?;
+
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+
+    fn sanitise_insert(
+        &self,
+        line: &[u8],
+        rules: &TableRules,
+        columns: &[String],
+    ) -> Option<Vec<u8>> {
+        // Without the columns from CREATE TABLE we can't tell which values to fake
+        if columns.is_empty() {
+            return None;
+        }
+
+        let (prefix, rows) = parse_insert(line)?;
+        let position = |name: &str| columns.iter().position(|c| c == name);
+
+        let targets: Vec<(usize, Fake)> = rules
+            .columns
+            .iter()
+            .filter_map(|(column, fake)| Some((position(column)?, *fake)))
+            .collect();
+
+        let password = match rules.reset_passwords {
+            true => position("user_pass"),
+            false => None,
+        };
+
+        let meta = match &rules.meta {
+            Some((key, value, keys)) => position(key)
+                .zip(position(value))
+                .map(|(k, v)| (k, v, keys)),
+            None => None,
+        };
+
+        let mut out = prefix.to_vec();
+        out.push(b' ');
+
+        for (i, row) in rows.iter().enumerate() {
+            let mut values: Vec<Vec<u8>> =
+                split_values(row)?.into_iter().map(<[u8]>::to_vec).collect();
+
+            for (index, fake) in &targets {
+                let value = values.get_mut(*index)?;
+                *value = fake.render(value, &self.salt);
+            }
+
+            if let Some(index) = password {
+                *values.get_mut(index)? = format!("'{}'", RESET_PASSWORD_HASH).into_bytes();
+            }
+
+            if let Some((key_index, value_index, keys)) = meta {
+                let key = values.get(key_index)?;
+                let key = String::from_utf8_lossy(key.strip_prefix(b"'")?.strip_suffix(b"'")?)
+                    .to_string();
+
+                if let Some(fake) = keys.get(&key) {
+                    let value = values.get_mut(value_index)?;
+                    *value = fake.render(value, &self.salt);
+                }
+            }
+
+            if i > 0 {
+                out.push(b',');
+            }
+
+            out.push(b'(');
+            out.extend(values.join(&b","[..]));
+            out.push(b')');
+        }
+
+        out.extend_from_slice(b";\n");
+
+        Some(out)
+    }
+}
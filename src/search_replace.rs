@@ -0,0 +1,418 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+/// A streaming search-replace engine for SQL dumps. String literals in the dump are unescaped,
+/// rewritten and re-escaped. PHP-serialised values are rewritten structurally, so `s:N:` length
+/// prefixes stay correct, including for values nested inside arrays, objects and other serialised strings.
+#[derive(Debug, Clone)]
+pub struct SearchReplace {
+    pairs: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Only match search terms which aren't followed by more of a hostname
+    whole_hosts: bool,
+}
+
+/// Where we are in the SQL stream between lines
+enum State {
+    Code,
+    Identifier,
+    Literal {
+        quote: u8,
+        raw: Vec<u8>,
+        value: Vec<u8>,
+    },
+}
+
+impl SearchReplace {
+    pub fn new<S: AsRef<[u8]>, R: AsRef<[u8]>>(pairs: &[(S, R)]) -> Self {
+        Self {
+            pairs: pairs
+                .iter()
+                .filter(|(search, _)| !search.as_ref().is_empty())
+                .map(|(search, replace)| (search.as_ref().to_vec(), replace.as_ref().to_vec()))
+                .collect(),
+            whole_hosts: false,
+        }
+    }
+
+    /// Only replace matches which end a hostname, so `//example.com` matches `//example.com/about` and
+    /// `//example.com.`, but not `//example.com.au` or `//example.community`
+    pub fn whole_hosts(mut self) -> Self {
+        self.whole_hosts = true;
+        self
+    }
+
+    /// Rewrite a dump file into a new file
+    pub fn rewrite_file(&self, input: &Path, output: &Path) -> anyhow::Result<()> {
+        let reader = BufReader::new(File::open(input)?);
+        let mut writer = BufWriter::new(File::create(output)?);
+
+        self.rewrite_dump(reader, &mut writer)?;
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Rewrite a SQL dump from `reader` into `writer`. Only quoted string literals are touched.
+    pub fn rewrite_dump<R: Read, W: Write + ?Sized>(
+        &self,
+        reader: R,
+        writer: &mut W,
+    ) -> anyhow::Result<()> {
+        let mut reader = BufReader::new(reader);
+        let mut line = vec![];
+        let mut state = State::Code;
+
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            if matches!(state, State::Code) && (line.starts_with(b"--") || line.starts_with(b"#")) {
+                writer.write_all(&line)?;
+                line.clear();
+                continue;
+            }
+
+            let mut out = Vec::with_capacity(line.len());
+            let mut i = 0;
+
+            while i < line.len() {
+                let byte = line[i];
+
+                match &mut state {
+                    State::Code => {
+                        match byte {
+                            b'\'' | b'"' => {
+                                state = State::Literal {
+                                    quote: byte,
+                                    raw: vec![],
+                                    value: vec![],
+                                }
+                            }
+                            b'`' => {
+                                out.push(byte);
+                                state = State::Identifier;
+                            }
+                            _ => out.push(byte),
+                        }
+                        i += 1;
+                    }
+                    State::Identifier => {
+                        out.push(byte);
+
+                        if byte == b'`' {
+                            if line.get(i + 1) == Some(&b'`') {
+                                out.push(b'`');
+                                i += 1;
+                            } else {
+                                state = State::Code;
+                            }
+                        }
+
+                        i += 1;
+                    }
+                    State::Literal { quote, raw, value } => {
+                        if byte == b'\\' && i + 1 < line.len() {
+                            raw.extend_from_slice(&line[i..i + 2]);
+                            value.extend_from_slice(&unescape(line[i + 1]));
+                            i += 2;
+                        } else if byte == *quote {
+                            if line.get(i + 1) == Some(&*quote) {
+                                raw.extend_from_slice(&line[i..i + 2]);
+                                value.push(byte);
+                                i += 2;
+                            } else {
+                                let quote = *quote;
+
+                                out.push(quote);
+
+                                match self.replace_value(value) {
+                                    Cow::Borrowed(_) => out.extend_from_slice(raw),
+                                    Cow::Owned(replaced) => out.extend(escape(&replaced)),
+                                }
+
+                                out.push(quote);
+                                state = State::Code;
+                                i += 1;
+                            }
+                        } else {
+                            raw.push(byte);
+                            value.push(byte);
+                            i += 1;
+                        }
+                    }
+                }
+            }
+
+            writer.write_all(&out)?;
+            line.clear();
+        }
+
+        if let State::Literal { quote, raw, .. } = state {
+            // Unterminated literal - pass it through untouched
+            writer.write_all(&[quote])?;
+            writer.write_all(&raw)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace every search term within a single (unescaped) value. PHP-serialised values are
+    /// rewritten structurally. Returns `Cow::Borrowed` when nothing changed.
+    pub fn replace_value<'a>(&self, value: &'a [u8]) -> Cow<'a, [u8]> {
+        if !self
+            .pairs
+            .iter()
+            .any(|(search, _)| self.find_match(value, search, 0).is_some())
+        {
+            return Cow::Borrowed(value);
+        }
+
+        if let Some(rewritten) = self.rewrite_serialized(value) {
+            return Cow::Owned(rewritten);
+        }
+
+        let mut value = value.to_vec();
+
+        for (search, replace) in &self.pairs {
+            value = match self.whole_hosts {
+                true => self.replace_matches(&value, search, replace),
+                false => replace_all(&value, search, replace),
+            };
+        }
+
+        Cow::Owned(value)
+    }
+
+    /// Find the next match of `search`, honouring `whole_hosts`
+    fn find_match(&self, haystack: &[u8], search: &[u8], from: usize) -> Option<usize> {
+        let mut pos = from;
+
+        loop {
+            let found = find(haystack, search, pos)?;
+
+            if !self.whole_hosts || ends_host(haystack, found + search.len()) {
+                return Some(found);
+            }
+
+            pos = found + 1;
+        }
+    }
+
+    /// As `replace_all`, but only replacing matches found by `find_match`
+    fn replace_matches(&self, haystack: &[u8], search: &[u8], replace: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(haystack.len());
+        let mut pos = 0;
+
+        while let Some(found) = self.find_match(haystack, search, pos) {
+            out.extend_from_slice(&haystack[pos..found]);
+            out.extend_from_slice(replace);
+            pos = found + search.len();
+        }
+
+        out.extend_from_slice(&haystack[pos..]);
+
+        out
+    }
+
+    fn rewrite_serialized(&self, input: &[u8]) -> Option<Vec<u8>> {
+        let mut out = Vec::with_capacity(input.len());
+        let end = self.rewrite_node(input, 0, &mut out, true)?;
+
+        if end == input.len() {
+            Some(out)
+        } else {
+            None
+        }
+    }
+
+    /// Rewrite one serialised node starting at `pos`, returning the position after it
+    fn rewrite_node(
+        &self,
+        input: &[u8],
+        pos: usize,
+        out: &mut Vec<u8>,
+        replace: bool,
+    ) -> Option<usize> {
+        match *input.get(pos)? {
+            b'N' => {
+                expect(input, pos + 1, b";")?;
+                out.extend_from_slice(b"N;");
+                Some(pos + 2)
+            }
+            b'b' | b'i' | b'd' | b'r' | b'R' => {
+                expect(input, pos + 1, b":")?;
+                let end = find(input, b";", pos + 2)?;
+                out.extend_from_slice(&input[pos..=end]);
+                Some(end + 1)
+            }
+            b's' => {
+                let (len, start) = read_length(input, pos + 1)?;
+                expect(input, start, b"\"")?;
+                let content = input.get(start + 1..start + 1 + len)?;
+                let end = start + 1 + len;
+                expect(input, end, b"\";")?;
+
+                let content = if replace {
+                    self.replace_value(content)
+                } else {
+                    Cow::Borrowed(content)
+                };
+
+                out.extend_from_slice(format!("s:{}:\"", content.len()).as_bytes());
+                out.extend_from_slice(&content);
+                out.extend_from_slice(b"\";");
+
+                Some(end + 2)
+            }
+            b'a' => {
+                let (count, start) = read_length(input, pos + 1)?;
+                expect(input, start, b"{")?;
+                out.extend_from_slice(&input[pos..=start]);
+                let end = self.rewrite_members(input, start + 1, count, out)?;
+                Some(end)
+            }
+            b'O' => {
+                let (len, start) = read_length(input, pos + 1)?;
+                let class_end = start + 1 + len;
+                expect(input, class_end, b"\"")?;
+                let (count, start) = read_length(input, class_end + 1)?;
+                expect(input, start, b"{")?;
+                out.extend_from_slice(&input[pos..=start]);
+                let end = self.rewrite_members(input, start + 1, count, out)?;
+                Some(end)
+            }
+            b'C' | b'E' => {
+                // Custom serialised objects and enums are opaque - copy them through untouched
+                let (len, start) = read_length(input, pos + 1)?;
+                let class_end = start + 1 + len;
+                expect(input, class_end, b"\"")?;
+
+                if input[pos] == b'E' {
+                    expect(input, class_end + 1, b";")?;
+                    out.extend_from_slice(&input[pos..class_end + 2]);
+                    return Some(class_end + 2);
+                }
+
+                let (len, start) = read_length(input, class_end + 1)?;
+                expect(input, start, b"{")?;
+                let end = start + 1 + len;
+                expect(input, end, b"}")?;
+                out.extend_from_slice(&input[pos..=end]);
+                Some(end + 1)
+            }
+            _ => None,
+        }
+    }
+
+    /// Rewrite `count` key / value pairs followed by a closing brace
+    fn rewrite_members(
+        &self,
+        input: &[u8],
+        mut pos: usize,
+        count: usize,
+        out: &mut Vec<u8>,
+    ) -> Option<usize> {
+        for _ in 0..count {
+            pos = self.rewrite_node(input, pos, out, false)?;
+            pos = self.rewrite_node(input, pos, out, true)?;
+        }
+
+        expect(input, pos, b"}")?;
+        out.push(b'}');
+
+        Some(pos + 1)
+    }
+}
+
+/// Read `:N:` at `pos` and return N and the position after the trailing colon
+fn read_length(input: &[u8], pos: usize) -> Option<(usize, usize)> {
+    expect(input, pos, b":")?;
+    let end = find(input, b":", pos + 1)?;
+    let len = std::str::from_utf8(&input[pos + 1..end])
+        .ok()?
+        .parse()
+        .ok()?;
+
+    Some((len, end + 1))
+}
+
+/// Whether a hostname ends at `pos`. A trailing dot only continues the hostname if another label follows it.
+fn ends_host(input: &[u8], pos: usize) -> bool {
+    let label = |byte: Option<&u8>| byte.is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'-');
+
+    match input.get(pos) {
+        Some(b'.') => !label(input.get(pos + 1)),
+        next => !label(next),
+    }
+}
+
+fn expect(input: &[u8], pos: usize, token: &[u8]) -> Option<()> {
+    if input.get(pos..pos + token.len())? == token {
+        Some(())
+    } else {
+        None
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if needle.is_empty() || from > haystack.len() {
+        return None;
+    }
+
+    haystack[from..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|p| p + from)
+}
+
+pub fn replace_all(haystack: &[u8], search: &[u8], replace: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(haystack.len());
+    let mut pos = 0;
+
+    while let Some(found) = find(haystack, search, pos) {
+        out.extend_from_slice(&haystack[pos..found]);
+        out.extend_from_slice(replace);
+        pos = found + search.len();
+    }
+
+    out.extend_from_slice(&haystack[pos..]);
+
+    out
+}
+
+/// Decode a MySQL backslash escape sequence
+fn unescape(byte: u8) -> Vec<u8> {
+    match byte {
+        b'0' => vec![0],
+        b'b' => vec![8],
+        b'n' => vec![b'\n'],
+        b'r' => vec![b'\r'],
+        b't' => vec![b'\t'],
+        b'Z' => vec![0x1a],
+        b'%' | b'_' => vec![b'\\', byte],
+        _ => vec![byte],
+    }
+}
+
+/// Escape a value the same way as mysqldump
+fn escape(value: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(value.len() + 16);
+
+    for &byte in value {
+        match byte {
+            0 => out.extend_from_slice(b"\\0"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            0x1a => out.extend_from_slice(b"\\Z"),
+            b'\\' | b'\'' | b'"' => {
+                out.push(b'\\');
+                out.push(byte);
+            }
+            _ => out.push(byte),
+        }
+    }
+
+    out
+}
@@ -1,7 +1,9 @@
-use rustic_core::{repofile::SnapshotFile, Id};
-use serde::Serialize;
+use std::collections::HashSet;
 
-use crate::repo::RusticRepo;
+use rustic_core::{repofile::SnapshotFile, Id, Open};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{filter::AppliedRules, multisite::Sites, placeholder::Placeholders, repo::RusticRepo};
 
 #[derive(Debug, Serialize, Clone)]
 pub struct Snapshot {
@@ -9,6 +11,103 @@ pub struct Snapshot {
     pub snapshot: SnapshotFile,
 }
 
+/// Which parts of a project a snapshot contains. Partial snapshots (eg. a database-only stash) carry a `sprt_scope` tag.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Scope {
+    pub database: bool,
+    pub uploads: bool,
+}
+
+impl Default for Scope {
+    fn default() -> Self {
+        Self {
+            database: true,
+            uploads: true,
+        }
+    }
+}
+
+impl Scope {
+    pub fn database_only() -> Self {
+        Self {
+            database: true,
+            uploads: false,
+        }
+    }
+
+    pub fn uploads_only() -> Self {
+        Self {
+            database: false,
+            uploads: true,
+        }
+    }
+
+    /// The `sprt_scope` tag value for a partial snapshot. Full snapshots aren't tagged.
+    pub fn tag(&self) -> Option<&'static str> {
+        match (self.database, self.uploads) {
+            (true, false) => Some("database"),
+            (false, true) => Some("uploads"),
+            _ => None,
+        }
+    }
+}
+
+/// A message and tag to record on a new snapshot
+#[derive(Debug, Clone, Default)]
+pub struct Annotation {
+    pub message: Option<String>,
+    pub tag: Option<String>,
+}
+
+/// Encode `value` for an `sprt_*` tag. Tags are comma separated, so commas (and our escape character) are escaped.
+pub fn encode_tag_value<T: Serialize>(value: &T) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(value)?
+        .replace('%', "%25")
+        .replace(',', "%2C"))
+}
+
+pub fn decode_tag_value<T: DeserializeOwned>(value: &str) -> anyhow::Result<T> {
+    Ok(serde_json::from_str(
+        &value.replace("%2C", ",").replace("%25", "%"),
+    )?)
+}
+
+/// Check a tag name is usable. Tags are stored in a comma separated list, and typed on the command line in place of
+/// snapshot IDs, so they're kept to letters, digits, dots, dashes and underscores.
+pub fn validate_tag(name: &str) -> anyhow::Result<()> {
+    let valid = name.len() <= 64
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+
+    if !valid {
+        return Err(anyhow::anyhow!(
+            "Invalid tag `{}`. Tags may contain letters, digits, dots, dashes and underscores, and must start with a letter or digit.",
+            name
+        ));
+    }
+
+    if name.len() == 64 && Id::from_hex(name).is_ok() {
+        return Err(anyhow::anyhow!(
+            "Invalid tag `{}`. Tags can't look like snapshot IDs.",
+            name
+        ));
+    }
+
+    Ok(())
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.database, self.uploads) {
+            (true, false) => write!(f, "database"),
+            (false, true) => write!(f, "uploads"),
+            _ => write!(f, "database and uploads"),
+        }
+    }
+}
+
 pub struct SnapshotStats {
     pub new: u64,
     pub changed: u64,
@@ -17,16 +116,27 @@ pub struct SnapshotStats {
 }
 
 impl Snapshot {
-    pub fn from_snapshot_id(repo: &RusticRepo<()>, snapshot_id: Id) -> anyhow::Result<Self> {
-        let repo = repo.clone().open()?.to_indexed()?;
-
-        let snapshot = repo.get_snapshot_from_str(&snapshot_id.to_hex(), |snap| {
+    pub fn from_snapshot_id<S: Open>(
+        repo: &RusticRepo<S>,
+        snapshot_id: Id,
+    ) -> anyhow::Result<Self> {
+        let snapshot = match repo.get_snapshot_from_str(&snapshot_id.to_hex(), |snap| {
             if snap.tags.contains("sprt_obj:bundle") && snap.id == snapshot_id {
                 return true;
             }
 
             false
-        })?;
+        }) {
+            Ok(snapshot) => snapshot,
+            // Retagged snapshots are saved under a new ID, but remember the one they were first saved under
+            Err(e) => repo
+                .get_matching_snapshots(|snap| {
+                    snap.tags.contains("sprt_obj:bundle") && snap.original == Some(snapshot_id)
+                })?
+                .into_iter()
+                .next()
+                .ok_or(e)?,
+        };
 
         Ok(Self {
             id: snapshot.id,
@@ -57,21 +167,21 @@ impl Snapshot {
         }
     }
 
-    pub fn pack_stats(
-        db_snapshot: &SnapshotFile,
-        uploads_snapshot: &SnapshotFile,
-    ) -> anyhow::Result<String> {
-        let new = db_snapshot.summary.as_ref().unwrap().files_new
-            + uploads_snapshot.summary.as_ref().unwrap().files_new;
-
-        let changed = db_snapshot.summary.as_ref().unwrap().files_changed
-            + uploads_snapshot.summary.as_ref().unwrap().files_changed;
-
-        let unmodified = db_snapshot.summary.as_ref().unwrap().files_unmodified
-            + uploads_snapshot.summary.as_ref().unwrap().files_unmodified;
+    pub fn pack_stats(fragments: &[SnapshotFile]) -> anyhow::Result<String> {
+        let summaries: Vec<_> = fragments
+            .iter()
+            .map(|fragment| {
+                fragment
+                    .summary
+                    .as_ref()
+                    .ok_or(anyhow::anyhow!("Snapshot fragment has no summary"))
+            })
+            .collect::<anyhow::Result<_>>()?;
 
-        let data_added = db_snapshot.summary.as_ref().unwrap().data_added
-            + uploads_snapshot.summary.as_ref().unwrap().data_added;
+        let new: u64 = summaries.iter().map(|s| s.files_new).sum();
+        let changed: u64 = summaries.iter().map(|s| s.files_changed).sum();
+        let unmodified: u64 = summaries.iter().map(|s| s.files_unmodified).sum();
+        let data_added: u64 = summaries.iter().map(|s| s.data_added).sum();
 
         Ok(format!("{}/{}/{}/{}", new, changed, unmodified, data_added))
     }
@@ -88,14 +198,94 @@ impl Snapshot {
         })
     }
 
+    /// Whether this snapshot is `id`, or was first saved as `id` before being retagged
+    pub fn has_id(&self, id: Id) -> bool {
+        self.id == id || self.snapshot.original == Some(id)
+    }
+
+    /// The ID this snapshot was first saved under. Only differs from `id` if it's been retagged.
+    pub fn original_id(&self) -> Id {
+        self.snapshot.original.unwrap_or(self.id)
+    }
+
+    /// Whether this snapshot is, or was first saved as, any of `ids`
+    pub fn is_any_of(&self, ids: &HashSet<Id>) -> bool {
+        ids.contains(&self.id) || self.snapshot.original.is_some_and(|id| ids.contains(&id))
+    }
+
+    /// The names this snapshot has been tagged with
+    pub fn get_tags(&self) -> Vec<String> {
+        self.snapshot
+            .tags
+            .iter()
+            .filter_map(|tag| tag.strip_prefix("sprt_tag:"))
+            .map(|tag| tag.to_string())
+            .collect()
+    }
+
+    pub fn has_tag(&self, name: &str) -> bool {
+        self.snapshot.tags.contains(&format!("sprt_tag:{}", name))
+    }
+
+    /// The message given when this snapshot was taken, if any
+    pub fn get_message(&self) -> Option<String> {
+        self.snapshot.description.clone()
+    }
+
     pub fn get_branch(&self) -> anyhow::Result<String> {
         Self::get_sprout_tag(&self.snapshot, "sprt_branch")
     }
 
+    pub fn get_scope(&self) -> Scope {
+        match Self::get_sprout_tag(&self.snapshot, "sprt_scope").as_deref() {
+            Ok("database") => Scope::database_only(),
+            Ok("uploads") => Scope::uploads_only(),
+            _ => Scope::default(),
+        }
+    }
+
+    /// The uploads exclusion rules in effect when this snapshot was taken, if any
+    pub fn get_rules(&self) -> Option<AppliedRules> {
+        Self::get_sprout_tag(&self.snapshot, "sprt_rules")
+            .ok()
+            .and_then(|rules| AppliedRules::decode(&rules).ok())
+    }
+
+    /// The placeholders captured in this snapshot's database, and the values they replaced
+    pub fn get_placeholders(&self) -> Placeholders {
+        Self::get_sprout_tag(&self.snapshot, "sprt_placeholders")
+            .ok()
+            .and_then(|placeholders| Placeholders::decode(&placeholders).ok())
+            .unwrap_or_default()
+    }
+
+    /// The multisite network captured in this snapshot's database. Empty for single site installs.
+    pub fn get_sites(&self) -> Sites {
+        Self::get_sprout_tag(&self.snapshot, "sprt_sites")
+            .ok()
+            .and_then(|sites| Sites::decode(&sites).ok())
+            .unwrap_or_default()
+    }
+
+    /// The `$table_prefix` of the database in this snapshot. Older snapshots don't record it.
+    pub fn get_table_prefix(&self) -> Option<String> {
+        Self::get_sprout_tag(&self.snapshot, "sprt_prefix").ok()
+    }
+
+    /// The sanitisation profile applied to this snapshot's database, if any
+    pub fn get_sanitise_profile(&self) -> Option<String> {
+        Self::get_sprout_tag(&self.snapshot, "sprt_sanitise").ok()
+    }
+
     pub fn get_project_identity_hash(&self) -> anyhow::Result<String> {
         Self::get_sprout_tag(&self.snapshot, "sprt_uniq")
     }
 
+    /// The machine this snapshot was taken on. Older snapshots don't record it.
+    pub fn get_host(&self) -> Option<String> {
+        Self::get_sprout_tag(&self.snapshot, "sprt_host").ok()
+    }
+
     pub fn get_project_name(&self) -> String {
         self.snapshot.hostname.clone()
     }
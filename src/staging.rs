@@ -0,0 +1,209 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+
+const STAGING_MARKER: &str = "sprout-staging";
+const OLD_MARKER: &str = "sprout-old";
+const LOCK_MARKER: &str = "sprout.lock";
+
+/// Split `target` into its parent, and the prefix of a sibling with `marker` in its name, eg. `.uploads.sprout-old-`
+fn sibling_prefix(target: &Path, marker: &str) -> anyhow::Result<(PathBuf, String)> {
+    let parent = target.parent().ok_or(anyhow::anyhow!(
+        "{} has no parent directory",
+        target.display()
+    ))?;
+    let name = target.file_name().ok_or(anyhow::anyhow!(
+        "{} has no directory name",
+        target.display()
+    ))?;
+
+    Ok((
+        parent.to_path_buf(),
+        format!(".{}.{}-", name.to_string_lossy(), marker),
+    ))
+}
+
+/// Every path under `root`, parents before their children. Symlinks aren't followed. Unlike a glob, any entry
+/// which can't be read is an error rather than silently missing - callers rely on this list being complete.
+pub fn walk(root: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let entries = fs::read_dir(&dir)
+            .map_err(|e| anyhow::anyhow!("Could not read {}: {}", dir.display(), e))?;
+
+        for entry in entries {
+            let entry =
+                entry.map_err(|e| anyhow::anyhow!("Could not read {}: {}", dir.display(), e))?;
+            let path = entry.path();
+
+            if entry.file_type()?.is_dir() {
+                pending.push(path.clone());
+            }
+
+            paths.push(path);
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Tidy up after an interrupted restore into `target`. Leftover staging directories are removed. If we were
+/// interrupted mid-swap, `target` is missing and the directory it was moved aside to is put back. Only call this
+/// while holding a `RestoreLock` on `target`, or a restore running alongside loses its staging directory.
+pub fn recover(target: &Path) -> anyhow::Result<()> {
+    let target = &fs::canonicalize(target).unwrap_or(target.to_path_buf());
+    let (parent, staging_prefix) = sibling_prefix(target, STAGING_MARKER)?;
+    let (_, old_prefix) = sibling_prefix(target, OLD_MARKER)?;
+
+    if !parent.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(&parent)?.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+
+        if name.starts_with(&staging_prefix) {
+            info!("Removing stale staging directory {}", path.display());
+            fs::remove_dir_all(&path)?;
+        } else if name.starts_with(&old_prefix) {
+            if target.exists() {
+                info!("Removing stale backup directory {}", path.display());
+                fs::remove_dir_all(&path)?;
+            } else {
+                warn!(
+                    "A previous restore was interrupted. Putting {} back.",
+                    target.display()
+                );
+                fs::rename(&path, target)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Held for the whole of a restore into a directory, so a second restore can't start alongside it and tidy up its
+/// staging directory as if it were stale. It's an OS file lock, so it's released if we're killed part way.
+pub struct RestoreLock {
+    _file: fs::File,
+}
+
+impl RestoreLock {
+    /// Lock `target` by a lock file beside it, eg. `.uploads.sprout.lock`. The file is left behind afterwards.
+    pub fn acquire(target: &Path) -> anyhow::Result<Self> {
+        let target = &fs::canonicalize(target).unwrap_or(target.to_path_buf());
+        let (parent, prefix) = sibling_prefix(target, LOCK_MARKER)?;
+        let path = parent.join(prefix.trim_end_matches('-'));
+
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| anyhow::anyhow!("Could not create {}: {}", path.display(), e))?;
+
+        match file.try_lock() {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(fs::TryLockError::WouldBlock) => Err(anyhow::anyhow!(
+                "Another seed is restoring into {}. Try again once it's finished.",
+                target.display()
+            )),
+            Err(fs::TryLockError::Error(e)) => {
+                Err(anyhow::anyhow!("Could not lock {}: {}", path.display(), e))
+            }
+        }
+    }
+}
+
+/// A directory assembled beside `target`, then swapped in for it once complete. Removed if dropped before then.
+pub struct StagingDir {
+    target: PathBuf,
+    path: PathBuf,
+    swapped: bool,
+}
+
+impl StagingDir {
+    /// Create a staging directory beside `target`, which must exist. Symlinks are resolved, so we stage on the
+    /// same filesystem as the real directory and the swap can be a rename.
+    pub fn new(target: &Path) -> anyhow::Result<Self> {
+        let target = fs::canonicalize(target)?;
+        let (parent, prefix) = sibling_prefix(&target, STAGING_MARKER)?;
+        let path = parent.join(format!("{}{}", prefix, std::process::id()));
+
+        fs::create_dir(&path)?;
+        fs::set_permissions(&path, fs::metadata(&target)?.permissions())?;
+
+        Ok(Self {
+            target,
+            path,
+            swapped: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Carry `relative` over from the target unchanged. Files are hardlinked, falling back to a copy (which is a
+    /// reflink on filesystems supporting them) if that fails. Directories are created empty.
+    pub fn carry_over(&self, relative: &Path) -> anyhow::Result<()> {
+        let from = self.target.join(relative);
+        let to = self.path.join(relative);
+
+        if fs::symlink_metadata(&from)?.is_dir() {
+            fs::create_dir_all(&to)?;
+            return Ok(());
+        }
+
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if fs::hard_link(&from, &to).is_err() {
+            fs::copy(&from, &to)?;
+        }
+
+        Ok(())
+    }
+
+    /// Swap the staging directory in for the target. The target is renamed aside first, then the staging
+    /// directory renamed into its place, so the target is only ever missing (never half restored) and `recover`
+    /// can put it back if we're interrupted in between.
+    pub fn swap(mut self) -> anyhow::Result<()> {
+        let (parent, prefix) = sibling_prefix(&self.target, OLD_MARKER)?;
+        let old = parent.join(format!("{}{}", prefix, std::process::id()));
+
+        fs::rename(&self.target, &old)?;
+
+        if let Err(e) = fs::rename(&self.path, &self.target) {
+            fs::rename(&old, &self.target)?;
+            return Err(e.into());
+        }
+
+        self.swapped = true;
+
+        if let Err(e) = fs::remove_dir_all(&old) {
+            warn!(
+                "Could not remove {}: {}. It'll be removed next time.",
+                old.display(),
+                e
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        if !self.swapped {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
@@ -1,15 +1,26 @@
-use std::path::PathBuf;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
 
+use chrono::{Duration, Local};
+use indicatif::HumanBytes;
 use log::{info, warn};
 use passwords::PasswordGenerator;
 use rustic_backend::BackendOptions;
-use rustic_core::{ConfigOptions, Id, KeyOptions, RepositoryOptions};
+use rustic_core::{ConfigOptions, Id, KeyOptions, OpenStatus, RepositoryOptions};
 
 use crate::{
     engine::*,
-    project::Project,
-    repo::{ProjectRepository, RusticRepo, RusticRepoFactory},
-    snapshot::Snapshot,
+    project::{Project, SeedOptions},
+    repo::{
+        delete_pin_records, get_pin_records, pinned_ids, prune_repo, refuse_pinned,
+        ProjectRepository, RusticRepo, RusticRepoFactory,
+    },
+    report::ChangeReport,
+    retention::KeepPolicy,
+    snapshot::{Scope, Snapshot},
 };
 
 pub struct Stash {
@@ -62,27 +73,32 @@ impl Stash {
         Ok(())
     }
 
-    fn open_stash(&self, project: &Project) -> anyhow::Result<ProjectRepository> {
+    pub fn open_stash(&self, project: &Project) -> anyhow::Result<ProjectRepository> {
         let sprout_config = self.engine.get_config()?;
         let backend = BackendOptions::default().repository(self.path.to_string_lossy());
-        let repo_opts = RepositoryOptions::default().password(sprout_config.stash_key);
+        let repo_opts = self.engine.repository_options(&sprout_config.stash_key)?;
 
         ProjectRepository::new(project, backend, repo_opts)
     }
 
-    fn direct_open_stash(&self) -> anyhow::Result<RusticRepo<()>> {
+    fn direct_open_stash(&self) -> anyhow::Result<RusticRepo<OpenStatus>> {
         let sprout_config = self.engine.get_config()?;
         let backend = BackendOptions::default().repository(self.path.to_string_lossy());
-        let repo_opts = RepositoryOptions::default().password(sprout_config.stash_key);
+        let repo_opts = self.engine.repository_options(&sprout_config.stash_key)?;
+
+        Ok(RusticRepo::<()>::open_repo(backend, repo_opts)?.open()?)
+    }
 
-        RusticRepo::<()>::open_repo(backend, repo_opts)
+    pub fn stash(&self, project: &Project) -> anyhow::Result<Snapshot> {
+        self.stash_with(project, &Scope::default())
     }
 
-    pub fn stash(&self, project: &Project) -> anyhow::Result<()> {
+    /// Stash only the parts of the project selected by `scope`
+    pub fn stash_with(&self, project: &Project, scope: &Scope) -> anyhow::Result<Snapshot> {
         info!("Stashing {}...", project.config.name);
         let repo = self.open_stash(project)?;
 
-        let snapshot = repo.snapshot(true)?;
+        let snapshot = repo.snapshot_with(true, scope)?;
 
         info!(
             "Stashed with snapshot id {}",
@@ -93,19 +109,173 @@ impl Stash {
             snapshot.id.to_hex().to_string()
         );
 
-        Ok(())
+        match self.apply_retention(Some(snapshot.id)) {
+            Ok((dropped, reclaimed)) => {
+                if !dropped.is_empty() {
+                    info!(
+                        "Dropped {} old stash(es), reclaiming {}",
+                        dropped.len(),
+                        HumanBytes(reclaimed)
+                    );
+                }
+            }
+            Err(e) => warn!("Could not apply the stash retention policy: {}", e),
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Apply the stash retention policy from sprout-config.yaml across all projects, then prune unreferenced data.
+    /// The snapshot passed as `keep`, and pinned stashes, are never dropped. Returns the dropped stashes and the number of bytes reclaimed.
+    pub fn apply_retention(&self, keep: Option<Id>) -> anyhow::Result<(Vec<Snapshot>, u64)> {
+        let retention = self.engine.get_config()?.stash_retention;
+        let repo = self.direct_open_stash()?;
+        let size_before = dir_size(&self.path)?;
+
+        let snapshots =
+            repo.get_matching_snapshots(|snap| snap.tags.contains("sprt_obj:bundle"))?;
+
+        let mut projects: HashMap<String, Vec<Snapshot>> = HashMap::new();
+
+        for snap in snapshots {
+            projects
+                .entry(snap.hostname.clone())
+                .or_default()
+                .push(Snapshot::from_snapshot(&snap)?);
+        }
+
+        let mut protected: HashSet<Id> = keep.into_iter().collect();
+        protected.extend(pinned_ids(&get_pin_records(&repo, None)?));
+        let policy = KeepPolicy {
+            last: retention.max_count,
+            ..KeepPolicy::default()
+        };
+
+        let mut kept = vec![];
+        let mut dropped = vec![];
+
+        for (_, stashes) in projects {
+            if policy.is_empty() {
+                kept.extend(stashes);
+                continue;
+            }
+
+            let (project_kept, project_dropped) = policy.apply(stashes, &protected);
+
+            kept.extend(project_kept);
+            dropped.extend(project_dropped);
+        }
+
+        if let Some(max_age_days) = retention.max_age_days {
+            let cutoff = Local::now() - Duration::days(max_age_days);
+
+            let (fresh, stale): (Vec<Snapshot>, Vec<Snapshot>) = kept
+                .into_iter()
+                .partition(|snap| snap.snapshot.time >= cutoff || snap.is_any_of(&protected));
+
+            kept = fresh;
+            dropped.extend(stale);
+        }
+
+        self.drop_and_prune(&repo, &dropped)?;
+
+        if let Some(max_size) = retention.max_size {
+            kept.sort_by(|a, b| a.snapshot.time.cmp(&b.snapshot.time));
+
+            for snap in kept {
+                if dir_size(&self.path)? <= max_size.as_u64() {
+                    break;
+                }
+
+                if snap.is_any_of(&protected) {
+                    continue;
+                }
+
+                self.drop_and_prune(&repo, &[snap.clone()])?;
+                dropped.push(snap);
+            }
+        }
+
+        let reclaimed = size_before.saturating_sub(dir_size(&self.path)?);
+
+        Ok((dropped, reclaimed))
+    }
+
+    fn drop_and_prune(
+        &self,
+        repo: &RusticRepo<OpenStatus>,
+        snapshots: &[Snapshot],
+    ) -> anyhow::Result<()> {
+        if snapshots.is_empty() {
+            return Ok(());
+        }
+
+        let ids: Vec<Id> = snapshots.iter().map(|snap| snap.id).collect();
+
+        repo.delete_snapshots(&ids)?;
+
+        prune_repo(repo)
     }
 
     pub fn restore(&self, project: &Project, snap_id: Id) -> anyhow::Result<()> {
         info!("Restoring stash...");
         let repo = self.open_stash(project)?;
-        let snapshot = Snapshot::from_snapshot_id(&repo.repo, snap_id)?;
+        let snapshot = repo.get_snapshot(snap_id)?;
 
-        project.restore_from_snapshot(&repo, &snapshot)?;
+        // Partial stashes only restore what they contain
+        let options = SeedOptions {
+            scope: snapshot.get_scope(),
+            ..Default::default()
+        };
+
+        project.restore_from_snapshot_with(&repo, &snapshot, &options)?;
 
         Ok(())
     }
 
+    /// Run `f`, restoring the stash `stash_id` if it fails. The original error is returned either way, along with
+    /// the outcome of the rollback.
+    pub fn with_rollback<T>(
+        &self,
+        project: &Project,
+        stash_id: Id,
+        f: impl FnOnce() -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        f().map_err(|e| {
+            warn!("{}", e);
+            warn!("Rolling back to stash {}...", stash_id);
+
+            match self.restore(project, stash_id) {
+                Ok(_) => {
+                    info!("Rolled back to stash {}", stash_id);
+
+                    let message = format!("{}. Rolled back to stash {}", e, stash_id);
+                    e.context(message)
+                }
+                Err(rollback) => {
+                    let message = format!(
+                        "{}. Rolling back to stash {} also failed: {}. Try `sprout un-stash {}`",
+                        e, stash_id, rollback, stash_id
+                    );
+                    e.context(message)
+                }
+            }
+        })
+    }
+
+    /// Report what restoring a stash would change, without touching disk or the database
+    pub fn plan_restore(&self, project: &Project, snap_id: Id) -> anyhow::Result<ChangeReport> {
+        let repo = self.open_stash(project)?;
+        let snapshot = repo.get_snapshot(snap_id)?;
+
+        let options = SeedOptions {
+            scope: snapshot.get_scope(),
+            ..Default::default()
+        };
+
+        project.plan_restore_from_snapshot(&repo, &snapshot, &options)
+    }
+
     pub fn get_latest_stash(&self, project: &Project) -> anyhow::Result<Snapshot> {
         let repo = self.open_stash(project)?;
 
@@ -122,16 +292,31 @@ impl Stash {
         Ok(snapshot)
     }
 
-    pub fn drop(&self, id: Id) -> anyhow::Result<()> {
+    /// IDs of pinned stashes, across all projects
+    pub fn get_pinned(&self) -> anyhow::Result<HashSet<Id>> {
+        Ok(pinned_ids(&get_pin_records(
+            &self.direct_open_stash()?,
+            None,
+        )?))
+    }
+
+    /// Drop a stash. Pinned stashes are only dropped if `force` is set.
+    pub fn drop(&self, id: Id, force: bool) -> anyhow::Result<()> {
         let repo = self.direct_open_stash()?;
 
         let snapshot = Snapshot::from_snapshot_id(&repo, id)?;
+        let snapshots = std::slice::from_ref(&snapshot);
 
-        let repo = repo.open()?;
+        refuse_pinned(
+            snapshots,
+            &pinned_ids(&get_pin_records(&repo, None)?),
+            force,
+        )?;
 
         let ids = vec![snapshot.snapshot.id];
 
         repo.delete_snapshots(&ids)?;
+        delete_pin_records(&repo, snapshots)?;
 
         Ok(())
     }
@@ -145,3 +330,21 @@ impl Stash {
         repo.get_all_snapshots_for_project(project)
     }
 }
+
+/// Total size of all files below `path`
+fn dir_size(path: &Path) -> anyhow::Result<u64> {
+    let mut size = 0;
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+
+    Ok(size)
+}
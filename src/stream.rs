@@ -0,0 +1,203 @@
+use std::{
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender},
+        Mutex,
+    },
+    thread,
+};
+
+/// Bytes buffered by a `PipeWriter` before they're handed to the reader
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Chunks in flight between two threads, before the writer blocks
+const PIPE_DEPTH: usize = 16;
+
+/// A step in a pipeline, reading from the step before and writing to the step after
+pub type Stage<'a> =
+    Box<dyn FnOnce(&mut dyn Read, &mut dyn Write) -> anyhow::Result<()> + Send + 'a>;
+
+/// Box up a pipeline step
+pub fn stage<'a>(
+    f: impl FnOnce(&mut dyn Read, &mut dyn Write) -> anyhow::Result<()> + Send + 'a,
+) -> Stage<'a> {
+    Box::new(f)
+}
+
+/// The writing end of an in-memory pipe between threads. If it's dropped without being finished, the reader sees
+/// an error rather than the end of the stream, so a failure upstream can't pass for a short dump.
+pub struct PipeWriter {
+    sender: SyncSender<io::Result<Vec<u8>>>,
+    buffer: Vec<u8>,
+    finished: bool,
+}
+
+pub struct PipeReader {
+    receiver: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+pub fn pipe() -> (PipeWriter, PipeReader) {
+    let (sender, receiver) = sync_channel(PIPE_DEPTH);
+
+    (
+        PipeWriter {
+            sender,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            finished: false,
+        },
+        PipeReader {
+            receiver,
+            chunk: vec![],
+            position: 0,
+        },
+    )
+}
+
+impl PipeWriter {
+    fn send(&mut self, chunk: io::Result<Vec<u8>>) -> io::Result<()> {
+        self.sender
+            .send(chunk)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The reader has gone away"))
+    }
+
+    /// Hand over anything buffered and close the pipe, so the reader sees the end of the stream
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush()?;
+        self.finished = true;
+
+        Ok(())
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+            self.send(Ok(chunk))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.sender.send(Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The stream was cut short",
+            )));
+        }
+    }
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.position = 0;
+                }
+                // The writer finished
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len() - self.position);
+        buf[..len].copy_from_slice(&self.chunk[self.position..self.position + len]);
+        self.position += len;
+
+        Ok(len)
+    }
+}
+
+/// A reader which fails once `abort` is raised, so a stream stops part way when work running alongside it fails
+pub struct AbortableReader<'a, R: Read + ?Sized> {
+    inner: &'a mut R,
+    abort: &'a AtomicBool,
+}
+
+impl<'a, R: Read + ?Sized> AbortableReader<'a, R> {
+    pub fn new(inner: &'a mut R, abort: &'a AtomicBool) -> Self {
+        Self { inner, abort }
+    }
+}
+
+impl<R: Read + ?Sized> Read for AbortableReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.abort.load(Ordering::SeqCst) {
+            return Err(io::Error::other(
+                "Stopped, as a step running alongside failed",
+            ));
+        }
+
+        self.inner.read(buf)
+    }
+}
+
+/// Stream from `source`, through each of `stages`, into `sink`, each on its own thread. If any step fails the rest
+/// are cut off, and the first error is returned - later ones are just fallout from it.
+pub fn run_pipeline<'a>(
+    source: impl FnOnce(&mut dyn Write) -> anyhow::Result<()> + Send + 'a,
+    stages: Vec<Stage<'a>>,
+    sink: impl FnOnce(&mut dyn Read) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let first_error: Mutex<Option<anyhow::Error>> = Mutex::new(None);
+
+    let fail = |e: anyhow::Error| {
+        first_error.lock().unwrap().get_or_insert(e);
+    };
+
+    thread::scope(|s| {
+        let (mut writer, mut reader) = pipe();
+
+        s.spawn(move || match source(&mut writer) {
+            Ok(()) => {
+                if let Err(e) = writer.finish() {
+                    fail(e.into());
+                }
+            }
+            Err(e) => fail(e),
+        });
+
+        for stage in stages {
+            let (mut next_writer, next_reader) = pipe();
+            let mut stage_reader = std::mem::replace(&mut reader, next_reader);
+
+            s.spawn(move || match stage(&mut stage_reader, &mut next_writer) {
+                Ok(()) => {
+                    if let Err(e) = next_writer.finish() {
+                        fail(e.into());
+                    }
+                }
+                Err(e) => fail(e),
+            });
+        }
+
+        if let Err(e) = sink(&mut reader) {
+            fail(e);
+        }
+
+        // Unblock anything still writing to us
+        drop(reader);
+    });
+
+    match first_error.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
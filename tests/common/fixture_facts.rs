@@ -1,5 +1,5 @@
 use anyhow::Result;
-use sprout::facts::ProjectFactProvider;
+use sprout::{facts::ProjectFactProvider, multisite::Site};
 use std::{
     fs,
     path::{Path, PathBuf},
@@ -11,6 +11,8 @@ pub struct FixtureFacts {
     pub is_wordpress_installed: bool,
     pub home_url: String,
     pub hash: Option<String>,
+    pub sites: Vec<Site>,
+    pub table_prefix: String,
 }
 
 impl FixtureFacts {}
@@ -36,19 +38,47 @@ impl ProjectFactProvider for FixtureFacts {
             .to_string())
     }
 
+    fn get_table_prefix(&self) -> Result<String> {
+        Ok(self.table_prefix.to_owned())
+    }
+
+    fn get_sites(&self) -> Result<Vec<Site>> {
+        Ok(self.sites.to_owned())
+    }
+
     fn generate_unique_hash(&self) -> Result<Option<String>> {
         Ok(self.hash.to_owned())
     }
 
-    fn dump_database(&self, output_path: &Path, _home_url: &str) -> Result<()> {
-        fs::write(output_path, "-- This is a test fixture\nSHOW DATABASES;")?;
-        Ok(())
-    }
-    fn import_database(&self, _import_path: &Path) -> Result<()> {
+    fn dump_database(&self, output_path: &Path) -> Result<()> {
+        let mut dump = format!(
+            "-- This is a test fixture\nINSERT INTO `{}options` VALUES (1,'home','{}'),(2,'widget','a:1:{{s:3:\\\"url\\\";s:{}:\\\"{}\\\";}}');\n",
+            self.table_prefix,
+            self.home_url,
+            self.home_url.len(),
+            self.home_url
+        );
+
+        for site in &self.sites {
+            dump.push_str(&format!(
+                "INSERT INTO `{}blogs` VALUES ({},1,'{}','{}');\n",
+                self.table_prefix, site.id, site.domain, site.path
+            ));
+
+            if site.id > 1 {
+                dump.push_str(&format!(
+                    "INSERT INTO `{}{}_options` VALUES (1,'home','https://{}{}');\n",
+                    self.table_prefix, site.id, site.domain, site.path
+                ));
+            }
+        }
+
+        fs::write(output_path, dump)?;
         Ok(())
     }
 
-    fn postprocess_database(&self, _home_url: &str) -> Result<()> {
+    fn import_database(&self, import_path: &Path) -> Result<()> {
+        fs::copy(import_path, self.path.join("imported.sql"))?;
         Ok(())
     }
 }
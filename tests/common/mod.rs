@@ -8,6 +8,7 @@ use rustic_backend::BackendOptions;
 use rustic_core::{ConfigOptions, KeyOptions, RepositoryOptions};
 use sprout::engine::Engine;
 use sprout::facts::ProjectFactProvider;
+use sprout::multisite::Site;
 use sprout::repo::definition::RepositoryDefinition;
 use sprout::repo::ProjectRepository;
 use tempfile::TempDir;
@@ -80,6 +81,20 @@ pub struct TestProjectContext {
 
 impl TestProjectContext {
     pub fn new(home_url: &str) -> Result<Self> {
+        Self::with_sites(home_url, vec![])
+    }
+
+    /// A multisite network, with `sites` in `wp_blogs`
+    pub fn with_sites(home_url: &str, sites: Vec<Site>) -> Result<Self> {
+        Self::build(home_url, sites, "wp_")
+    }
+
+    /// A project whose tables use `table_prefix`, rather than `wp_`
+    pub fn with_table_prefix(home_url: &str, table_prefix: &str) -> Result<Self> {
+        Self::build(home_url, vec![], table_prefix)
+    }
+
+    fn build(home_url: &str, sites: Vec<Site>, table_prefix: &str) -> Result<Self> {
         let project_path = TempDir::new()?;
 
         Ok(Self {
@@ -94,6 +109,8 @@ impl TestProjectContext {
                         .map(|x| x as char)
                         .collect(),
                 ),
+                sites,
+                table_prefix: table_prefix.to_string(),
             }),
             project_path,
         })
@@ -0,0 +1,16 @@
+<?php
+/** The name of the database for WordPress */
+define( 'DB_NAME', 'sprout_db' );
+
+/** Database username */
+define( 'DB_USER', "sprout" );
+
+/** Database password */
+define( 'DB_PASSWORD', 'p@ss\'word' );
+
+/** Database hostname */
+define( 'DB_HOST', '127.0.0.1:3307' );
+
+$table_prefix = 'wpx7_';
+
+require_once ABSPATH . 'wp-settings.php';
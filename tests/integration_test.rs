@@ -1,14 +1,48 @@
 mod common;
 
-use std::{fs, path::Path};
+use std::{
+    fs,
+    io::{Cursor, Read},
+    path::Path,
+    sync::Arc,
+};
 
 use crate::common::{content_generator, TestProjectContext, TestResult};
 use assert_cmd::Command;
+use bytesize::ByteSize;
 use common::TestContext;
 use predicates::prelude::*;
 
 use rustic_backend::BackendOptions;
-use sprout::{project::Project, repo::definition::RepositoryDefinition, stash::Stash};
+use sprout::{
+    branch::validate_branch,
+    diff::SnapshotDiff,
+    dump::{
+        database_file, filter_tables, split_to_dir, table_digests, DatabaseLayout,
+        INSERT_BATCH_MAX_ROWS, INSERT_BATCH_MIN_ROWS,
+    },
+    facts::{
+        mysql::{DbCredentials, MySql},
+        run_command, run_command_from, run_command_to, verify_dump, verify_dump_stream, FactError,
+        ProjectFactProvider,
+    },
+    filter::{AppliedRules, PathFilter, TableSelection, UploadsRules, IGNORE_FILE},
+    hooks::Hook,
+    multisite::{network_uploads_dir, Site},
+    placeholder::Placeholders,
+    prefix::rewrite_prefix,
+    project::{Project, SeedOptions},
+    repo::{definition::RepositoryDefinition, refuse_pinned},
+    report::FileChange,
+    retention::KeepPolicy,
+    sanitise::{Fake, SanitiseProfile, Sanitiser},
+    search_replace::SearchReplace,
+    snapshot::{validate_tag, Annotation, Scope},
+    staging::RestoreLock,
+    stash::Stash,
+    stream::{run_pipeline, stage},
+};
+use tempfile::TempDir;
 
 #[test]
 fn test_prints_usage() -> TestResult {
@@ -19,6 +53,28 @@ fn test_prints_usage() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn test_reports_invalid_project_config() -> TestResult {
+    let home = TempDir::new()?;
+    let project = TempDir::new()?;
+
+    // A typo mustn't quietly switch the project to WP-CLI
+    fs::write(
+        project.path().join("sprout.yaml"),
+        "name: typo\nfacts: mysqll\n",
+    )?;
+
+    Command::cargo_bin("sprout")?
+        .env("SPROUT_HOME", home.path())
+        .arg("--path")
+        .arg(project.path())
+        .arg("ls")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown variant `mysqll`"));
+    Ok(())
+}
+
 #[test]
 fn test_creates_sprout_home() -> TestResult {
     let ctx = TestContext::new()?;
@@ -347,6 +403,55 @@ fn test_project_snapshot_respected() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn test_snapshot_failure_removes_fragments() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://invalid-project.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let mut project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    // Fail the database dump, while the uploads backup runs alongside it
+    project.config.sanitise = Some("missing-profile".to_string());
+
+    let repo = project.open_repo("TEST")?;
+
+    assert!(
+        repo.snapshot(true).is_err(),
+        "Snapshot should fail when the database dump does"
+    );
+
+    assert!(
+        repo.repo.clone().open()?.get_all_snapshots()?.is_empty(),
+        "Fragments from a failed snapshot should be deleted"
+    );
+
+    project.config.sanitise = None;
+
+    let repo = project.open_repo("TEST")?;
+    let snapshot = repo.snapshot(true)?;
+
+    assert_eq!(
+        snapshot.get_total_files(),
+        4, // 3 (fixture) + 1 (db)
+        "Snapshot has wrong fixture file count"
+    );
+
+    assert_eq!(
+        repo.repo.clone().open()?.get_all_snapshots()?.len(),
+        1,
+        "Only the bundle should be left in the repo"
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_seeding() -> TestResult {
     let ctx = TestContext::new()?;
@@ -496,6 +601,100 @@ fn test_seeding_intersect() -> TestResult {
     Ok(())
 }
 
+#[test]
+fn test_seeding_staged_swap() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://invalid-project.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let uploads_dir = Path::new(&project_ctx.facts.get_uploads_dir()?).to_path_buf();
+    let parent = uploads_dir.parent().unwrap().to_path_buf();
+
+    let project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    let repo = project.open_repo("TEST")?;
+    let snapshot = repo.snapshot(true)?;
+
+    // Leftovers from an interrupted restore
+    fs::create_dir(parent.join(".uploads.sprout-staging-1"))?;
+    fs::write(parent.join(".uploads.sprout-staging-1/junk.txt"), "Junk")?;
+
+    fs::write(uploads_dir.join("3.txt"), "Three changed")?;
+    fs::write(uploads_dir.join("4.txt"), "Four")?;
+
+    #[cfg(unix)]
+    let inode = |path: &Path| -> std::io::Result<u64> {
+        use std::os::unix::fs::MetadataExt;
+        Ok(fs::metadata(path)?.ino())
+    };
+
+    #[cfg(unix)]
+    let unchanged_inode = inode(&uploads_dir.join("1.txt"))?;
+
+    // A seed already restoring into the uploads directory blocks another, which mustn't touch its staging directory
+    let lock = RestoreLock::acquire(&uploads_dir)?;
+    let err = project.restore_from_snapshot(&repo, &snapshot).unwrap_err();
+
+    assert!(
+        err.to_string()
+            .starts_with("Another seed is restoring into"),
+        "Unexpected error: {}",
+        err
+    );
+    assert!(parent.join(".uploads.sprout-staging-1").exists());
+
+    drop(lock);
+
+    project.restore_from_snapshot(&repo, &snapshot)?;
+
+    assert_eq!(
+        fs::read_to_string(uploads_dir.join("3.txt"))?,
+        "Three A",
+        "Changed file should be restored"
+    );
+    assert!(
+        !uploads_dir.join("4.txt").exists(),
+        "File missing from the snapshot should be removed"
+    );
+
+    #[cfg(unix)]
+    assert_eq!(
+        inode(&uploads_dir.join("1.txt"))?,
+        unchanged_inode,
+        "Unchanged file should be hardlinked, not restored"
+    );
+
+    let leftovers: Vec<_> = fs::read_dir(&parent)?
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|name| name.contains(".sprout-"))
+        .collect();
+
+    assert!(
+        leftovers.is_empty(),
+        "Staging directories should be cleaned up. Saw: {:?}",
+        leftovers
+    );
+
+    // Interrupted mid-swap, with the uploads directory moved aside
+    fs::rename(&uploads_dir, parent.join(".uploads.sprout-old-1"))?;
+
+    project.restore_from_snapshot(&repo, &snapshot)?;
+
+    assert!(
+        uploads_dir.join("1.txt").is_file() && !parent.join(".uploads.sprout-old-1").exists(),
+        "Uploads moved aside should be put back, then restored"
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_project_path_escape_safety() -> TestResult {
     let ctx = TestContext::new()?;
@@ -540,3 +739,1889 @@ fn test_project_path_escape_safety() -> TestResult {
 
     Ok(())
 }
+
+#[test]
+fn test_mysql_credentials_from_wp_config() -> TestResult {
+    let project_ctx = TestProjectContext::new("https://mysql-credentials.test")?;
+
+    project_ctx.apply_fixture("06_wp_config")?;
+
+    let facts = MySql {
+        path: project_ctx.project_path.path().to_path_buf(),
+    };
+
+    assert_eq!(
+        facts.credentials()?,
+        DbCredentials {
+            name: "sprout_db".to_string(),
+            user: "sprout".to_string(),
+            password: "p@ss'word".to_string(),
+            host: "127.0.0.1".to_string(),
+            port: Some(3307),
+            socket: None,
+            table_prefix: "wpx7_".to_string(),
+        },
+        "Credentials were not read correctly from wp-config.php"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_mysql_credentials_from_bedrock_env() -> TestResult {
+    let project_ctx = TestProjectContext::new("https://mysql-credentials.test")?;
+
+    project_ctx.apply_fixture("07_bedrock_env")?;
+
+    let facts = MySql {
+        path: project_ctx.project_path.path().to_path_buf(),
+    };
+
+    assert_eq!(
+        facts.credentials()?,
+        DbCredentials {
+            name: "bedrock".to_string(),
+            user: "root".to_string(),
+            password: "secret".to_string(),
+            host: "localhost".to_string(),
+            port: None,
+            socket: Some("/var/run/mysqld/mysqld.sock".to_string()),
+            table_prefix: "wp_".to_string(),
+        },
+        "Credentials were not read correctly from .env"
+    );
+
+    Ok(())
+}
+
+/// Runs a dump / import round trip against a real MySQL or MariaDB server.
+/// Skipped unless SPROUT_TEST_DB_NAME is set (along with SPROUT_TEST_DB_USER, SPROUT_TEST_DB_PASSWORD and SPROUT_TEST_DB_HOST).
+#[test]
+fn test_mysql_dump_and_import() -> TestResult {
+    let db_name = match std::env::var("SPROUT_TEST_DB_NAME") {
+        Ok(name) => name,
+        Err(_) => return Ok(()),
+    };
+
+    let project_ctx = TestProjectContext::new("https://mysql-roundtrip.test")?;
+
+    fs::write(
+        project_ctx.project_path.path().join("wp-config.php"),
+        format!(
+            "<?php\ndefine('DB_NAME', '{}');\ndefine('DB_USER', '{}');\ndefine('DB_PASSWORD', '{}');\ndefine('DB_HOST', '{}');\n$table_prefix = 'sprttest_';\n",
+            db_name,
+            std::env::var("SPROUT_TEST_DB_USER").unwrap_or("root".to_string()),
+            std::env::var("SPROUT_TEST_DB_PASSWORD").unwrap_or_default(),
+            std::env::var("SPROUT_TEST_DB_HOST").unwrap_or("127.0.0.1".to_string()),
+        ),
+    )?;
+
+    let facts = MySql {
+        path: project_ctx.project_path.path().to_path_buf(),
+    };
+
+    let seed = project_ctx.project_path.path().join("seed.sql");
+
+    fs::write(
+        &seed,
+        "DROP TABLE IF EXISTS sprttest_options;\nCREATE TABLE sprttest_options (option_id BIGINT PRIMARY KEY AUTO_INCREMENT, option_name VARCHAR(191), option_value LONGTEXT);\nINSERT INTO sprttest_options (option_name, option_value) VALUES ('home', 'https://mysql-roundtrip.test'), ('siteurl', 'https://mysql-roundtrip.test/wp');\n",
+    )?;
+
+    facts.import_database(&seed)?;
+
+    assert!(
+        facts.is_wordpress_installed()?,
+        "Seeded database should be detected"
+    );
+
+    let dump = project_ctx.project_path.path().join("dump.sql");
+
+    facts.dump_database(&dump)?;
+
+    assert!(
+        fs::read_to_string(&dump)?.contains("'https://mysql-roundtrip.test/wp'"),
+        "Dump should contain the seeded options"
+    );
+
+    let rewritten = project_ctx.project_path.path().join("rewritten.sql");
+
+    SearchReplace::new(&[("https://mysql-roundtrip.test", "https://seeded.test")])
+        .rewrite_file(&dump, &rewritten)?;
+
+    facts.import_database(&rewritten)?;
+
+    assert_eq!(
+        facts.get_home_url()?,
+        "https://seeded.test",
+        "Home URL should be rewritten after import"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_search_replace_serialized() -> TestResult {
+    let search_replace = SearchReplace::new(&[("https://example.com", "https://example.test")]);
+
+    let replaced = search_replace.replace_value(
+        b"a:2:{s:3:\"url\";s:23:\"https://example.com/foo\";s:6:\"nested\";s:37:\"a:1:{i:0;s:19:\"https://example.com\";}\";}",
+    );
+
+    assert_eq!(
+        String::from_utf8_lossy(&replaced),
+        "a:2:{s:3:\"url\";s:24:\"https://example.test/foo\";s:6:\"nested\";s:38:\"a:1:{i:0;s:20:\"https://example.test\";}\";}",
+        "Serialised lengths should be updated, including nested serialised strings"
+    );
+
+    let replaced = search_replace.replace_value(b"s:5:\"https://example.com is broken\";");
+
+    assert_eq!(
+        String::from_utf8_lossy(&replaced),
+        "s:5:\"https://example.test is broken\";",
+        "Invalid serialised data should fall back to a plain replacement"
+    );
+
+    let mut dump = vec![];
+
+    search_replace.rewrite_dump(
+        &b"-- Don't touch https://example.com in comments\nINSERT INTO `wp_options` VALUES (1,'home','https://example.com'),(2,'widget','a:1:{s:3:\\\"url\\\";s:19:\\\"https://example.com\\\";}'),(3,'quote','it\\'s https://example.com');\n"[..],
+        &mut dump,
+    )?;
+
+    assert_eq!(
+        String::from_utf8_lossy(&dump),
+        "-- Don't touch https://example.com in comments\nINSERT INTO `wp_options` VALUES (1,'home','https://example.test'),(2,'widget','a:1:{s:3:\\\"url\\\";s:20:\\\"https://example.test\\\";}'),(3,'quote','it\\'s https://example.test');\n",
+        "SQL string literals should be rewritten and re-escaped"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_database_url_placeholder() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://placeholder-project.test")?;
+
+    ctx.setup_single_repo()?;
+
+    let mut project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    project.determine_home_url()?;
+
+    let dump = project_ctx.project_path.path().join("database.sql");
+
+    project.dump_database(&dump)?;
+
+    let contents = fs::read_to_string(&dump)?;
+
+    assert!(
+        contents.contains("'__SPROUT__HOME__'")
+            && contents.contains("s:16:\\\"__SPROUT__HOME__\\\"")
+            && !contents.contains("placeholder-project.test"),
+        "Home URL should be replaced with a placeholder in the dump. Saw: {}",
+        contents
+    );
+
+    project.home_url = "http://local.test".to_string();
+    project.import_database(dump)?;
+
+    let imported = fs::read_to_string(project_ctx.project_path.path().join("imported.sql"))?;
+
+    assert!(
+        imported.contains("'http://local.test'")
+            && imported.contains("s:17:\\\"http://local.test\\\""),
+        "Placeholder should be replaced with the local home URL on import. Saw: {}",
+        imported
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_verify_dump() -> TestResult {
+    let dir = tempfile::tempdir()?;
+    let dump = dir.path().join("database.sql");
+
+    fs::write(&dump, "")?;
+
+    assert!(
+        matches!(verify_dump(&dump), Err(FactError::EmptyDump(_))),
+        "A zero-byte dump should be rejected"
+    );
+
+    fs::write(&dump, "-- Just a comment\n\n")?;
+
+    assert!(
+        matches!(verify_dump(&dump), Err(FactError::EmptyDump(_))),
+        "A dump with no statements should be rejected"
+    );
+
+    fs::write(
+        &dump,
+        "DROP TABLE IF EXISTS `wp_options`;\nINSERT INTO `wp_options` VALUES (1,'home','https://",
+    )?;
+
+    assert!(
+        matches!(verify_dump(&dump), Err(FactError::TruncatedDump(_))),
+        "A dump that stops mid-statement should be rejected"
+    );
+
+    fs::write(
+        &dump,
+        "INSERT INTO `wp_options` VALUES (1,'home','https://example.test');\n/*!40101 SET SQL_MODE=@OLD_SQL_MODE */;\n\n-- Dump completed on 2024-02-20 10:00:00\n",
+    )?;
+
+    assert!(verify_dump(&dump).is_ok(), "A complete dump should pass");
+
+    Ok(())
+}
+
+#[test]
+fn test_failed_command_captures_stderr() -> TestResult {
+    let mut cmd = std::process::Command::new("sh");
+
+    cmd.arg("-c").arg("echo 'something went wrong' >&2; exit 3");
+
+    match run_command(&mut cmd) {
+        Err(FactError::CommandFailed { code, stderr, .. }) => {
+            assert_eq!(code, Some(3), "Exit code should be captured");
+            assert_eq!(stderr, "something went wrong", "Stderr should be captured");
+        }
+        _ => panic!("A non-zero exit status should return FactError::CommandFailed"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_streamed_command_and_dump() -> TestResult {
+    let mut cmd = std::process::Command::new("sh");
+    cmd.arg("-c")
+        .arg("echo 'INSERT INTO `wp_options` VALUES (1);'");
+
+    let mut dumped = vec![];
+
+    run_pipeline(
+        |writer| Ok(run_command_to(&mut cmd, writer)?),
+        vec![stage(|reader, writer| {
+            Ok(verify_dump_stream(reader, writer)?)
+        })],
+        |reader| {
+            reader.read_to_end(&mut dumped)?;
+            Ok(())
+        },
+    )?;
+
+    assert_eq!(
+        String::from_utf8(dumped)?,
+        "INSERT INTO `wp_options` VALUES (1);\n",
+        "Command output should stream through the pipeline"
+    );
+
+    let mut truncated = Cursor::new(b"INSERT INTO `wp_options` VALUES (1),\n(2".to_vec());
+
+    assert!(
+        matches!(
+            verify_dump_stream(&mut truncated, &mut std::io::sink()),
+            Err(FactError::TruncatedExport)
+        ),
+        "A truncated export should be rejected"
+    );
+
+    assert!(
+        matches!(
+            verify_dump_stream(&mut std::io::empty(), &mut std::io::sink()),
+            Err(FactError::EmptyExport)
+        ),
+        "An empty export should be rejected"
+    );
+
+    let mut cmd = std::process::Command::new("sh");
+    cmd.arg("-c")
+        .arg("head -c 1 > /dev/null; echo 'ERROR 1064' >&2; exit 1");
+
+    let lines = "INSERT INTO `wp_options` VALUES (1);\n".repeat(100_000);
+
+    match run_command_from(&mut cmd, &mut lines.as_bytes()) {
+        Err(FactError::CommandFailed { stderr, .. }) => {
+            assert_eq!(stderr, "ERROR 1064", "Stderr should be captured");
+        }
+        _ => panic!("A failed import should return FactError::CommandFailed"),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_forget_respects_policy_and_pins() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://forget-project.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let mut project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    let repo = project.open_repo("TEST")?;
+
+    let snapshot_1 = repo.snapshot(true)?;
+    let snapshot_2 = repo.snapshot(true)?;
+    let snapshot_3 = repo.snapshot(true)?;
+
+    project.update_snapshot_id(snapshot_1.id, snapshot_1.get_branch()?)?;
+
+    let protected = project.get_referenced_snapshots()?;
+
+    assert!(
+        protected.contains(&snapshot_1.id),
+        "Snapshot pinned in sprout.yaml should be protected"
+    );
+
+    let repo = project.open_repo("TEST")?;
+
+    let (keep, forget) = repo.plan_forget(
+        &KeepPolicy {
+            last: Some(1),
+            ..KeepPolicy::default()
+        },
+        None,
+        &protected,
+    )?;
+
+    let keep: Vec<_> = keep.iter().map(|snap| snap.id).collect();
+    let forget_ids: Vec<_> = forget.iter().map(|snap| snap.id).collect();
+
+    assert_eq!(
+        keep,
+        vec![snapshot_3.id, snapshot_1.id],
+        "Latest and pinned snapshots should be kept"
+    );
+
+    assert_eq!(
+        forget_ids,
+        vec![snapshot_2.id],
+        "Only the unpinned, older snapshot should be forgotten"
+    );
+
+    repo.forget(&forget)?;
+
+    let (snapshots, _) = project.get_all_snapshots(&repo)?;
+
+    assert_eq!(
+        snapshots.len(),
+        2,
+        "Expected 2 snapshots after forgetting, got {}",
+        snapshots.len()
+    );
+
+    project.restore_from_snapshot(&repo, &project.get_active_snapshot(&repo)?)?;
+
+    assert!(
+        Path::new(&project_ctx.facts.get_uploads_dir()?)
+            .join("1.txt")
+            .exists(),
+        "Pinned snapshot should still restore after pruning"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_stash_retention() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://stash-retention.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let mut sprout_config = ctx.engine.get_config()?;
+    sprout_config.stash_retention.max_count = Some(2);
+    ctx.engine.write_config(&sprout_config)?;
+
+    let project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    let stash = Stash::new(&ctx.engine, ctx.engine.get_stash_path())?;
+
+    stash.stash(&project)?;
+    stash.stash(&project)?;
+    stash.stash(&project)?;
+
+    let (stashes, _) = stash.get_all_stashes_for_project(&project)?;
+
+    assert_eq!(
+        stashes.len(),
+        2,
+        "Expected 2 stashes after retention, got {}",
+        stashes.len()
+    );
+
+    let latest = stash.get_latest_stash(&project)?;
+
+    sprout_config.stash_retention.max_count = Some(1);
+    ctx.engine.write_config(&sprout_config)?;
+
+    let (dropped, _) = stash.apply_retention(None)?;
+
+    assert_eq!(dropped.len(), 1, "Expected 1 stash to be dropped on demand");
+
+    let (stashes, _) = stash.get_all_stashes_for_project(&project)?;
+
+    assert_eq!(
+        stashes.first().map(|snap| snap.id),
+        Some(latest.id),
+        "The newest stash should survive"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_selective_seed() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://selective-seed.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    let repo = project.open_repo("TEST")?;
+    let snapshot = repo.snapshot(true)?;
+
+    let uploads_dir = Path::new(&project_ctx.facts.get_uploads_dir()?).to_path_buf();
+    let imported = project_ctx.project_path.path().join("imported.sql");
+
+    project_ctx.wipe_uploads()?;
+
+    project.restore_from_snapshot_with(
+        &repo,
+        &snapshot,
+        &SeedOptions {
+            scope: Scope::database_only(),
+            ..Default::default()
+        },
+    )?;
+
+    assert!(imported.is_file(), "Database was not imported");
+    assert!(
+        !uploads_dir.join("1.txt").exists(),
+        "Uploads should not be restored by a database-only seed"
+    );
+
+    fs::remove_file(&imported)?;
+
+    project.restore_from_snapshot_with(
+        &repo,
+        &snapshot,
+        &SeedOptions {
+            scope: Scope::uploads_only(),
+            ..Default::default()
+        },
+    )?;
+
+    assert!(
+        uploads_dir.join("1.txt").exists(),
+        "1.txt not restored by an uploads-only seed"
+    );
+    assert!(
+        !imported.exists(),
+        "Database should not be imported by an uploads-only seed"
+    );
+
+    let stash = Stash::new(&ctx.engine, ctx.engine.get_stash_path())?;
+    stash.stash_with(&project, &Scope::uploads_only())?;
+
+    let stashed = stash.get_latest_stash(&project)?;
+
+    assert_eq!(
+        stashed.get_scope(),
+        Scope::uploads_only(),
+        "Partial stash should record its scope"
+    );
+
+    project_ctx.wipe_uploads()?;
+    stash.restore(&project, stashed.id)?;
+
+    assert!(
+        uploads_dir.join("1.txt").exists(),
+        "1.txt not restored from partial stash"
+    );
+    assert!(
+        !imported.exists(),
+        "Restoring an uploads-only stash should not import a database"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_filtered_seed() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://filtered-seed.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    let repo = project.open_repo("TEST")?;
+    let snapshot = repo.snapshot(true)?;
+
+    let uploads_dir = Path::new(&project_ctx.facts.get_uploads_dir()?).to_path_buf();
+
+    project_ctx.wipe_uploads()?;
+    project_ctx.apply_fixture("02_upload_diff_b")?;
+    fs::create_dir(uploads_dir.join("[nested]"))?;
+    fs::write(uploads_dir.join("[nested]/5*.txt"), "Five")?;
+
+    project.restore_from_snapshot_with(
+        &repo,
+        &snapshot,
+        &SeedOptions {
+            scope: Scope::uploads_only(),
+            filter: PathFilter::new(&["1.*"], &[])?,
+            ..Default::default()
+        },
+    )?;
+
+    assert!(
+        uploads_dir.join("1.txt").exists(),
+        "1.txt should be restored by the include filter"
+    );
+    assert!(
+        uploads_dir.join("4.txt").exists(),
+        "4.txt is outside the include filter and should be kept"
+    );
+    assert_eq!(
+        fs::read_to_string(uploads_dir.join("[nested]/5*.txt"))?,
+        "Five",
+        "Files with glob characters in their paths are outside the filter and should be kept"
+    );
+    assert_eq!(
+        fs::read_to_string(uploads_dir.join("2.txt"))?,
+        "Two B",
+        "2.txt is outside the include filter and should not be restored"
+    );
+
+    project.restore_from_snapshot_with(
+        &repo,
+        &snapshot,
+        &SeedOptions {
+            scope: Scope::uploads_only(),
+            filter: PathFilter::new(&[], &["3.txt"])?,
+            ..Default::default()
+        },
+    )?;
+
+    assert_eq!(
+        fs::read_to_string(uploads_dir.join("2.txt"))?,
+        "Two A",
+        "2.txt should be restored"
+    );
+    assert!(
+        !uploads_dir.join("4.txt").exists(),
+        "4.txt is not in the snapshot and should be deleted"
+    );
+    assert_eq!(
+        fs::read_to_string(uploads_dir.join("3.txt"))?,
+        "Three B",
+        "3.txt is excluded and should be left alone"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_uploads_rules() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://uploads-rules.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let uploads_dir = Path::new(&project_ctx.facts.get_uploads_dir()?).to_path_buf();
+    fs::write(uploads_dir.join(IGNORE_FILE), "3.txt\n")?;
+
+    let mut project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    project.config.uploads_rules = UploadsRules {
+        exclude: vec!["2.txt".to_string()],
+        max_file_size: None,
+    };
+
+    let repo = project.open_repo("TEST")?;
+    let snapshot = repo.snapshot(true)?;
+
+    assert_eq!(
+        snapshot.get_rules(),
+        Some(AppliedRules {
+            rules: project.config.uploads_rules.clone(),
+            ignore_files: vec![IGNORE_FILE.into()],
+        }),
+        "Snapshot should record the rules in effect"
+    );
+
+    project_ctx.wipe_uploads()?;
+    project.restore_from_snapshot(&repo, &snapshot)?;
+
+    assert!(
+        uploads_dir.join("1.txt").exists(),
+        "1.txt should be restored"
+    );
+    assert!(
+        !uploads_dir.join("2.txt").exists(),
+        "2.txt is excluded in sprout.yaml and should not be in the snapshot"
+    );
+    assert!(
+        !uploads_dir.join("3.txt").exists(),
+        "3.txt is excluded by .sproutignore and should not be in the snapshot"
+    );
+
+    let rules = AppliedRules {
+        rules: UploadsRules {
+            exclude: vec!["{cache,backups}/**".to_string(), "100%.txt".to_string()],
+            max_file_size: Some(ByteSize::mb(50)),
+        },
+        ignore_files: vec![],
+    };
+
+    assert!(
+        !rules.encode()?.contains(','),
+        "Encoded rules must be safe to use as a tag"
+    );
+    assert_eq!(AppliedRules::decode(&rules.encode()?)?, rules);
+
+    Ok(())
+}
+
+#[test]
+fn test_seed_dry_run() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://dry-run.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    let repo = project.open_repo("TEST")?;
+    let snapshot = repo.snapshot(true)?;
+
+    let uploads_dir = Path::new(&project_ctx.facts.get_uploads_dir()?).to_path_buf();
+
+    project_ctx.wipe_uploads()?;
+    project_ctx.apply_fixture("02_upload_diff_b")?;
+    fs::remove_file(uploads_dir.join("2.txt"))?;
+
+    let report = project.plan_restore_from_snapshot(&repo, &snapshot, &SeedOptions::default())?;
+
+    let paths = |files: &Vec<FileChange>| -> Vec<String> {
+        files
+            .iter()
+            .map(|f| f.path.to_string_lossy().to_string())
+            .collect()
+    };
+
+    assert_eq!(paths(&report.added), vec!["1.txt", "2.txt"]);
+    assert_eq!(paths(&report.changed), vec!["3.txt"]);
+    assert_eq!(paths(&report.deleted), vec!["4.txt"]);
+    assert!(
+        report.database_size.is_some_and(|size| size > 0),
+        "Dry run should report the database size"
+    );
+
+    assert!(
+        uploads_dir.join("4.txt").exists(),
+        "A dry run must not delete anything"
+    );
+    assert!(
+        !uploads_dir.join("1.txt").exists(),
+        "A dry run must not restore anything"
+    );
+    assert!(
+        !project_ctx
+            .project_path
+            .path()
+            .join("imported.sql")
+            .exists(),
+        "A dry run must not import the database"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_diff() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://snapshot-diff.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    let repo = project.open_repo("TEST")?;
+    let snapshot_a = repo.snapshot(true)?;
+
+    project_ctx.wipe_uploads()?;
+    project_ctx.apply_fixture("02_upload_diff_b")?;
+
+    let snapshot_b = repo.snapshot(true)?;
+
+    let diff = SnapshotDiff::new(&repo, &snapshot_a, &snapshot_b)?;
+
+    assert_eq!(
+        diff.added
+            .iter()
+            .map(|f| f.path.to_string_lossy())
+            .collect::<Vec<_>>(),
+        vec!["4.txt"]
+    );
+    assert_eq!(
+        diff.modified
+            .iter()
+            .map(|f| f.path.to_string_lossy())
+            .collect::<Vec<_>>(),
+        vec!["2.txt", "3.txt"]
+    );
+    assert_eq!(
+        diff.removed
+            .iter()
+            .map(|f| f.path.to_string_lossy())
+            .collect::<Vec<_>>(),
+        vec!["1.txt"]
+    );
+    assert!(diff.tables.is_empty(), "Database should be unchanged");
+    assert_eq!(diff.tables_unchanged, 1);
+
+    let before = "DROP TABLE IF EXISTS `wp_options`;\nINSERT INTO `wp_options` VALUES (1,'a');\nUNLOCK TABLES;\nDROP TABLE IF EXISTS `wp_posts`;\nINSERT INTO `wp_posts` VALUES (1,'b');\nUNLOCK TABLES;\n";
+    let after = "DROP TABLE IF EXISTS `wp_options`;\nINSERT INTO `wp_options` VALUES (1,'a');\nUNLOCK TABLES;\nDROP TABLE IF EXISTS `wp_posts`;\nINSERT INTO `wp_posts` VALUES (1,'c');\nUNLOCK TABLES;\n";
+
+    let before = table_digests(before.as_bytes())?;
+    let after = table_digests(after.as_bytes())?;
+
+    assert_eq!(
+        before.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(),
+        vec!["wp_options", "wp_posts"]
+    );
+    assert_eq!(before[0], after[0], "wp_options should be unchanged");
+    assert_ne!(before[1].digest, after[1].digest, "wp_posts should differ");
+
+    Ok(())
+}
+
+#[test]
+fn test_per_table_database_layout() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://per-table.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let mut project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    project.config.database_layout = DatabaseLayout::Tables;
+
+    let repo = project.open_repo("TEST")?;
+    let snapshot = repo.snapshot(true)?;
+
+    project.restore_from_snapshot_with(
+        &repo,
+        &snapshot,
+        &SeedOptions {
+            scope: Scope::database_only(),
+            ..Default::default()
+        },
+    )?;
+
+    let imported = fs::read_to_string(project_ctx.project_path.path().join("imported.sql"))?;
+
+    assert!(
+        imported
+            .contains("INSERT INTO `wp_options` VALUES\n(1,'home','https://per-table.test'),\n"),
+        "Per-table dump should be reassembled with one row per line, got:\n{}",
+        imported
+    );
+
+    // Per-table snapshots are diffed by their table files' content, without restoring them
+    let unchanged = repo.snapshot(true)?;
+    let diff = SnapshotDiff::new(&repo, &snapshot, &unchanged)?;
+
+    assert!(diff.tables.is_empty(), "Database should be unchanged");
+    assert_eq!(diff.tables_unchanged, 1);
+
+    let dir = tempfile::tempdir()?;
+    let dump = dir.path().join("dump.sql");
+
+    fs::write(
+        &dump,
+        "SET NAMES utf8mb4;\nDROP TABLE IF EXISTS `wp_posts`;\nCREATE TABLE `wp_posts` (\n  `post_author` int,\n  CONSTRAINT `fk_author` FOREIGN KEY (`post_author`) REFERENCES `wp_users` (`ID`)\n);\nINSERT INTO `wp_posts` VALUES (1,'a),(b'),(2,'it''s');\nUNLOCK TABLES;\n/*!50003 SET @saved_sql_mode = @@sql_mode */ ;\nDELIMITER ;;\n/*!50003 CREATE*/ /*!50003 TRIGGER `stamp` BEFORE INSERT ON `wp_posts` FOR EACH ROW BEGIN\n  SET NEW.post_author = 1;\nEND */;;\nDELIMITER ;\n/*!50003 SET sql_mode = @saved_sql_mode */ ;\n\n--\n-- Table structure for table `wp_users`\n--\nDROP TABLE IF EXISTS `wp_users`;\nCREATE TABLE `wp_users` (`ID` int);\nINSERT INTO `wp_users` VALUES (1);\nUNLOCK TABLES;\n",
+    )?;
+
+    let split = dir.path().join("split");
+    split_to_dir(&dump, &split)?;
+
+    assert!(split.join("tables/wp_posts.sql").is_file());
+    assert!(split.join("tables/wp_users.sql").is_file());
+
+    let reassembled = fs::read_to_string(database_file(&split)?)?;
+
+    assert!(
+        reassembled.find("CREATE TABLE `wp_users`") < reassembled.find("CREATE TABLE `wp_posts`"),
+        "Referenced tables should be restored first"
+    );
+    assert!(
+        reassembled.contains("INSERT INTO `wp_posts` VALUES\n(1,'a),(b'),\n(2,'it''s');\n"),
+        "Rows should be split one per line, respecting quotes"
+    );
+
+    let posts = fs::read_to_string(split.join("tables/wp_posts.sql"))?;
+
+    assert!(
+        posts.contains("TRIGGER `stamp`")
+            && posts.ends_with("/*!50003 SET sql_mode = @saved_sql_mode */ ;\n"),
+        "Triggers should stay with the table they're on, got:\n{}",
+        posts
+    );
+    assert!(
+        fs::read_to_string(split.join("tables/wp_users.sql"))?.starts_with("\n--\n"),
+        "Comments before a table should still be attached to it"
+    );
+
+    // Batches are the same however mysqldump split the rows between statements
+    let rows = |ids: &[usize]| {
+        ids.iter()
+            .map(|i| format!("({},'post {}')", i, i))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+
+    let batches = |name: &str, statements: &[&[usize]]| -> anyhow::Result<Vec<String>> {
+        let dump = dir.path().join(format!("{}.sql", name));
+
+        fs::write(
+            &dump,
+            statements
+                .iter()
+                .map(|ids| format!("INSERT INTO `wp_links` VALUES {};\n", rows(ids)))
+                .collect::<String>()
+                + "UNLOCK TABLES;\n",
+        )?;
+
+        let split = dir.path().join(name);
+        split_to_dir(&dump, &split)?;
+
+        Ok(fs::read_to_string(split.join("tables/wp_links.sql"))?
+            .split("INSERT INTO")
+            .skip(1)
+            .map(|batch| batch.to_string())
+            .collect())
+    };
+
+    let ids: Vec<usize> = (0..2000).collect();
+    let original = batches("original", &[&ids[..]])?;
+
+    assert_eq!(
+        batches("resplit", &[&ids[..60], &ids[60..]])?,
+        original,
+        "Rows should be batched across statements"
+    );
+    assert!(original.len() > 1);
+    assert!(original
+        .iter()
+        .all(|batch| batch.matches("\n(").count() <= INSERT_BATCH_MAX_ROWS));
+    assert!(original[..original.len() - 1]
+        .iter()
+        .all(|batch| batch.matches("\n(").count() >= INSERT_BATCH_MIN_ROWS));
+
+    // Batch boundaries follow the rows, so adding one mid-table leaves every later batch as it was
+    let inserted_ids = [&ids[..1000], &[5000], &ids[1000..]].concat();
+    let inserted = batches("inserted", &[&inserted_ids[..]])?;
+    let changed = (0..original.len())
+        .find(|&i| original[i] != inserted[i])
+        .unwrap();
+
+    assert!(changed > 0);
+    assert_eq!(original.len(), inserted.len());
+    assert_eq!(
+        original[changed + 1..],
+        inserted[changed + 1..],
+        "Batches after the inserted row's should be untouched"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_seed_table_selection() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://table-selection.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    let repo = project.open_repo("TEST")?;
+    let snapshot = repo.snapshot(true)?;
+
+    project.restore_from_snapshot_with(
+        &repo,
+        &snapshot,
+        &SeedOptions {
+            scope: Scope::database_only(),
+            tables: TableSelection {
+                include: vec![],
+                exclude: vec!["wp_options".to_string()],
+            }
+            .to_filter()?,
+            ..Default::default()
+        },
+    )?;
+
+    let imported = fs::read_to_string(project_ctx.project_path.path().join("imported.sql"))?;
+
+    assert!(
+        !imported.contains("wp_options"),
+        "Excluded table should not be imported, got:\n{}",
+        imported
+    );
+
+    let dir = tempfile::tempdir()?;
+    let dump = dir.path().join("dump.sql");
+    let filtered = dir.path().join("filtered.sql");
+
+    fs::write(
+        &dump,
+        "SET NAMES utf8mb4;\nDROP TABLE IF EXISTS `wp_actionscheduler_logs`;\nINSERT INTO `wp_actionscheduler_logs` VALUES (1);\nUNLOCK TABLES;\nDROP TABLE IF EXISTS `wp_posts`;\nINSERT INTO `wp_posts` VALUES (1);\nUNLOCK TABLES;\nDROP TABLE IF EXISTS `wp_users`;\nINSERT INTO `wp_users` VALUES (1);\nUNLOCK TABLES;\nSET SQL_MODE=@OLD_SQL_MODE;\n",
+    )?;
+
+    let skipped = filter_tables(
+        &dump,
+        &filtered,
+        &PathFilter::new(&["wp_*"], &["wp_users", "*_logs"])?,
+    )?;
+
+    assert_eq!(skipped, vec!["wp_actionscheduler_logs", "wp_users"]);
+    assert_eq!(
+        fs::read_to_string(&filtered)?,
+        "SET NAMES utf8mb4;\nDROP TABLE IF EXISTS `wp_posts`;\nINSERT INTO `wp_posts` VALUES (1);\nUNLOCK TABLES;\nSET SQL_MODE=@OLD_SQL_MODE;\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_sanitise_profile() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://sanitise.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let mut project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    project.config.sanitise = Some("strict".to_string());
+    project.config.sanitise_profiles.insert(
+        "strict".to_string(),
+        SanitiseProfile {
+            extends: vec!["wordpress".to_string()],
+            truncate: vec!["options".to_string()],
+            ..Default::default()
+        },
+    );
+
+    let repo = project.open_repo("TEST")?;
+    let snapshot = repo.snapshot(true)?;
+
+    assert_eq!(snapshot.get_sanitise_profile(), Some("strict".to_string()));
+
+    project.restore_from_snapshot_with(
+        &repo,
+        &snapshot,
+        &SeedOptions {
+            scope: Scope::database_only(),
+            ..Default::default()
+        },
+    )?;
+
+    let imported = fs::read_to_string(project_ctx.project_path.path().join("imported.sql"))?;
+
+    assert!(
+        !imported.contains("INSERT INTO `wp_options`"),
+        "Truncated table should have no rows, got:\n{}",
+        imported
+    );
+
+    let dir = tempfile::tempdir()?;
+    let dump = dir.path().join("dump.sql");
+    let sanitised = dir.path().join("sanitised.sql");
+
+    fs::write(
+        &dump,
+        "DROP TABLE IF EXISTS `wp_users`;\nCREATE TABLE `wp_users` (\n  `ID` bigint,\n  `user_login` varchar(60),\n  `user_pass` varchar(255),\n  `user_email` varchar(100),\n  `display_name` varchar(250),\n  PRIMARY KEY (`ID`)\n);\nINSERT INTO `wp_users` VALUES (1,'real@person.com','$P$hash','real@person.com','Real, Person'),(2,'Real Login','$P$hash','real@person.com',NULL);\nUNLOCK TABLES;\nDROP TABLE IF EXISTS `wp_2_options`;\nCREATE TABLE `wp_2_options` (\n  `option_id` bigint,\n  `option_name` varchar(191),\n  `option_value` longtext\n);\nINSERT INTO `wp_2_options` VALUES (1,'mailserver_pass','secret'),(2,'blogname','It''s mine');\nUNLOCK TABLES;\nDROP TABLE IF EXISTS `wp_woocommerce_sessions`;\nCREATE TABLE `wp_woocommerce_sessions` (\n  `session_id` bigint\n);\nINSERT INTO `wp_woocommerce_sessions` VALUES (1);\nUNLOCK TABLES;\nDROP TABLE IF EXISTS `wp_foo_users`;\nCREATE TABLE `wp_foo_users` (\n  `id` bigint,\n  `score` int\n);\nINSERT INTO `wp_foo_users` VALUES (1,10);\nUNLOCK TABLES;\n",
+    )?;
+
+    Sanitiser::new(
+        SanitiseProfile::resolve("woocommerce", &Default::default())?,
+        "wp_",
+        "salt",
+    )
+    .rewrite_file(&dump, &sanitised)?;
+
+    let output = fs::read_to_string(&sanitised)?;
+    let users = output
+        .lines()
+        .find(|l| l.starts_with("INSERT INTO `wp_users`"))
+        .unwrap();
+
+    assert!(!output.contains("real@person.com"));
+    assert!(!output.contains("Real, Person"));
+    assert!(!output.contains("secret"));
+    assert!(!output.contains("INSERT INTO `wp_woocommerce_sessions`"));
+    assert!(output.contains("(1,'mailserver_pass',''),(2,'blogname','It''s mine')"));
+    // Plugin tables which only end like a core table are left alone
+    assert!(output.contains("INSERT INTO `wp_foo_users` VALUES (1,10);"));
+    assert!(users.contains("'5f4dcc3b5aa765d61d8327deb882cf99'"));
+    assert!(
+        users.ends_with(",NULL);"),
+        "NULLs should be kept: {}",
+        users
+    );
+
+    // The same email always gets the same fake
+    let fake = Fake::Email.render(b"'real@person.com'", "salt");
+    assert_eq!(users.matches(std::str::from_utf8(&fake)?).count(), 2);
+
+    // Without the salt, fakes can't be matched against guessed values
+    assert_ne!(fake, Fake::Email.render(b"'real@person.com'", "other"));
+
+    // Logins have a unique index, so each gets its own fake
+    assert!(!users.contains("Real Login"));
+    assert_ne!(
+        Fake::Username.render(b"'real@person.com'", "salt"),
+        Fake::Username.render(b"'Real Login'", "salt")
+    );
+
+    let mut custom = std::collections::BTreeMap::new();
+    custom.insert(
+        "loop".to_string(),
+        SanitiseProfile {
+            extends: vec!["loop".to_string()],
+            ..Default::default()
+        },
+    );
+
+    assert!(SanitiseProfile::resolve("loop", &custom).is_err());
+    assert!(SanitiseProfile::resolve("missing", &custom).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_extra_placeholders() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://extra-placeholders.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let mut project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    project.determine_home_url()?;
+
+    project
+        .config
+        .placeholders
+        .0
+        .insert("option".to_string(), "widget".to_string());
+
+    // Contained in the home URL, which is longer, so should never be tokenised on its own
+    project
+        .config
+        .placeholders
+        .0
+        .insert("host".to_string(), "extra-placeholders.test".to_string());
+
+    let dump = project_ctx.project_path.path().join("database.sql");
+
+    project.dump_database(&dump)?;
+
+    let contents = fs::read_to_string(&dump)?;
+
+    assert!(
+        contents.contains("(1,'home','__SPROUT__HOME__'),(2,'__SPROUT__OPTION__'")
+            && !contents.contains("__SPROUT__HOST__"),
+        "Placeholders should be tokenised, longest first. Saw: {}",
+        contents
+    );
+
+    let repo = project.open_repo("TEST")?;
+    let snapshot = repo.snapshot(true)?;
+
+    assert_eq!(
+        snapshot.get_placeholders().0.get("option"),
+        Some(&"widget".to_string())
+    );
+
+    let seed = |project: &Project| -> anyhow::Result<String> {
+        project.restore_from_snapshot_with(
+            &repo,
+            &snapshot,
+            &SeedOptions {
+                scope: Scope::database_only(),
+                ..Default::default()
+            },
+        )?;
+
+        Ok(fs::read_to_string(
+            project_ctx.project_path.path().join("imported.sql"),
+        )?)
+    };
+
+    project
+        .config
+        .placeholders
+        .0
+        .insert("option".to_string(), "sidebar".to_string());
+
+    let imported = seed(&project)?;
+
+    assert!(
+        imported.contains("(2,'sidebar'"),
+        "Placeholder should be replaced with this environment's value. Saw: {}",
+        imported
+    );
+
+    project.config.placeholders = Placeholders::default();
+
+    let imported = seed(&project)?;
+
+    assert!(
+        imported.contains("(2,'widget'") && !imported.contains("__SPROUT__"),
+        "Unconfigured placeholders should fall back to the snapshot's value. Saw: {}",
+        imported
+    );
+
+    project
+        .config
+        .placeholders
+        .0
+        .insert("home".to_string(), "x".to_string());
+    assert!(project.dump_database(&dump).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_multisite_domains() -> TestResult {
+    let ctx = TestContext::new()?;
+    let site = |id: u64, domain: &str, path: &str| Site {
+        id,
+        domain: domain.to_string(),
+        path: path.to_string(),
+    };
+
+    let project_ctx = TestProjectContext::with_sites(
+        "https://network.test",
+        vec![
+            site(1, "network.test", "/"),
+            site(2, "shop.network.test", "/"),
+            site(3, "mapped.com", "/"),
+            site(4, "network.test", "/blog/"),
+        ],
+    )?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let mut project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    project.determine_home_url()?;
+
+    let repo = project.open_repo("TEST")?;
+    let snapshot = repo.snapshot(true)?;
+
+    assert_eq!(snapshot.get_sites().0.len(), 4);
+
+    project.home_url = "https://local.test".to_string();
+    project
+        .config
+        .multisite
+        .domains
+        .insert("mapped.com".to_string(), "mapped.local".to_string());
+
+    project.restore_from_snapshot_with(
+        &repo,
+        &snapshot,
+        &SeedOptions {
+            scope: Scope::database_only(),
+            ..Default::default()
+        },
+    )?;
+
+    let imported = fs::read_to_string(project_ctx.project_path.path().join("imported.sql"))?;
+
+    for expected in [
+        "INSERT INTO `wp_options` VALUES (1,'home','https://local.test')",
+        "INSERT INTO `wp_blogs` VALUES (1,1,'local.test','/');",
+        "INSERT INTO `wp_blogs` VALUES (2,1,'shop.local.test','/');",
+        "INSERT INTO `wp_blogs` VALUES (3,1,'mapped.local','/');",
+        "INSERT INTO `wp_blogs` VALUES (4,1,'local.test','/blog/');",
+        "INSERT INTO `wp_2_options` VALUES (1,'home','https://shop.local.test/');",
+        "INSERT INTO `wp_4_options` VALUES (1,'home','https://local.test/blog/');",
+    ] {
+        assert!(
+            imported.contains(expected),
+            "Expected {} in:\n{}",
+            expected,
+            imported
+        );
+    }
+
+    project.config.multisite.domain_template = "{subdomain}.test".to_string();
+
+    assert_eq!(
+        snapshot
+            .get_sites()
+            .mapping(&project.config.multisite, &project.home_url),
+        vec![
+            ("mapped.com".to_string(), "mapped.local".to_string()),
+            ("network.test".to_string(), "local.test".to_string()),
+            ("shop.network.test".to_string(), "shop.test".to_string()),
+        ]
+    );
+
+    // Domains are only tokenised as whole hosts in URLs, and exactly in the network tables
+    let sites = snapshot.get_sites();
+    let replace = SearchReplace::new(&sites.capture_pairs()).whole_hosts();
+
+    assert_eq!(
+        String::from_utf8_lossy(&replace.replace_value(
+            b"https://mapped.com/a https://cdn.mapped.com/b https://mapped.com.au me@mapped.com https://mapped.com."
+        )),
+        "https://__SPROUT__SITE_3__/a https://cdn.mapped.com/b https://mapped.com.au me@mapped.com https://__SPROUT__SITE_3__."
+    );
+
+    let mut captured = vec![];
+    sites.capture_domains_dump(
+        "INSERT INTO `wp_blogs` VALUES (3,1,'mapped.com','/'),(5,1,'cdn.mapped.com','/');\nINSERT INTO `wp_links` VALUES (1,'mapped.com');\n".as_bytes(),
+        &mut captured,
+        "wp_",
+    )?;
+
+    assert_eq!(
+        String::from_utf8(captured)?,
+        "INSERT INTO `wp_blogs` VALUES (3,1,'__SPROUT__SITE_3__','/'),(5,1,'cdn.mapped.com','/');\nINSERT INTO `wp_links` VALUES (1,'mapped.com');\n"
+    );
+
+    assert_eq!(
+        network_uploads_dir(Path::new("wp-content/uploads/sites/3")),
+        Path::new("wp-content/uploads")
+    );
+    assert_eq!(
+        network_uploads_dir(Path::new("wp-content/uploads")),
+        Path::new("wp-content/uploads")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_table_prefix_rewrite() -> TestResult {
+    let ctx = TestContext::new()?;
+    let production_ctx = TestProjectContext::with_table_prefix("https://prefix.test", "wpx7_")?;
+    let local_ctx = TestProjectContext::new("https://prefix.test")?;
+
+    ctx.setup_single_repo()?;
+    production_ctx.apply_fixture("01_upload_diff_a")?;
+    local_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let mut production = Project::initialise(
+        &ctx.engine,
+        production_ctx.project_path.path().to_path_buf(),
+        production_ctx.facts.clone(),
+    )?;
+
+    let local = Project::initialise(
+        &ctx.engine,
+        local_ctx.project_path.path().to_path_buf(),
+        local_ctx.facts.clone(),
+    )?;
+
+    let repo = production.open_repo("TEST")?;
+    let snapshot = repo.snapshot(true)?;
+
+    assert_eq!(snapshot.get_table_prefix(), Some("wpx7_".to_string()));
+
+    local.restore_from_snapshot_with(
+        &repo,
+        &snapshot,
+        &SeedOptions {
+            scope: Scope::database_only(),
+            ..Default::default()
+        },
+    )?;
+
+    let imported = fs::read_to_string(local_ctx.project_path.path().join("imported.sql"))?;
+
+    assert!(
+        imported.contains("INSERT INTO `wp_options` VALUES (1,'home','https://prefix.test')")
+            && !imported.contains("wpx7_"),
+        "Tables should be renamed to the local prefix, got:\n{}",
+        imported
+    );
+
+    // Dry runs select tables by the name they'll be imported under too
+    production.config.database_layout = DatabaseLayout::Tables;
+
+    let per_table_repo = production.open_repo("TEST")?;
+    let per_table = per_table_repo.snapshot(true)?;
+
+    let planned_size = |include: &str| -> anyhow::Result<u64> {
+        let report = local.plan_restore_from_snapshot(
+            &per_table_repo,
+            &per_table,
+            &SeedOptions {
+                scope: Scope::database_only(),
+                tables: TableSelection {
+                    include: vec![include.to_string()],
+                    exclude: vec![],
+                }
+                .to_filter()?,
+                ..Default::default()
+            },
+        )?;
+
+        Ok(report.database_size.unwrap_or(0))
+    };
+
+    assert!(
+        planned_size("wp_options")? > planned_size("wpx7_options")?,
+        "Dry runs should match tables after renaming them to the local prefix"
+    );
+
+    let dir = tempfile::tempdir()?;
+    let dump = dir.path().join("dump.sql");
+    let renamed = dir.path().join("renamed.sql");
+
+    fs::write(
+        &dump,
+        "DROP TABLE IF EXISTS `wpx7_options`;\nCREATE TABLE `wpx7_options` (\n  `option_id` bigint\n);\nINSERT INTO `wpx7_options` VALUES (1,'wpx7_user_roles','a:0:{}'),(2,'wpx7_other','wpx7_');\nUNLOCK TABLES;\nDROP TABLE IF EXISTS `wpx7_2_options`;\nINSERT INTO `wpx7_2_options` VALUES\n(1,'wpx7_2_user_roles','a:0:{}'),\n(2,'blogname','Two');\nUNLOCK TABLES;\nDROP TABLE IF EXISTS `wpx7_usermeta`;\nINSERT INTO `wpx7_usermeta` VALUES (1,1,'wpx7_capabilities','a:0:{}'),(2,1,'wpx7_user_level','10'),(3,1,'nickname','wpx7_');\nUNLOCK TABLES;\nDROP TABLE IF EXISTS `legacy_table`;\nINSERT INTO `legacy_table` VALUES (1,'wpx7_user_roles');\nUNLOCK TABLES;\n",
+    )?;
+
+    rewrite_prefix(&dump, &renamed, "wpx7_", "wp_")?;
+
+    assert_eq!(
+        fs::read_to_string(&renamed)?,
+        "DROP TABLE IF EXISTS `wp_options`;\nCREATE TABLE `wp_options` (\n  `option_id` bigint\n);\nINSERT INTO `wp_options` VALUES (1,'wp_user_roles','a:0:{}'),(2,'wpx7_other','wpx7_');\nUNLOCK TABLES;\nDROP TABLE IF EXISTS `wp_2_options`;\nINSERT INTO `wp_2_options` VALUES\n(1,'wp_2_user_roles','a:0:{}'),\n(2,'blogname','Two');\nUNLOCK TABLES;\nDROP TABLE IF EXISTS `wp_usermeta`;\nINSERT INTO `wp_usermeta` VALUES (1,1,'wp_capabilities','a:0:{}'),(2,1,'wp_user_level','10'),(3,1,'nickname','wpx7_');\nUNLOCK TABLES;\nDROP TABLE IF EXISTS `legacy_table`;\nINSERT INTO `legacy_table` VALUES (1,'wpx7_user_roles');\nUNLOCK TABLES;\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_lifecycle_hooks() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://hooks.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let mut project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    let config_path = project_ctx.project_path.path().join("sprout.yaml");
+
+    fs::write(
+        &config_path,
+        format!(
+            "{}hooks:\n  pre_snap: echo \"$SPROUT_HOOK $SPROUT_HOOK_STATUS $SPROUT_BRANCH $SPROUT_HOME_URL $SPROUT_SNAPSHOT_ID\" > hook.txt\n  post_snap: echo \"$SPROUT_HOOK $SPROUT_HOOK_STATUS\" > post_snap.txt\n  post_seed:\n    - echo first >> seed.txt\n    - exit 3\n    - echo never >> seed.txt\n",
+            fs::read_to_string(&config_path)?
+        ),
+    )?;
+
+    project.config = Project::load_project_config(&config_path)?;
+    project.determine_home_url()?;
+
+    let repo = project.open_repo("TEST")?;
+    let snapshot = repo.snapshot(true)?;
+
+    project.run_hook(Hook::PreSnap, Some(snapshot.id))?;
+
+    assert_eq!(
+        fs::read_to_string(project_ctx.project_path.path().join("hook.txt"))?,
+        format!(
+            "pre_snap success main https://hooks.test {}\n",
+            snapshot.id.to_hex()
+        )
+    );
+
+    // post_snap runs after a failed snap too, so it can undo pre_snap
+    project.run_hook_after_failure(Hook::PostSnap, Some(snapshot.id))?;
+
+    assert_eq!(
+        fs::read_to_string(project_ctx.project_path.path().join("post_snap.txt"))?,
+        "post_snap failure\n"
+    );
+
+    let err = project
+        .run_hook(Hook::PostSeed, Some(snapshot.id))
+        .unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        "The post_seed hook `exit 3` failed with exit code 3"
+    );
+    assert_eq!(
+        fs::read_to_string(project_ctx.project_path.path().join("seed.txt"))?,
+        "first\n",
+        "Hooks should stop at the first failing command"
+    );
+
+    // Hooks with nothing configured are a no-op
+    project.run_hook(Hook::PostUnstash, None)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_seed_rollback() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://rollback.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    let uploads_dir = Path::new(&project_ctx.facts.get_uploads_dir()?).to_path_buf();
+
+    let stash = Stash::new(&ctx.engine, ctx.engine.get_stash_path())?;
+    let stashed = stash.stash(&project)?;
+
+    // A seed which gets halfway through the uploads, then fails
+    let err = stash
+        .with_rollback(&project, stashed.id, || -> anyhow::Result<()> {
+            project_ctx.wipe_uploads()?;
+            project_ctx.apply_fixture("02_upload_diff_b")?;
+
+            Err(anyhow::anyhow!("Database import failed"))
+        })
+        .unwrap_err();
+
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Database import failed. Rolled back to stash {}",
+            stashed.id
+        )
+    );
+    assert_eq!(
+        err.root_cause().to_string(),
+        "Database import failed",
+        "The original error should be kept in the chain"
+    );
+
+    assert_eq!(fs::read_to_string(uploads_dir.join("1.txt"))?, "One A");
+    assert_eq!(fs::read_to_string(uploads_dir.join("2.txt"))?, "Two A");
+    assert!(
+        !uploads_dir.join("4.txt").exists(),
+        "Files from the failed seed should be removed by the rollback"
+    );
+
+    let value = stash.with_rollback(&project, stashed.id, || Ok(42))?;
+    assert_eq!(value, 42);
+
+    Ok(())
+}
+
+#[test]
+fn test_shared_index_and_cache() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://invalid-project.test")?;
+    let cache_dir = TempDir::new()?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let mut config = ctx.engine.get_config()?;
+    config.cache_dir = Some(cache_dir.path().join("sprout"));
+    ctx.engine.write_config(&config)?;
+
+    let project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    let repo = project.open_repo("TEST")?;
+    let first = repo.snapshot(true)?;
+
+    let index = repo.indexed()?;
+    assert!(
+        Arc::ptr_eq(&index, &repo.indexed()?),
+        "Lookups should share one index"
+    );
+
+    project_ctx.apply_fixture("02_upload_diff_b")?;
+    let second = repo.snapshot(true)?;
+
+    assert!(
+        !Arc::ptr_eq(&index, &repo.indexed()?),
+        "The index should be rebuilt after a snapshot"
+    );
+    assert_eq!(repo.get_snapshot(first.id)?.id, first.id);
+
+    project_ctx.wipe_uploads()?;
+    project.restore_from_snapshot(&repo, &second)?;
+
+    assert!(
+        Path::new(&project_ctx.facts.get_uploads_dir()?)
+            .join("4.txt")
+            .exists(),
+        "4.txt not restored from the second snapshot"
+    );
+
+    assert!(
+        fs::read_dir(cache_dir.path().join("sprout"))?.count() > 0,
+        "The repo should be cached in the configured directory"
+    );
+
+    config.no_cache = true;
+    ctx.engine.write_config(&config)?;
+
+    assert_eq!(ctx.engine.get_cache_path()?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_tags() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://invalid-project.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    let repo = project.open_repo("TEST")?;
+
+    let first = repo.snapshot_annotated(
+        true,
+        &Scope::default(),
+        &Annotation {
+            message: Some("After the content freeze".to_string()),
+            tag: Some("release-2.3".to_string()),
+        },
+    )?;
+
+    assert_eq!(first.get_tags(), vec!["release-2.3".to_string()]);
+    assert_eq!(
+        first.get_message(),
+        Some("After the content freeze".to_string())
+    );
+    assert_eq!(repo.resolve_snapshot("release-2.3")?.id, first.id);
+    assert_eq!(repo.resolve_snapshot(&first.id.to_hex())?.id, first.id);
+
+    project_ctx.apply_fixture("02_upload_diff_b")?;
+    let second = repo.snapshot(true)?;
+
+    assert!(
+        repo.check_tag("release-2.3", &project.config.branch, false)
+            .is_err(),
+        "Tags should be unique per branch"
+    );
+    assert!(
+        repo.tag(&second, "release-2.3", false).is_err(),
+        "Moving a tag should need force"
+    );
+
+    let moved = repo.tag(&second, "release-2.3", true)?;
+
+    assert_ne!(moved.id, second.id, "Retagging saves a new snapshot file");
+    assert!(moved.has_id(second.id));
+    assert_eq!(repo.resolve_snapshot("release-2.3")?.id, moved.id);
+
+    // The first snapshot lost its tag, but can still be found by the ID it was saved with
+    let untagged = repo.get_snapshot(first.id)?;
+
+    assert!(untagged.get_tags().is_empty());
+    assert_eq!(
+        untagged.get_message(),
+        Some("After the content freeze".to_string())
+    );
+    assert_eq!(repo.get_all_snapshots_for_project(&project)?.0.len(), 2);
+
+    assert!(repo.resolve_snapshot("no-such-tag").is_err());
+    assert!(validate_tag("release_2.3-rc1").is_ok());
+    assert!(validate_tag("-release").is_err());
+    assert!(validate_tag("a,b").is_err());
+    assert!(validate_tag(&first.id.to_hex()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_pinned_snapshots() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://pinned-project.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+
+    let repo = project.open_repo("TEST")?;
+
+    let golden = repo.snapshot(true)?;
+    let latest = repo.snapshot(true)?;
+
+    assert!(repo.pin(&golden)?);
+    assert!(!repo.pin(&golden)?, "Pinning twice should be a no-op");
+    assert!(repo.get_pinned()?.contains(&golden.id));
+
+    // Retagging gives the snapshot a new ID, but it stays pinned
+    let golden = repo.tag(&golden, "qa-fixture", false)?;
+    let pinned = repo.get_pinned()?;
+
+    assert!(golden.is_any_of(&pinned));
+    assert!(
+        refuse_pinned(std::slice::from_ref(&golden), &pinned, false).is_err(),
+        "Removing a pinned snapshot should need an override"
+    );
+    assert!(refuse_pinned(std::slice::from_ref(&golden), &pinned, true).is_ok());
+
+    let (keep, forget) = repo.plan_forget(
+        &KeepPolicy {
+            last: Some(1),
+            ..KeepPolicy::default()
+        },
+        None,
+        &pinned,
+    )?;
+
+    assert_eq!(
+        keep.iter().map(|snap| snap.id).collect::<Vec<_>>(),
+        vec![latest.id, golden.id],
+        "Pinned snapshots should be kept by forget"
+    );
+    assert!(forget.is_empty());
+
+    assert_eq!(
+        repo.get_all_snapshots_for_project(&project)?.0.len(),
+        2,
+        "Pin records shouldn't be listed as snapshots"
+    );
+
+    // Forgetting a pinned snapshot (with the override) removes its pin too
+    repo.forget(std::slice::from_ref(&golden))?;
+
+    assert!(repo.get_pinned()?.is_empty());
+
+    assert!(repo.unpin(&latest).is_ok_and(|unpinned| !unpinned));
+
+    // Stashes can be pinned too
+    let stash = Stash::new(&ctx.engine, ctx.engine.get_stash_path())?;
+    let stashed = stash.stash(&project)?;
+
+    assert!(stash.open_stash(&project)?.pin(&stashed)?);
+    assert!(stash.get_pinned()?.contains(&stashed.id));
+    assert!(
+        stash.drop(stashed.id, false).is_err(),
+        "Pinned stashes should only be dropped with --force"
+    );
+
+    stash.drop(stashed.id, true)?;
+
+    assert!(stash.get_pinned()?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_branch_management() -> TestResult {
+    let ctx = TestContext::new()?;
+    let project_ctx = TestProjectContext::new("https://branch-project.test")?;
+    let other_ctx = TestProjectContext::new("https://other-project.test")?;
+
+    ctx.setup_single_repo()?;
+    project_ctx.apply_fixture("01_upload_diff_a")?;
+    other_ctx.apply_fixture("01_upload_diff_a")?;
+
+    let project = Project::initialise(
+        &ctx.engine,
+        project_ctx.project_path.path().to_path_buf(),
+        project_ctx.facts.clone(),
+    )?;
+    let other = Project::initialise(
+        &ctx.engine,
+        other_ctx.project_path.path().to_path_buf(),
+        other_ctx.facts.clone(),
+    )?;
+
+    let main = project.config.branch.to_owned();
+    let repo = project.open_repo("TEST")?;
+
+    repo.snapshot(true)?;
+    let head = repo.snapshot(true)?;
+
+    // Another project in the same repo, on the same branch name
+    other.open_repo("TEST")?.snapshot(true)?;
+
+    let fork = repo.fork_branch(&head, "feature")?;
+
+    assert_eq!(fork.get_branch()?, "feature");
+    assert_eq!(
+        fork.snapshot.tree, head.snapshot.tree,
+        "Forking shouldn't upload anything"
+    );
+    assert!(
+        repo.fork_branch(&head, "feature").is_err(),
+        "Forking onto an existing branch should fail"
+    );
+    assert!(validate_branch("feature/new-homepage").is_ok());
+    assert!(validate_branch("a,b").is_err());
+
+    let branches = repo.get_branches()?;
+
+    assert_eq!(
+        branches
+            .iter()
+            .map(|branch| (branch.name.as_str(), branch.count))
+            .collect::<Vec<_>>(),
+        vec![("feature", 1), (main.as_str(), 2)],
+        "Branches should be scoped to the project, newest first"
+    );
+    assert_eq!(branches[1].head.id, head.id);
+    assert_eq!(
+        branches[0].data_added, 0,
+        "A fork shouldn't count the original's data again"
+    );
+    assert_eq!(
+        fork.get_stats()?.unmodified,
+        head.get_stats()?.new + head.get_stats()?.changed + head.get_stats()?.unmodified
+    );
+    assert!(branches[0].host.is_some(), "The machine should be recorded");
+
+    project_ctx.wipe_uploads()?;
+    project.restore_from_snapshot(&repo, &fork)?;
+
+    assert!(
+        Path::new(&project_ctx.facts.get_uploads_dir()?)
+            .join("1.txt")
+            .exists(),
+        "1.txt not restored from the fork"
+    );
+
+    let renamed = repo.rename_branch("feature", "feature-x")?;
+
+    assert_eq!(renamed.len(), 1);
+    assert!(renamed[0].has_id(fork.id));
+    assert!(repo.get_branch_snapshots("feature")?.is_empty());
+    assert!(repo.rename_branch("feature-x", &main).is_err());
+
+    repo.pin(&renamed[0])?;
+
+    assert!(
+        repo.delete_branch("feature-x", false).is_err(),
+        "Deleting a branch with a pinned snapshot should need an override"
+    );
+
+    // Another checkout of this project, seeded from the fork before it was renamed
+    let checkout = tempfile::tempdir()?;
+    let mut checkout_config = project.config.clone();
+    checkout_config.snapshot = Some(fork.id);
+    fs::write(
+        checkout.path().join("sprout.yaml"),
+        serde_yaml::to_string(&checkout_config)?,
+    )?;
+    ctx.engine.register_project(checkout.path())?;
+
+    assert!(
+        repo.delete_branch("feature-x", true).is_err(),
+        "Deleting a branch with a snapshot referenced by a sprout.yaml should fail"
+    );
+
+    fs::remove_file(checkout.path().join("sprout.yaml"))?;
+
+    let forgotten = repo.delete_branch("feature-x", true)?;
+
+    assert_eq!(forgotten.len(), 1);
+    assert_eq!(repo.get_branches()?.len(), 1);
+    assert_eq!(
+        other.open_repo("TEST")?.get_branch_snapshots(&main)?.len(),
+        1,
+        "Other projects' branches should be untouched"
+    );
+
+    Ok(())
+}